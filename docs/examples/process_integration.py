@@ -0,0 +1,95 @@
+#!/usr/bin/env python3
+"""Minimal homectl process integration.
+
+Provides a lamp that can be controlled from homectl, and a temperature sensor
+that reports a new value every 10 seconds. See docs/process-integrations.md.
+
+[integrations.python]
+plugin = "process"
+command = "python3"
+args = ["docs/examples/process_integration.py"]
+"""
+
+import json
+import random
+import sys
+import threading
+
+write_lock = threading.Lock()
+stop = threading.Event()
+
+
+def send(message):
+    with write_lock:
+        sys.stdout.write(json.dumps({"jsonrpc": "2.0", **message}) + "\n")
+        sys.stdout.flush()
+
+
+def log(message):
+    # stderr ends up in homectl's log
+    print(message, file=sys.stderr, flush=True)
+
+
+def send_device(device_id, name, data):
+    send(
+        {
+            "method": "external_state_update",
+            "params": {"device": {"id": device_id, "name": name, "data": data}},
+        }
+    )
+
+
+def lamp_data(power, brightness):
+    return {
+        "Controllable": {
+            "state": {"power": power, "brightness": brightness},
+            "capabilities": {},
+            "managed": "Full",
+        }
+    }
+
+
+def poll_temperature():
+    while not stop.wait(10):
+        value = round(random.uniform(20, 23), 1)
+        send_device("temperature", "Temperature", {"Sensor": {"value": value}})
+
+
+def handle(method, params):
+    if method == "register":
+        log(f"registering {params['integration_id']}")
+        send_device("lamp", "Python lamp", lamp_data(False, 1.0))
+    elif method == "start":
+        threading.Thread(target=poll_temperature, daemon=True).start()
+    elif method == "set_integration_device_state":
+        device = params["device"]
+        state = device["data"]["Controllable"]["state"]
+        log(f"setting {device['name']} to {state}")
+        # A real integration would talk to the device here, and report back
+        # the state the device ended up in
+        send_device(device["id"], device["name"], lamp_data(state["power"], state["brightness"]))
+    elif method == "run_integration_action":
+        log(f"running action {params['payload']}")
+    else:
+        raise KeyError(method)
+
+
+for line in sys.stdin:
+    message = json.loads(line)
+    method = message["method"]
+
+    if "id" not in message:
+        # Notifications don't get a response
+        if method == "stop":
+            break
+        continue
+
+    try:
+        handle(method, message.get("params"))
+        send({"id": message["id"], "result": None})
+    except KeyError:
+        send({"id": message["id"], "error": {"code": -32601, "message": f"Unknown method {method}"}})
+    except Exception as e:
+        send({"id": message["id"], "error": {"code": -32000, "message": str(e)}})
+
+stop.set()
@@ -0,0 +1,4 @@
+create table routine_state (
+  routine_id text primary key not null,
+  enabled boolean not null
+);
@@ -0,0 +1,6 @@
+create table api_tokens (
+  name text primary key not null,
+  token_hash text not null unique,
+  scope text not null,
+  created_at timestamptz not null default now()
+);
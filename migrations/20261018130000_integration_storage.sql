@@ -0,0 +1,6 @@
+create table integration_storage (
+  integration_id text not null,
+  key text not null,
+  value jsonb not null,
+  primary key (integration_id, key)
+);
@@ -1,16 +1,33 @@
 use std::sync::Arc;
 
 use crate::core::state::AppState;
-use crate::types::{action::Action, event::Event};
+use crate::types::{
+    action::Action,
+    auth::ApiScope,
+    event::{Event, EventSource},
+};
 use tokio::sync::RwLock;
-use warp::Filter;
+use warp::{http::StatusCode, Filter};
 
-use super::with_state;
+use super::{
+    auth::{forbidden, require_scope, with_scope},
+    validation::{action_scope, validate_action},
+    with_state,
+};
+
+#[derive(serde::Serialize)]
+pub struct ActionErrorResponse {
+    error: Option<String>,
+}
 
 pub fn actions(
     app_state: &Arc<RwLock<AppState>>,
 ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
-    warp::path("actions").and(post_action(app_state).or(warp::get().map(|| warp::reply::json(&()))))
+    warp::path("actions").and(
+        post_action(app_state).or(warp::get()
+            .and(require_scope(app_state, ApiScope::ReadOnly))
+            .map(|| warp::reply::json(&()))),
+    )
 }
 
 fn post_action(
@@ -18,18 +35,86 @@ fn post_action(
 ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     warp::path("trigger")
         .and(warp::post())
+        .and(with_scope(app_state, ApiScope::Actions))
         .and(warp::body::json())
         .and(with_state(app_state))
         .and_then(post_action_impl)
 }
 
+/// Actions are subject to the same scope and validation rules as when sent
+/// over the WebSocket.
 async fn post_action_impl(
-    action: Action,
+    scope: ApiScope,
+    mut action: Action,
     app_state: Arc<RwLock<AppState>>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
+    let required = action_scope(&action);
+    if scope < required {
+        return Err(forbidden(required));
+    }
+
     let app_state = app_state.read().await;
-    let sender = app_state.event_tx.clone();
+
+    if let Err(error) = validate_action(&app_state, &mut action) {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&ActionErrorResponse { error: Some(error) }),
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    let sender = app_state.event_tx.with_source(EventSource::Api);
     sender.send(Event::Action(action));
 
-    Ok(warp::reply::json(&()))
+    Ok(warp::reply::with_status(
+        warp::reply::json(&()),
+        StatusCode::OK,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::auth::handle_auth_rejection;
+    use crate::core::config::{ApiConfig, ApiTokenConfig};
+    use crate::types::{
+        integration::{CustomActionDescriptor, IntegrationActionPayload, IntegrationId},
+        rule::{RoutineDescriptor, RoutineId},
+    };
+
+    #[tokio::test]
+    async fn test_rejected_actions() {
+        let (app_state, _event_rx) =
+            AppState::mk_test_state(Default::default(), Default::default(), Default::default());
+        app_state.write().await.auth.set_config(&ApiConfig {
+            tokens: Some(vec![ApiTokenConfig {
+                name: "tablet".to_string(),
+                token: "secret".to_string(),
+                scope: ApiScope::Actions,
+            }]),
+            ..Default::default()
+        });
+        let filter = &actions(&app_state).recover(handle_auth_rejection);
+
+        let trigger = |action: Action| async move {
+            warp::test::request()
+                .method("POST")
+                .path("/actions/trigger")
+                .header("authorization", "Bearer secret")
+                .json(&action)
+                .reply(filter)
+                .await
+                .status()
+        };
+
+        let custom = Action::Custom(CustomActionDescriptor {
+            integration_id: IntegrationId::from("test".to_string()),
+            payload: IntegrationActionPayload::from("{}".to_string()),
+        });
+        assert_eq!(trigger(custom).await, StatusCode::FORBIDDEN);
+
+        let unknown_routine = Action::EnableRoutine(RoutineDescriptor {
+            routine_id: RoutineId("unknown".to_string()),
+        });
+        assert_eq!(trigger(unknown_routine).await, StatusCode::BAD_REQUEST);
+    }
 }
@@ -0,0 +1,100 @@
+use std::sync::Arc;
+
+use crate::core::state::AppState;
+use crate::types::auth::ApiScope;
+use serde::Deserialize;
+use tokio::sync::RwLock;
+use warp::{http::StatusCode, reject::Reject, Filter, Rejection};
+
+use super::with_state;
+
+#[derive(Debug)]
+struct Unauthorized;
+impl Reject for Unauthorized {}
+
+#[derive(Debug)]
+struct Forbidden {
+    required: ApiScope,
+}
+impl Reject for Forbidden {}
+
+#[derive(Deserialize)]
+struct TokenQuery {
+    token: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct AuthErrorResponse {
+    error: String,
+}
+
+/// Authenticates the request and extracts the granted scope, rejecting the
+/// request unless it's at least `scope`. The token is read from an
+/// `Authorization: Bearer <token>` header, or from a `token` query parameter
+/// for clients that can't set headers, such as browser WebSockets.
+pub fn with_scope(
+    app_state: &Arc<RwLock<AppState>>,
+    scope: ApiScope,
+) -> impl Filter<Extract = (ApiScope,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and(warp::query::<TokenQuery>())
+        .and(with_state(app_state))
+        .and_then(
+            move |header: Option<String>, query: TokenQuery, app_state: Arc<RwLock<AppState>>| async move {
+                let token = header
+                    .as_deref()
+                    .and_then(|header| header.strip_prefix("Bearer "))
+                    .map(str::to_string)
+                    .or(query.token);
+
+                let granted = app_state
+                    .read()
+                    .await
+                    .auth
+                    .authenticate(token.as_deref())
+                    .ok_or_else(|| warp::reject::custom(Unauthorized))?;
+
+                if granted < scope {
+                    return Err(warp::reject::custom(Forbidden { required: scope }));
+                }
+
+                Ok(granted)
+            },
+        )
+}
+
+/// Rejects a request that turned out to need a wider scope than was granted,
+/// see [with_scope].
+pub fn forbidden(required: ApiScope) -> Rejection {
+    warp::reject::custom(Forbidden { required })
+}
+
+/// Like [with_scope], but doesn't extract the granted scope.
+pub fn require_scope(
+    app_state: &Arc<RwLock<AppState>>,
+    scope: ApiScope,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    with_scope(app_state, scope).map(|_| ()).untuple_one()
+}
+
+/// Turns authentication rejections into 401 and 403 responses.
+pub async fn handle_auth_rejection(err: Rejection) -> Result<impl warp::Reply, Rejection> {
+    let (error, status) = if err.find::<Unauthorized>().is_some() {
+        (
+            "Missing or invalid API token".to_string(),
+            StatusCode::UNAUTHORIZED,
+        )
+    } else if let Some(Forbidden { required }) = err.find::<Forbidden>() {
+        (
+            format!("API token lacks the required scope {required}"),
+            StatusCode::FORBIDDEN,
+        )
+    } else {
+        return Err(err);
+    };
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&AuthErrorResponse { error }),
+        status,
+    ))
+}
@@ -0,0 +1,50 @@
+use std::{convert::Infallible, sync::Arc};
+
+use crate::core::{reload::reload_config, state::AppState};
+use crate::types::auth::ApiScope;
+use tokio::sync::RwLock;
+use warp::{http::StatusCode, Filter};
+
+use super::{auth::require_scope, with_state};
+
+#[derive(serde::Serialize)]
+pub struct ReloadConfigResponse {
+    error: Option<String>,
+}
+
+pub fn config(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path("config").and(reload(app_state))
+}
+
+fn reload(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("reload")
+        .and(warp::post())
+        .and(require_scope(app_state, ApiScope::Admin))
+        .and(with_state(app_state))
+        .and_then(reload_impl)
+}
+
+async fn reload_impl(app_state: Arc<RwLock<AppState>>) -> Result<impl warp::Reply, Infallible> {
+    let mut app_state = app_state.write().await;
+
+    match reload_config(&mut app_state).await {
+        Ok(()) => Ok(warp::reply::with_status(
+            warp::reply::json(&ReloadConfigResponse { error: None }),
+            StatusCode::OK,
+        )),
+        Err(e) => {
+            error!("Rejected config reload, keeping previous config:\n    {e:?}");
+
+            Ok(warp::reply::with_status(
+                warp::reply::json(&ReloadConfigResponse {
+                    error: Some(format!("{e:#}")),
+                }),
+                StatusCode::BAD_REQUEST,
+            ))
+        }
+    }
+}
@@ -1,26 +1,47 @@
 use std::{convert::Infallible, sync::Arc};
 
+use crate::core::history::downsample;
+use crate::db::actions::db_get_device_history;
 use crate::types::{
+    auth::ApiScope,
     color::ColorMode,
-    device::{Device, DeviceId},
+    device::{Device, DeviceHistoryEntry, DeviceId, DeviceKey},
+    event::{Event, EventSource},
+    integration::IntegrationId,
 };
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use tokio::sync::RwLock;
-use warp::Filter;
+use warp::{http::StatusCode, Filter};
 
 use crate::core::state::AppState;
 
-use super::with_state;
+use super::{auth::require_scope, validation::check_set_device_state, with_state};
 
 #[derive(serde::Serialize)]
 pub struct DevicesResponse {
     devices: Vec<Device>,
 }
 
+#[derive(serde::Serialize)]
+pub struct DeviceHistoryResponse {
+    history: Vec<DeviceHistoryEntry>,
+}
+
+#[derive(serde::Serialize)]
+pub struct DeviceErrorResponse {
+    error: Option<String>,
+}
+
 pub fn devices(
     app_state: &Arc<RwLock<AppState>>,
 ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
-    warp::path("devices").and(get_devices(app_state).or(put_device(app_state)))
+    warp::path("devices").and(
+        get_device_history(app_state)
+            .or(get_devices(app_state))
+            .or(put_device(app_state))
+            .or(delete_device(app_state)),
+    )
 }
 
 #[derive(Serialize, Deserialize)]
@@ -32,6 +53,7 @@ fn get_devices(
     app_state: &Arc<RwLock<AppState>>,
 ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     warp::get()
+        .and(require_scope(app_state, ApiScope::ReadOnly))
         .and(warp::query::<GetQuery>())
         .and(with_state(app_state))
         .and_then(get_devices_impl)
@@ -62,6 +84,7 @@ fn put_device(
 ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     warp::path!(DeviceId)
         .and(warp::put())
+        .and(require_scope(app_state, ApiScope::Actions))
         .and(warp::body::json())
         .and(with_state(app_state))
         .and_then(put_device_impl)
@@ -69,16 +92,26 @@ fn put_device(
 
 async fn put_device_impl(
     device_id: DeviceId,
-    device: Device,
+    mut device: Device,
     app_state: Arc<RwLock<AppState>>,
 ) -> Result<impl warp::Reply, Infallible> {
     // Make sure device_id matches with provided device
     if device_id != device.id {
-        return Ok(warp::reply::json(&DevicesResponse { devices: vec![] }));
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&DevicesResponse { devices: vec![] }),
+            StatusCode::OK,
+        ));
     }
 
     let mut app_state = app_state.write().await;
 
+    if let Err(error) = check_set_device_state(&app_state, &mut device) {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&DeviceErrorResponse { error: Some(error) }),
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+
     app_state.devices.set_state(&device, false, false);
 
     let devices = app_state.devices.get_state();
@@ -86,5 +119,145 @@ async fn put_device_impl(
         devices: devices.0.values().cloned().collect(),
     };
 
-    Ok(warp::reply::json(&response))
+    Ok(warp::reply::with_status(
+        warp::reply::json(&response),
+        StatusCode::OK,
+    ))
+}
+
+fn delete_device(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!(IntegrationId / DeviceId)
+        .and(warp::delete())
+        .and(require_scope(app_state, ApiScope::Admin))
+        .and(with_state(app_state))
+        .and_then(delete_device_impl)
+}
+
+/// Forgets a device. If its integration still reports the device, it will be
+/// discovered again.
+async fn delete_device_impl(
+    integration_id: IntegrationId,
+    device_id: DeviceId,
+    app_state: Arc<RwLock<AppState>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let app_state = app_state.read().await;
+    let device_key = DeviceKey::new(integration_id, device_id);
+
+    if app_state.devices.get_device(&device_key).is_none() {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&DeviceErrorResponse {
+                error: Some(format!("No device with key {device_key}")),
+            }),
+            StatusCode::NOT_FOUND,
+        ));
+    }
+
+    let sender = app_state.event_tx.with_source(EventSource::Api);
+    sender.send(Event::DeviceRemoved { device_key });
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&DeviceErrorResponse { error: None }),
+        StatusCode::ACCEPTED,
+    ))
+}
+
+#[derive(Serialize, Deserialize)]
+struct HistoryQuery {
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+
+    /// Downsample to one entry per this many seconds
+    interval: Option<u64>,
+}
+
+fn get_device_history(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!(IntegrationId / DeviceId / "history")
+        .and(warp::get())
+        .and(require_scope(app_state, ApiScope::ReadOnly))
+        .and(warp::query::<HistoryQuery>())
+        .and(with_state(app_state))
+        .and_then(get_device_history_impl)
+}
+
+async fn get_device_history_impl(
+    integration_id: IntegrationId,
+    device_id: DeviceId,
+    query: HistoryQuery,
+    app_state: Arc<RwLock<AppState>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let device_key = DeviceKey::new(integration_id, device_id);
+    let interval = query
+        .interval
+        .or(app_state.read().await.history.downsample_interval_seconds());
+    let history = db_get_device_history(&device_key, query.from, query.to).await;
+
+    match history {
+        Ok(history) => Ok(warp::reply::with_status(
+            warp::reply::json(&DeviceHistoryResponse {
+                history: downsample(history, interval.unwrap_or_default()),
+            }),
+            StatusCode::OK,
+        )),
+        Err(e) => {
+            error!("Failed to fetch device history for {device_key}: {e}");
+
+            Ok(warp::reply::with_status(
+                warp::reply::json(&DeviceHistoryResponse { history: vec![] }),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::device::{DeviceData, SensorDevice};
+
+    #[tokio::test]
+    async fn test_put_sensor_state() {
+        let (app_state, _event_rx) =
+            AppState::mk_test_state(Default::default(), Default::default(), Default::default());
+        let filter = devices(&app_state);
+
+        let mut sensor = Device::new(
+            IntegrationId::from("test".to_string()),
+            DeviceId::new("sensor"),
+            "Sensor".to_string(),
+            DeviceData::Sensor(SensorDevice::Boolean { value: false }),
+            None,
+        );
+        {
+            let mut app_state = app_state.write().await;
+            let scenes = app_state.scenes.clone();
+            app_state
+                .devices
+                .handle_external_state_update(&sensor, &scenes)
+                .await
+                .unwrap();
+        }
+
+        sensor.data = DeviceData::Sensor(SensorDevice::Boolean { value: true });
+        let response = warp::test::request()
+            .method("PUT")
+            .path("/devices/sensor")
+            .json(&sensor)
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let app_state = app_state.read().await;
+        let current = app_state
+            .devices
+            .get_device(&sensor.get_device_key())
+            .unwrap();
+        assert_eq!(
+            current.data,
+            DeviceData::Sensor(SensorDevice::Boolean { value: false })
+        );
+    }
 }
@@ -0,0 +1,103 @@
+use std::{convert::Infallible, sync::Arc};
+
+use crate::core::state::AppState;
+use crate::types::{
+    auth::ApiScope,
+    group::{FlattenedGroupConfig, GroupConfig, GroupId},
+};
+use tokio::sync::RwLock;
+use warp::{http::StatusCode, Filter};
+
+use super::{auth::require_scope, with_state};
+
+#[derive(serde::Serialize)]
+pub struct GroupResponse {
+    id: GroupId,
+
+    /// Group with any contained groups expanded into devices.
+    #[serde(flatten)]
+    group: FlattenedGroupConfig,
+
+    /// Group as defined in the config file.
+    config: Option<GroupConfig>,
+}
+
+#[derive(serde::Serialize)]
+pub struct GroupsResponse {
+    groups: Vec<GroupResponse>,
+}
+
+#[derive(serde::Serialize)]
+pub struct GroupErrorResponse {
+    error: String,
+}
+
+pub fn groups(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path("groups").and(get_groups(app_state).or(get_group(app_state)))
+}
+
+fn mk_group_response(app_state: &AppState, group_id: &GroupId) -> Option<GroupResponse> {
+    let group = app_state.groups.get_flattened_groups().0.get(group_id)?;
+
+    Some(GroupResponse {
+        id: group_id.clone(),
+        group: group.clone(),
+        config: app_state.groups.get_config().get(group_id).cloned(),
+    })
+}
+
+fn get_groups(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path::end()
+        .and(warp::get())
+        .and(require_scope(app_state, ApiScope::ReadOnly))
+        .and(with_state(app_state))
+        .and_then(get_groups_impl)
+}
+
+async fn get_groups_impl(app_state: Arc<RwLock<AppState>>) -> Result<impl warp::Reply, Infallible> {
+    let app_state = app_state.read().await;
+
+    let groups = app_state
+        .groups
+        .get_flattened_groups()
+        .0
+        .keys()
+        .filter_map(|group_id| mk_group_response(&app_state, group_id))
+        .collect();
+
+    Ok(warp::reply::json(&GroupsResponse { groups }))
+}
+
+fn get_group(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!(GroupId)
+        .and(warp::get())
+        .and(require_scope(app_state, ApiScope::ReadOnly))
+        .and(with_state(app_state))
+        .and_then(get_group_impl)
+}
+
+async fn get_group_impl(
+    group_id: GroupId,
+    app_state: Arc<RwLock<AppState>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let app_state = app_state.read().await;
+
+    match mk_group_response(&app_state, &group_id) {
+        Some(group) => Ok(warp::reply::with_status(
+            warp::reply::json(&group),
+            StatusCode::OK,
+        )),
+        None => Ok(warp::reply::with_status(
+            warp::reply::json(&GroupErrorResponse {
+                error: format!("No group with id {group_id}"),
+            }),
+            StatusCode::NOT_FOUND,
+        )),
+    }
+}
@@ -0,0 +1,170 @@
+use std::{convert::Infallible, sync::Arc};
+
+use crate::core::state::AppState;
+use crate::types::{
+    auth::ApiScope,
+    integration::{IntegrationId, IntegrationState, IntegrationStatus},
+};
+use tokio::sync::RwLock;
+use warp::{http::StatusCode, Filter};
+
+use super::{auth::require_scope, with_state};
+
+#[derive(serde::Serialize)]
+pub struct IntegrationsResponse {
+    integrations: Vec<IntegrationStatus>,
+}
+
+#[derive(serde::Serialize)]
+pub struct IntegrationErrorResponse {
+    error: String,
+}
+
+pub fn integrations(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path("integrations").and(
+        get_integrations(app_state)
+            .or(get_integration(app_state))
+            .or(restart_integration(app_state)),
+    )
+}
+
+fn get_integrations(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path::end()
+        .and(warp::get())
+        .and(require_scope(app_state, ApiScope::ReadOnly))
+        .and(with_state(app_state))
+        .and_then(get_integrations_impl)
+}
+
+async fn get_integrations_impl(
+    app_state: Arc<RwLock<AppState>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let app_state = app_state.read().await;
+    let integrations = app_state.integrations.get_integrations();
+
+    Ok(warp::reply::json(&IntegrationsResponse { integrations }))
+}
+
+fn get_integration(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!(IntegrationId)
+        .and(warp::get())
+        .and(require_scope(app_state, ApiScope::ReadOnly))
+        .and(with_state(app_state))
+        .and_then(get_integration_impl)
+}
+
+async fn get_integration_impl(
+    integration_id: IntegrationId,
+    app_state: Arc<RwLock<AppState>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let app_state = app_state.read().await;
+
+    let integration = app_state
+        .integrations
+        .get_integrations()
+        .into_iter()
+        .find(|integration| integration.id == integration_id);
+
+    match integration {
+        Some(integration) => Ok(warp::reply::with_status(
+            warp::reply::json(&integration),
+            StatusCode::OK,
+        )),
+        None => Ok(not_found(&integration_id)),
+    }
+}
+
+fn restart_integration(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!(IntegrationId / "restart")
+        .and(warp::post())
+        .and(require_scope(app_state, ApiScope::Admin))
+        .and(with_state(app_state))
+        .and_then(restart_integration_impl)
+}
+
+/// Responds once the restart has been started, the outcome shows up in the
+/// integration status.
+async fn restart_integration_impl(
+    integration_id: IntegrationId,
+    app_state: Arc<RwLock<AppState>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let mut app_state = app_state.write().await;
+
+    let status = |app_state: &AppState| {
+        app_state
+            .integrations
+            .get_integrations()
+            .into_iter()
+            .find(|integration| integration.id == integration_id)
+    };
+
+    match status(&app_state) {
+        None => return Ok(not_found(&integration_id)),
+        Some(status) if status.state == IntegrationState::Starting => {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&IntegrationErrorResponse {
+                    error: format!("Integration {integration_id} is already starting"),
+                }),
+                StatusCode::CONFLICT,
+            ));
+        }
+        Some(_) => {}
+    }
+
+    let result = app_state.integrations.restart(&integration_id);
+
+    match (result, status(&app_state)) {
+        (Ok(()), Some(status)) => Ok(warp::reply::with_status(
+            warp::reply::json(&status),
+            StatusCode::ACCEPTED,
+        )),
+        (Err(e), _) => Ok(warp::reply::with_status(
+            warp::reply::json(&IntegrationErrorResponse {
+                error: format!("{e:#}"),
+            }),
+            StatusCode::INTERNAL_SERVER_ERROR,
+        )),
+        (Ok(()), None) => Ok(not_found(&integration_id)),
+    }
+}
+
+fn not_found(integration_id: &IntegrationId) -> warp::reply::WithStatus<warp::reply::Json> {
+    warp::reply::with_status(
+        warp::reply::json(&IntegrationErrorResponse {
+            error: format!("No integration with id {integration_id}"),
+        }),
+        StatusCode::NOT_FOUND,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_unknown_integration() {
+        let (app_state, _event_rx) =
+            AppState::mk_test_state(Default::default(), Default::default(), Default::default());
+        let filter = integrations(&app_state);
+
+        for (method, path) in [
+            ("GET", "/integrations/unknown"),
+            ("POST", "/integrations/unknown/restart"),
+        ] {
+            let response = warp::test::request()
+                .method(method)
+                .path(path)
+                .reply(&filter)
+                .await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        }
+    }
+}
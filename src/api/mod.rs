@@ -3,14 +3,36 @@ use std::sync::Arc;
 use crate::AppState;
 
 mod actions;
+mod auth;
+mod config;
 mod devices;
+mod groups;
+mod integrations;
+mod routines;
+mod scenes;
+mod tokens;
+mod validation;
 mod ws;
 
 use actions::*;
+use config::*;
 use devices::*;
+use groups::*;
+use integrations::*;
+use routines::*;
+use scenes::*;
+use tokens::*;
 
+use crate::core::config::{ApiConfig, ListenAddress};
 use color_eyre::Result;
-use tokio::sync::RwLock;
+use eyre::Context;
+use std::future::Future;
+use std::net::{Ipv4Addr, SocketAddr};
+use tokio::{
+    net::UnixListener,
+    sync::{watch, RwLock},
+};
+use tokio_stream::wrappers::UnixListenerStream;
 use warp::Filter;
 
 use self::ws::ws;
@@ -22,17 +44,91 @@ pub fn with_state(
     warp::any().map(move || app_state.clone())
 }
 
+static DEFAULT_PORT: u16 = 45289;
+
+/// Resolves once `true` has been sent to the shutdown channel.
+fn shutdown_signal(shutdown: &watch::Receiver<bool>) -> impl Future<Output = ()> {
+    let mut shutdown = shutdown.clone();
+
+    async move {
+        shutdown.wait_for(|shutdown| *shutdown).await.ok();
+    }
+}
+
 // Example of warp usage: https://github.com/seanmonstar/warp/blob/master/examples/todos.rs
-pub fn init_api(app_state: &Arc<RwLock<AppState>>) -> Result<()> {
-    let api = warp::path("api")
-        .and(warp::path("v1"))
-        .and(devices(app_state).or(actions(app_state)));
+//
+// The servers stop accepting new connections once `true` is sent to the
+// shutdown channel.
+pub fn init_api(
+    app_state: &Arc<RwLock<AppState>>,
+    api_config: &ApiConfig,
+    shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let api = warp::path("api").and(warp::path("v1")).and(
+        devices(app_state)
+            .or(actions(app_state))
+            .or(groups(app_state))
+            .or(scenes(app_state))
+            .or(routines(app_state))
+            .or(integrations(app_state))
+            .or(config(app_state))
+            .or(tokens(app_state)),
+    );
 
     let ws = ws(app_state);
+    let routes = ws.or(api).recover(auth::handle_auth_rejection);
 
-    tokio::spawn(async move {
-        warp::serve(ws.or(api)).run(([0, 0, 0, 0], 45289)).await;
+    let listen = api_config.listen.clone().unwrap_or_else(|| {
+        vec![ListenAddress::Tcp {
+            ip: Ipv4Addr::UNSPECIFIED.into(),
+            port: None,
+        }]
     });
 
+    for address in listen {
+        match address {
+            ListenAddress::Tcp { ip, port } => {
+                let addr = SocketAddr::new(ip, port.or(api_config.port).unwrap_or(DEFAULT_PORT));
+
+                if let Some(tls) = &api_config.tls {
+                    let (addr, server) = warp::serve(routes.clone())
+                        .tls()
+                        .cert_path(&tls.cert_path)
+                        .key_path(&tls.key_path)
+                        .try_bind_with_graceful_shutdown(addr, shutdown_signal(&shutdown))
+                        .wrap_err_with(|| format!("Failed to listen on https://{addr}"))?;
+
+                    info!("API listening on https://{addr}");
+                    tokio::spawn(server);
+                } else {
+                    let (addr, server) = warp::serve(routes.clone())
+                        .try_bind_with_graceful_shutdown(addr, shutdown_signal(&shutdown))
+                        .wrap_err_with(|| format!("Failed to listen on http://{addr}"))?;
+
+                    info!("API listening on http://{addr}");
+                    tokio::spawn(server);
+                }
+            }
+            ListenAddress::Unix(path) => {
+                // Remove socket left behind by a previous run
+                if path.exists() {
+                    std::fs::remove_file(&path).wrap_err_with(|| {
+                        format!("Failed to remove stale socket {}", path.display())
+                    })?;
+                }
+
+                let listener = UnixListener::bind(&path)
+                    .wrap_err_with(|| format!("Failed to listen on {}", path.display()))?;
+                let server = warp::serve(routes.clone()).serve_incoming_with_graceful_shutdown(
+                    UnixListenerStream::new(listener),
+                    shutdown_signal(&shutdown),
+                );
+
+                info!("API listening on unix:{}", path.display());
+                tokio::spawn(server);
+            }
+        }
+    }
+
     Ok(())
 }
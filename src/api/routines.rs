@@ -0,0 +1,207 @@
+use std::{convert::Infallible, sync::Arc};
+
+use crate::core::state::AppState;
+use crate::types::{
+    action::Action,
+    auth::ApiScope,
+    event::{Event, EventSource},
+    rule::{RoutineDescriptor, RoutineId, RoutineStatus},
+};
+use tokio::sync::RwLock;
+use warp::{http::StatusCode, Filter};
+
+use super::{auth::require_scope, with_state};
+
+#[derive(serde::Serialize)]
+pub struct RoutinesResponse {
+    routines: Vec<RoutineStatus>,
+}
+
+#[derive(serde::Serialize)]
+pub struct RoutineErrorResponse {
+    error: String,
+}
+
+pub fn routines(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path("routines").and(
+        get_routines(app_state)
+            .or(get_routine(app_state))
+            .or(enable_routine(app_state))
+            .or(disable_routine(app_state)),
+    )
+}
+
+fn get_routines(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path::end()
+        .and(warp::get())
+        .and(require_scope(app_state, ApiScope::ReadOnly))
+        .and(with_state(app_state))
+        .and_then(get_routines_impl)
+}
+
+async fn get_routines_impl(
+    app_state: Arc<RwLock<AppState>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let app_state = app_state.read().await;
+
+    let routines =
+        app_state
+            .rules
+            .get_status(&app_state.devices, &app_state.groups, &app_state.expr);
+
+    Ok(warp::reply::json(&RoutinesResponse { routines }))
+}
+
+fn get_routine(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!(RoutineId)
+        .and(warp::get())
+        .and(require_scope(app_state, ApiScope::ReadOnly))
+        .and(with_state(app_state))
+        .and_then(get_routine_impl)
+}
+
+async fn get_routine_impl(
+    routine_id: RoutineId,
+    app_state: Arc<RwLock<AppState>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let app_state = app_state.read().await;
+
+    let routine = app_state
+        .rules
+        .get_status(&app_state.devices, &app_state.groups, &app_state.expr)
+        .into_iter()
+        .find(|routine| routine.id == routine_id);
+
+    match routine {
+        Some(routine) => Ok(warp::reply::with_status(
+            warp::reply::json(&routine),
+            StatusCode::OK,
+        )),
+        None => Ok(not_found(&routine_id)),
+    }
+}
+
+fn enable_routine(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!(RoutineId / "enable")
+        .and(warp::post())
+        .and(require_scope(app_state, ApiScope::Actions))
+        .and(with_state(app_state))
+        .and_then(enable_routine_impl)
+}
+
+async fn enable_routine_impl(
+    routine_id: RoutineId,
+    app_state: Arc<RwLock<AppState>>,
+) -> Result<impl warp::Reply, Infallible> {
+    send_action(
+        routine_id.clone(),
+        Action::EnableRoutine(RoutineDescriptor { routine_id }),
+        app_state,
+    )
+    .await
+}
+
+fn disable_routine(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!(RoutineId / "disable")
+        .and(warp::post())
+        .and(require_scope(app_state, ApiScope::Actions))
+        .and(with_state(app_state))
+        .and_then(disable_routine_impl)
+}
+
+async fn disable_routine_impl(
+    routine_id: RoutineId,
+    app_state: Arc<RwLock<AppState>>,
+) -> Result<impl warp::Reply, Infallible> {
+    send_action(
+        routine_id.clone(),
+        Action::DisableRoutine(RoutineDescriptor { routine_id }),
+        app_state,
+    )
+    .await
+}
+
+/// Sends an action targeting the given routine, or responds with 404 if the
+/// routine doesn't exist.
+async fn send_action(
+    routine_id: RoutineId,
+    action: Action,
+    app_state: Arc<RwLock<AppState>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let app_state = app_state.read().await;
+
+    if !app_state.rules.has_routine(&routine_id) {
+        return Ok(not_found(&routine_id));
+    }
+
+    let sender = app_state.event_tx.with_source(EventSource::Api);
+    sender.send(Event::Action(action));
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&()),
+        StatusCode::OK,
+    ))
+}
+
+fn not_found(routine_id: &RoutineId) -> warp::reply::WithStatus<warp::reply::Json> {
+    warp::reply::with_status(
+        warp::reply::json(&RoutineErrorResponse {
+            error: format!("No routine with id {routine_id}"),
+        }),
+        StatusCode::NOT_FOUND,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::rule::{Routine, RoutinesConfig};
+
+    #[tokio::test]
+    async fn test_enable_unknown_routine() {
+        let routine_id: RoutineId = "known".parse().unwrap();
+        let routine = Routine {
+            name: "Known".to_string(),
+            rules: vec![],
+            actions: vec![],
+            on_release: None,
+            cooldown: None,
+            debounce: None,
+            rate_limit: None,
+        };
+        let (app_state, mut event_rx) = AppState::mk_test_state(
+            Default::default(),
+            Default::default(),
+            RoutinesConfig::from([(routine_id, routine)]),
+        );
+        let filter = routines(&app_state);
+
+        for action in ["enable", "disable"] {
+            let response = warp::test::request()
+                .method("POST")
+                .path(&format!("/routines/unknown/{action}"))
+                .reply(&filter)
+                .await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+            assert!(event_rx.try_recv().is_err());
+
+            let response = warp::test::request()
+                .method("POST")
+                .path(&format!("/routines/known/{action}"))
+                .reply(&filter)
+                .await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert!(event_rx.try_recv().is_ok());
+        }
+    }
+}
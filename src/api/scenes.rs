@@ -0,0 +1,525 @@
+use std::{convert::Infallible, sync::Arc};
+
+use crate::core::state::AppState;
+use crate::db::{
+    actions::{db_delete_scene, db_edit_scene, db_store_scene},
+    get_db_connection,
+};
+use crate::types::{
+    auth::ApiScope,
+    device::DeviceRef,
+    scene::{FlattenedSceneConfig, SceneConfig, SceneDeviceConfig, SceneId},
+};
+use serde::Deserialize;
+use tokio::sync::RwLock;
+use warp::{http::StatusCode, Filter};
+
+use super::{auth::require_scope, with_state};
+
+#[derive(serde::Serialize)]
+pub struct SceneResponse {
+    id: SceneId,
+
+    /// Scene with device and group states resolved.
+    flattened: Option<FlattenedSceneConfig>,
+
+    /// Scene as defined in the config file or stored in the DB.
+    config: SceneConfig,
+
+    /// Whether the scene is stored in the DB, and can thus be modified
+    /// through the API.
+    from_db: bool,
+}
+
+#[derive(serde::Serialize)]
+pub struct ScenesResponse {
+    scenes: Vec<SceneResponse>,
+}
+
+#[derive(serde::Serialize)]
+pub struct SceneErrorResponse {
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct CreateSceneRequest {
+    id: SceneId,
+    config: SceneConfig,
+}
+
+#[derive(Deserialize)]
+pub struct RenameSceneRequest {
+    name: String,
+}
+
+pub fn scenes(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path("scenes").and(
+        get_scenes(app_state)
+            .or(get_scene(app_state))
+            .or(create_scene(app_state))
+            .or(update_scene(app_state))
+            .or(rename_scene(app_state))
+            .or(delete_scene(app_state)),
+    )
+}
+
+fn mk_scene_response(app_state: &AppState, scene_id: &SceneId) -> Option<SceneResponse> {
+    let config = app_state.scenes.find_scene(scene_id)?;
+
+    Some(SceneResponse {
+        id: scene_id.clone(),
+        flattened: app_state
+            .scenes
+            .get_flattened_scenes()
+            .0
+            .get(scene_id)
+            .cloned(),
+        config,
+        from_db: app_state.scenes.is_db_scene(scene_id),
+    })
+}
+
+fn error_reply(error: String, status: StatusCode) -> warp::reply::WithStatus<warp::reply::Json> {
+    warp::reply::with_status(
+        warp::reply::json(&SceneErrorResponse { error: Some(error) }),
+        status,
+    )
+}
+
+/// Checks that the scene exists and is stored in the DB.
+fn check_db_scene(
+    app_state: &AppState,
+    scene_id: &SceneId,
+) -> Result<(), warp::reply::WithStatus<warp::reply::Json>> {
+    if app_state.scenes.is_config_scene(scene_id) {
+        return Err(error_reply(
+            format!("Scene {scene_id} is defined in the config file and can't be modified"),
+            StatusCode::CONFLICT,
+        ));
+    }
+
+    if !app_state.scenes.is_db_scene(scene_id) {
+        return Err(error_reply(
+            format!("No scene with id {scene_id}"),
+            StatusCode::NOT_FOUND,
+        ));
+    }
+
+    Ok(())
+}
+
+fn check_scene_name(name: &str) -> Result<(), warp::reply::WithStatus<warp::reply::Json>> {
+    if name.trim().is_empty() {
+        return Err(error_reply(
+            "Scene name must not be empty".to_string(),
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    Ok(())
+}
+
+/// Checks that groups, integrations and linked scenes referred to by the scene
+/// exist. Device names aren't checked, as the devices may not have been
+/// discovered yet.
+fn check_scene_config(
+    app_state: &AppState,
+    config: &SceneConfig,
+) -> Result<(), warp::reply::WithStatus<warp::reply::Json>> {
+    let bad_request = |error: String| error_reply(error, StatusCode::BAD_REQUEST);
+    let groups = app_state.groups.get_flattened_groups();
+
+    let mut device_configs: Vec<&SceneDeviceConfig> = vec![];
+
+    for (group_id, device_config) in config.groups.iter().flat_map(|groups| &groups.0) {
+        if !groups.0.contains_key(group_id) {
+            return Err(bad_request(format!("Unknown group {group_id}")));
+        }
+
+        device_configs.push(device_config);
+    }
+
+    for (integration_id, devices) in config.devices.iter().flat_map(|devices| &devices.0) {
+        if !app_state.integrations.has_integration(integration_id) {
+            return Err(bad_request(format!("Unknown integration {integration_id}")));
+        }
+
+        device_configs.extend(devices.values());
+    }
+
+    for device_config in device_configs {
+        match device_config {
+            SceneDeviceConfig::SceneLink(link) => {
+                if app_state.scenes.find_scene(&link.scene_id).is_none() {
+                    return Err(bad_request(format!("Unknown scene {}", link.scene_id)));
+                }
+            }
+            SceneDeviceConfig::DeviceLink(link) => {
+                let integration_id = match &link.device_ref {
+                    DeviceRef::Id(id_ref) => &id_ref.integration_id,
+                    DeviceRef::Name(name_ref) => &name_ref.integration_id,
+                };
+                if !app_state.integrations.has_integration(integration_id) {
+                    return Err(bad_request(format!("Unknown integration {integration_id}")));
+                }
+            }
+            SceneDeviceConfig::DeviceState(_) => {}
+        }
+    }
+
+    Ok(())
+}
+
+async fn check_db_connection() -> Result<(), warp::reply::WithStatus<warp::reply::Json>> {
+    if let Err(e) = get_db_connection().await {
+        return Err(error_reply(
+            format!("Scenes can't be modified: {e}"),
+            StatusCode::SERVICE_UNAVAILABLE,
+        ));
+    }
+
+    Ok(())
+}
+
+/// Reloads DB scenes once the DB has been modified, or responds with the
+/// error if modifying the DB failed.
+async fn finish_db_update(
+    app_state: &mut AppState,
+    result: eyre::Result<()>,
+    status: StatusCode,
+) -> warp::reply::WithStatus<warp::reply::Json> {
+    if let Err(e) = result {
+        return error_reply(
+            format!("Failed to store scene: {e:#}"),
+            StatusCode::INTERNAL_SERVER_ERROR,
+        );
+    }
+
+    app_state.refresh_db_scenes().await;
+
+    warp::reply::with_status(
+        warp::reply::json(&SceneErrorResponse { error: None }),
+        status,
+    )
+}
+
+fn get_scenes(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path::end()
+        .and(warp::get())
+        .and(require_scope(app_state, ApiScope::ReadOnly))
+        .and(with_state(app_state))
+        .and_then(get_scenes_impl)
+}
+
+async fn get_scenes_impl(app_state: Arc<RwLock<AppState>>) -> Result<impl warp::Reply, Infallible> {
+    let app_state = app_state.read().await;
+
+    let scenes = app_state
+        .scenes
+        .get_scene_ids()
+        .iter()
+        .filter_map(|scene_id| mk_scene_response(&app_state, scene_id))
+        .collect();
+
+    Ok(warp::reply::json(&ScenesResponse { scenes }))
+}
+
+fn get_scene(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!(SceneId)
+        .and(warp::get())
+        .and(require_scope(app_state, ApiScope::ReadOnly))
+        .and(with_state(app_state))
+        .and_then(get_scene_impl)
+}
+
+async fn get_scene_impl(
+    scene_id: SceneId,
+    app_state: Arc<RwLock<AppState>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let app_state = app_state.read().await;
+
+    match mk_scene_response(&app_state, &scene_id) {
+        Some(scene) => Ok(warp::reply::with_status(
+            warp::reply::json(&scene),
+            StatusCode::OK,
+        )),
+        None => Ok(error_reply(
+            format!("No scene with id {scene_id}"),
+            StatusCode::NOT_FOUND,
+        )),
+    }
+}
+
+fn create_scene(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path::end()
+        .and(warp::post())
+        .and(require_scope(app_state, ApiScope::Admin))
+        .and(warp::body::json())
+        .and(with_state(app_state))
+        .and_then(create_scene_impl)
+}
+
+async fn create_scene_impl(
+    request: CreateSceneRequest,
+    app_state: Arc<RwLock<AppState>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let mut app_state = app_state.write().await;
+    let scene_id = request.id;
+
+    if let Err(reply) = check_scene_name(&request.config.name) {
+        return Ok(reply);
+    }
+
+    if app_state.scenes.find_scene(&scene_id).is_some() {
+        return Ok(error_reply(
+            format!("Scene {scene_id} already exists"),
+            StatusCode::CONFLICT,
+        ));
+    }
+
+    if let Err(reply) = check_scene_config(&app_state, &request.config) {
+        return Ok(reply);
+    }
+
+    if let Err(reply) = check_db_connection().await {
+        return Ok(reply);
+    }
+
+    let result = db_store_scene(&scene_id, &request.config).await;
+    Ok(finish_db_update(&mut app_state, result, StatusCode::CREATED).await)
+}
+
+fn update_scene(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!(SceneId)
+        .and(warp::put())
+        .and(require_scope(app_state, ApiScope::Admin))
+        .and(warp::body::json())
+        .and(with_state(app_state))
+        .and_then(update_scene_impl)
+}
+
+async fn update_scene_impl(
+    scene_id: SceneId,
+    config: SceneConfig,
+    app_state: Arc<RwLock<AppState>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let mut app_state = app_state.write().await;
+
+    if let Err(reply) = check_scene_name(&config.name) {
+        return Ok(reply);
+    }
+
+    if let Err(reply) = check_db_scene(&app_state, &scene_id) {
+        return Ok(reply);
+    }
+
+    if let Err(reply) = check_scene_config(&app_state, &config) {
+        return Ok(reply);
+    }
+
+    if let Err(reply) = check_db_connection().await {
+        return Ok(reply);
+    }
+
+    let result = db_store_scene(&scene_id, &config).await;
+    Ok(finish_db_update(&mut app_state, result, StatusCode::OK).await)
+}
+
+fn rename_scene(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!(SceneId)
+        .and(warp::patch())
+        .and(require_scope(app_state, ApiScope::Admin))
+        .and(warp::body::json())
+        .and(with_state(app_state))
+        .and_then(rename_scene_impl)
+}
+
+async fn rename_scene_impl(
+    scene_id: SceneId,
+    request: RenameSceneRequest,
+    app_state: Arc<RwLock<AppState>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let mut app_state = app_state.write().await;
+
+    if let Err(reply) = check_scene_name(&request.name) {
+        return Ok(reply);
+    }
+
+    if let Err(reply) = check_db_scene(&app_state, &scene_id) {
+        return Ok(reply);
+    }
+
+    if let Err(reply) = check_db_connection().await {
+        return Ok(reply);
+    }
+
+    let result = db_edit_scene(&scene_id, &request.name).await;
+    Ok(finish_db_update(&mut app_state, result, StatusCode::OK).await)
+}
+
+fn delete_scene(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!(SceneId)
+        .and(warp::delete())
+        .and(require_scope(app_state, ApiScope::Admin))
+        .and(with_state(app_state))
+        .and_then(delete_scene_impl)
+}
+
+async fn delete_scene_impl(
+    scene_id: SceneId,
+    app_state: Arc<RwLock<AppState>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let mut app_state = app_state.write().await;
+
+    if let Err(reply) = check_db_scene(&app_state, &scene_id) {
+        return Ok(reply);
+    }
+
+    if let Err(reply) = check_db_connection().await {
+        return Ok(reply);
+    }
+
+    let result = db_delete_scene(&scene_id).await;
+    Ok(finish_db_update(&mut app_state, result, StatusCode::OK).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::scene::ScenesConfig;
+    use serde_json::json;
+
+    fn mk_filter() -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+        let config = SceneConfig {
+            name: "Evening".to_string(),
+            devices: None,
+            groups: None,
+            hidden: None,
+            expr: None,
+        };
+        let scenes = ScenesConfig::from([("evening".parse().unwrap(), config)]);
+        let (app_state, _event_rx) =
+            AppState::mk_test_state(Default::default(), scenes, Default::default());
+
+        super::scenes(&app_state)
+    }
+
+    async fn request(method: &str, path: &str, body: Option<serde_json::Value>) -> StatusCode {
+        reply(method, path, body).await.status()
+    }
+
+    async fn reply(
+        method: &str,
+        path: &str,
+        body: Option<serde_json::Value>,
+    ) -> warp::http::Response<warp::hyper::body::Bytes> {
+        let request = warp::test::request().method(method).path(path);
+        let request = match body {
+            Some(body) => request.json(&body),
+            None => request,
+        };
+
+        request.reply(&mk_filter()).await
+    }
+
+    #[tokio::test]
+    async fn test_create_scene() {
+        let create = |config| {
+            request(
+                "POST",
+                "/scenes",
+                Some(json!({ "id": "movie", "config": config })),
+            )
+        };
+
+        assert_eq!(
+            create(json!({ "name": " " })).await,
+            StatusCode::BAD_REQUEST
+        );
+        for (config, error) in [
+            (
+                json!({ "name": "Movie", "groups": { "unknown": { "power": false } } }),
+                "Unknown group unknown",
+            ),
+            (
+                json!({ "name": "Movie", "devices": { "unknown": {} } }),
+                "Unknown integration unknown",
+            ),
+        ] {
+            let response = reply(
+                "POST",
+                "/scenes",
+                Some(json!({ "id": "movie", "config": config })),
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+            assert_eq!(
+                serde_json::from_slice::<serde_json::Value>(response.body()).unwrap(),
+                json!({ "error": error })
+            );
+        }
+        assert_eq!(
+            create(json!({ "name": "Movie", "groups": {}, "devices": {} })).await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(
+            request("POST", "/scenes", Some(json!({ "id": "movie" }))).await,
+            StatusCode::BAD_REQUEST
+        );
+
+        let evening = json!({ "id": "evening", "config": { "name": "Evening" } });
+        assert_eq!(
+            request("POST", "/scenes", Some(evening)).await,
+            StatusCode::CONFLICT
+        );
+    }
+
+    #[tokio::test]
+    async fn test_modify_scene() {
+        let config = json!({ "name": "Movie" });
+        let name = json!({ "name": "Movie night" });
+
+        assert_eq!(
+            request("PUT", "/scenes/unknown", Some(config.clone())).await,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            request("PUT", "/scenes/evening", Some(config)).await,
+            StatusCode::CONFLICT
+        );
+        assert_eq!(
+            request("PATCH", "/scenes/unknown", Some(name.clone())).await,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            request("PATCH", "/scenes/evening", Some(json!({ "name": "" }))).await,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            request("PATCH", "/scenes/evening", Some(name)).await,
+            StatusCode::CONFLICT
+        );
+        assert_eq!(
+            request("DELETE", "/scenes/unknown", None).await,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            request("DELETE", "/scenes/evening", None).await,
+            StatusCode::CONFLICT
+        );
+    }
+}
@@ -0,0 +1,130 @@
+use std::{convert::Infallible, sync::Arc};
+
+use crate::core::state::AppState;
+use crate::types::auth::{ApiScope, ApiToken};
+use serde::Deserialize;
+use tokio::sync::RwLock;
+use warp::{http::StatusCode, Filter};
+
+use super::{auth::require_scope, with_state};
+
+#[derive(serde::Serialize)]
+pub struct TokensResponse {
+    tokens: Vec<ApiToken>,
+}
+
+#[derive(Deserialize)]
+pub struct CreateTokenRequest {
+    name: String,
+    scope: ApiScope,
+}
+
+#[derive(serde::Serialize)]
+pub struct CreateTokenResponse {
+    name: String,
+    scope: ApiScope,
+    token: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct TokenErrorResponse {
+    error: Option<String>,
+}
+
+pub fn tokens(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path("tokens").and(
+        get_tokens(app_state)
+            .or(create_token(app_state))
+            .or(delete_token(app_state)),
+    )
+}
+
+fn get_tokens(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path::end()
+        .and(warp::get())
+        .and(require_scope(app_state, ApiScope::Admin))
+        .and(with_state(app_state))
+        .and_then(get_tokens_impl)
+}
+
+async fn get_tokens_impl(app_state: Arc<RwLock<AppState>>) -> Result<impl warp::Reply, Infallible> {
+    let app_state = app_state.read().await;
+    let tokens = app_state.auth.get_tokens();
+
+    Ok(warp::reply::json(&TokensResponse { tokens }))
+}
+
+fn create_token(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path::end()
+        .and(warp::post())
+        .and(require_scope(app_state, ApiScope::Admin))
+        .and(warp::body::json())
+        .and(with_state(app_state))
+        .and_then(create_token_impl)
+}
+
+async fn create_token_impl(
+    request: CreateTokenRequest,
+    app_state: Arc<RwLock<AppState>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let mut app_state = app_state.write().await;
+
+    let result = app_state
+        .auth
+        .create_token(request.name.clone(), request.scope)
+        .await;
+
+    match result {
+        Ok(token) => Ok(warp::reply::with_status(
+            warp::reply::json(&CreateTokenResponse {
+                name: request.name,
+                scope: request.scope,
+                token,
+            }),
+            StatusCode::OK,
+        )),
+        Err(e) => Ok(warp::reply::with_status(
+            warp::reply::json(&TokenErrorResponse {
+                error: Some(format!("{e:#}")),
+            }),
+            StatusCode::BAD_REQUEST,
+        )),
+    }
+}
+
+fn delete_token(
+    app_state: &Arc<RwLock<AppState>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!(String)
+        .and(warp::delete())
+        .and(require_scope(app_state, ApiScope::Admin))
+        .and(with_state(app_state))
+        .and_then(delete_token_impl)
+}
+
+async fn delete_token_impl(
+    name: String,
+    app_state: Arc<RwLock<AppState>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let mut app_state = app_state.write().await;
+
+    let (error, status) = match app_state.auth.delete_token(&name).await {
+        Ok(true) => (None, StatusCode::OK),
+        Ok(false) => (
+            Some(format!("No token named {name}")),
+            StatusCode::NOT_FOUND,
+        ),
+        Err(e) => (Some(format!("{e:#}")), StatusCode::BAD_REQUEST),
+    };
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&TokenErrorResponse { error }),
+        status,
+    ))
+}
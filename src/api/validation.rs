@@ -0,0 +1,306 @@
+use crate::core::state::AppState;
+use crate::types::{
+    action::Action,
+    auth::ApiScope,
+    device::{Device, DeviceKey},
+    group::GroupId,
+    rule::{ForceTriggerRoutineDescriptor, RoutineDescriptor, RoutineId},
+    scene::{ActivateSceneDescriptor, SceneId},
+    ui::UiActionDescriptor,
+    websockets::{MessageKind, WebSocketRequest},
+};
+
+/// Scope required for a WebSocket peer to send the given request.
+pub fn required_scope(request: &WebSocketRequest) -> ApiScope {
+    match request {
+        WebSocketRequest::GetState => ApiScope::ReadOnly,
+        WebSocketRequest::Subscribe(subscription) => {
+            let kinds = subscription.kinds.iter().flatten();
+
+            if kinds.into_iter().any(|kind| *kind == MessageKind::Logs) {
+                ApiScope::Admin
+            } else {
+                ApiScope::ReadOnly
+            }
+        }
+        WebSocketRequest::Action(action) => action_scope(action),
+        WebSocketRequest::StoreScene { .. }
+        | WebSocketRequest::EditScene { .. }
+        | WebSocketRequest::DeleteScene { .. } => ApiScope::Admin,
+    }
+}
+
+/// Scope required to trigger the given action, over the WebSocket or the
+/// REST API.
+pub fn action_scope(action: &Action) -> ApiScope {
+    match action {
+        // Custom actions and expressions can reach any integration and
+        // device, bypassing the checks in validate_action
+        Action::Custom(_) | Action::EvalExpr(_) => ApiScope::Admin,
+        _ => ApiScope::Actions,
+    }
+}
+
+fn check_devices(app_state: &AppState, device_keys: &Option<Vec<DeviceKey>>) -> Result<(), String> {
+    for device_key in device_keys.iter().flatten() {
+        if app_state.devices.get_device(device_key).is_none() {
+            return Err(format!("Unknown device {device_key}"));
+        }
+    }
+
+    Ok(())
+}
+
+fn check_groups(app_state: &AppState, group_keys: &Option<Vec<GroupId>>) -> Result<(), String> {
+    let groups = app_state.groups.get_flattened_groups();
+
+    for group_id in group_keys.iter().flatten() {
+        if !groups.0.contains_key(group_id) {
+            return Err(format!("Unknown group {group_id}"));
+        }
+    }
+
+    Ok(())
+}
+
+fn check_scene(app_state: &AppState, scene: &ActivateSceneDescriptor) -> Result<(), String> {
+    if app_state.scenes.find_scene(&scene.scene_id).is_none() {
+        return Err(format!("Unknown scene {}", scene.scene_id));
+    }
+
+    check_devices(app_state, &scene.device_keys)?;
+    check_groups(app_state, &scene.group_keys)
+}
+
+fn check_routine(app_state: &AppState, routine_id: &RoutineId) -> Result<(), String> {
+    if !app_state.rules.has_routine(routine_id) {
+        return Err(format!("Unknown routine {routine_id}"));
+    }
+
+    Ok(())
+}
+
+fn check_db_scene(app_state: &AppState, scene_id: &SceneId) -> Result<(), String> {
+    if !app_state.scenes.is_db_scene(scene_id) {
+        return Err(format!("Scene {scene_id} is not stored in the DB"));
+    }
+
+    Ok(())
+}
+
+/// Only devices that homectl may control can have their state set. Fields
+/// tracked by homectl itself are taken from the current device state rather
+/// than from the client.
+pub fn check_set_device_state(app_state: &AppState, device: &mut Device) -> Result<(), String> {
+    let device_key = device.get_device_key();
+    let current = app_state
+        .devices
+        .get_device(&device_key)
+        .ok_or_else(|| format!("Unknown device {device_key}"))?;
+
+    if current.is_sensor() || device.is_sensor() {
+        return Err(format!("Device {device_key} is a sensor"));
+    }
+
+    if current.is_readonly() {
+        return Err(format!("Device {device_key} is read-only"));
+    }
+
+    device.available = current.available;
+    device.last_seen = current.last_seen;
+
+    Ok(())
+}
+
+/// Makes sure the action only refers to existing devices, groups, scenes and
+/// routines, and only sets the state of controllable devices.
+pub fn validate_action(app_state: &AppState, action: &mut Action) -> Result<(), String> {
+    match action {
+        Action::ActivateScene(scene) => check_scene(app_state, scene),
+        Action::CycleScenes(cycle) => {
+            for scene in &cycle.scenes {
+                check_scene(app_state, scene)?;
+            }
+            check_devices(app_state, &cycle.device_keys)?;
+            check_groups(app_state, &cycle.group_keys)
+        }
+        Action::Dim(dim) => {
+            check_devices(app_state, &dim.device_keys)?;
+            check_groups(app_state, &dim.group_keys)
+        }
+        Action::ForceTriggerRoutine(ForceTriggerRoutineDescriptor { routine_id })
+        | Action::EnableRoutine(RoutineDescriptor { routine_id })
+        | Action::DisableRoutine(RoutineDescriptor { routine_id }) => {
+            check_routine(app_state, routine_id)
+        }
+        Action::SetDeviceState(device) => check_set_device_state(app_state, device),
+        Action::ToggleDeviceOverride { device_keys, .. } => {
+            check_devices(app_state, &Some(device_keys.clone()))
+        }
+        Action::Ui(UiActionDescriptor::StoreUIState { key, .. }) => {
+            if key.is_empty() {
+                return Err("UI state key must not be empty".to_string());
+            }
+
+            Ok(())
+        }
+        // Only allowed with the Admin scope, see action_scope
+        Action::Custom(_) | Action::EvalExpr(_) => Ok(()),
+    }
+}
+
+/// Validates actions with [validate_action], and makes sure the request only
+/// modifies scenes that are stored in the DB.
+pub fn validate_request(
+    app_state: &AppState,
+    request: &mut WebSocketRequest,
+) -> Result<(), String> {
+    match request {
+        WebSocketRequest::Action(action) => validate_action(app_state, action),
+        WebSocketRequest::StoreScene { scene_id, config } => {
+            if config.name.trim().is_empty() {
+                return Err("Scene name must not be empty".to_string());
+            }
+
+            if app_state.scenes.is_config_scene(scene_id) {
+                return Err(format!(
+                    "Scene {scene_id} is defined in the config file and can't be replaced"
+                ));
+            }
+
+            Ok(())
+        }
+        WebSocketRequest::EditScene { scene_id, name } => {
+            if name.trim().is_empty() {
+                return Err("Scene name must not be empty".to_string());
+            }
+
+            check_db_scene(app_state, scene_id)
+        }
+        WebSocketRequest::DeleteScene { scene_id } => check_db_scene(app_state, scene_id),
+        WebSocketRequest::Subscribe(subscription) => {
+            check_devices(app_state, &subscription.devices)?;
+            check_groups(app_state, &subscription.groups)?;
+
+            for integration_id in subscription.integrations.iter().flatten() {
+                if !app_state.integrations.has_integration(integration_id) {
+                    return Err(format!("Unknown integration {integration_id}"));
+                }
+            }
+
+            Ok(())
+        }
+        WebSocketRequest::GetState => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{
+        color::Capabilities,
+        device::{ControllableDevice, DeviceData, DeviceId, ManageKind, SensorDevice},
+        integration::{CustomActionDescriptor, IntegrationActionPayload, IntegrationId},
+    };
+    use chrono::Utc;
+
+    fn mk_device(id: &str, data: DeviceData) -> Device {
+        Device::new(
+            IntegrationId::from("test".to_string()),
+            DeviceId::new(id),
+            id.to_string(),
+            data,
+            None,
+        )
+    }
+
+    fn mk_light(id: &str, managed: ManageKind) -> Device {
+        let light = ControllableDevice::new(
+            None,
+            true,
+            Some(1.0),
+            None,
+            None,
+            Capabilities::default(),
+            managed,
+        );
+
+        mk_device(id, DeviceData::Controllable(light))
+    }
+
+    fn set_state(device: Device) -> WebSocketRequest {
+        WebSocketRequest::Action(Action::SetDeviceState(device))
+    }
+
+    #[tokio::test]
+    async fn test_set_device_state() {
+        let (app_state, _event_rx) =
+            AppState::mk_test_state(Default::default(), Default::default(), Default::default());
+        let mut app_state = app_state.write().await;
+        let scenes = app_state.scenes.clone();
+        let sensor = mk_device(
+            "sensor",
+            DeviceData::Sensor(SensorDevice::Boolean { value: true }),
+        );
+
+        for device in [
+            sensor.clone(),
+            mk_light("light", ManageKind::Full),
+            mk_light("readonly", ManageKind::FullReadOnly),
+        ] {
+            app_state
+                .devices
+                .handle_external_state_update(&device, &scenes)
+                .await
+                .unwrap();
+        }
+
+        let err = validate_request(&app_state, &mut set_state(sensor)).unwrap_err();
+        assert_eq!(err, "Device test/sensor is a sensor");
+
+        let readonly = mk_light("readonly", ManageKind::FullReadOnly);
+        let err = validate_request(&app_state, &mut set_state(readonly)).unwrap_err();
+        assert_eq!(err, "Device test/readonly is read-only");
+
+        let unknown = mk_light("unknown", ManageKind::Full);
+        let err = validate_request(&app_state, &mut set_state(unknown)).unwrap_err();
+        assert_eq!(err, "Unknown device test/unknown");
+
+        // Client supplied availability is replaced with the tracked one
+        let mut light = mk_light("light", ManageKind::Full);
+        light.available = false;
+        light.last_seen = Some(Utc::now() + chrono::Duration::days(1));
+        let mut request = set_state(light);
+        validate_request(&app_state, &mut request).unwrap();
+
+        let WebSocketRequest::Action(Action::SetDeviceState(light)) = request else {
+            unreachable!()
+        };
+        let current = app_state
+            .devices
+            .get_device(&light.get_device_key())
+            .unwrap();
+        assert!(light.available);
+        assert_eq!(light.last_seen, current.last_seen);
+    }
+
+    #[test]
+    fn test_required_scope() {
+        let custom = Action::Custom(CustomActionDescriptor {
+            integration_id: IntegrationId::from("test".to_string()),
+            payload: IntegrationActionPayload::from("{}".to_string()),
+        });
+        let eval = Action::EvalExpr(evalexpr::build_operator_tree("true").unwrap());
+        let light = mk_light("light", ManageKind::Full);
+
+        assert_eq!(
+            required_scope(&WebSocketRequest::Action(custom)),
+            ApiScope::Admin
+        );
+        assert_eq!(
+            required_scope(&WebSocketRequest::Action(eval)),
+            ApiScope::Admin
+        );
+        assert_eq!(required_scope(&set_state(light)), ApiScope::Actions);
+    }
+}
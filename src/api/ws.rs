@@ -1,5 +1,14 @@
-use super::with_state;
-use crate::types::websockets::WebSocketRequest;
+use super::{
+    auth::with_scope,
+    validation::{required_scope, validate_request},
+    with_state,
+};
+use crate::core::websockets::WebSockets;
+use crate::types::{
+    auth::ApiScope,
+    event::{Event, EventSource},
+    websockets::{WebSocketError, WebSocketErrorKind, WebSocketRequest, WebSocketResponse},
+};
 use crate::AppState;
 use futures::SinkExt;
 use futures_util::{StreamExt, TryFutureExt};
@@ -20,15 +29,25 @@ pub fn ws(
     warp::path("ws")
         // The `ws()` filter will prepare the Websocket handshake.
         .and(warp::ws())
+        .and(with_scope(app_state, ApiScope::ReadOnly))
         .and(with_state(app_state))
-        .map(|ws: warp::ws::Ws, app_state: Arc<RwLock<AppState>>| {
-            // This will call our function if the handshake succeeds.
-            ws.on_upgrade(move |socket| user_connected(socket, app_state))
-        })
+        .map(
+            |ws: warp::ws::Ws, scope: ApiScope, app_state: Arc<RwLock<AppState>>| {
+                // This will call our function if the handshake succeeds.
+                ws.on_upgrade(move |socket| user_connected(socket, app_state, scope))
+            },
+        )
+}
+
+async fn send_error(ws: &WebSockets, user_id: usize, kind: WebSocketErrorKind, message: String) {
+    debug!("Rejected websocket request (uid={user_id}): {message}");
+
+    let error = WebSocketResponse::Error(WebSocketError { kind, message });
+    ws.send(Some(user_id), &error).await;
 }
 
 // https://github.com/seanmonstar/warp/blob/master/examples/websockets_chat.rs
-async fn user_connected(ws: WebSocket, app_state: Arc<RwLock<AppState>>) {
+async fn user_connected(ws: WebSocket, app_state: Arc<RwLock<AppState>>, scope: ApiScope) {
     // Use a counter to assign a new unique ID for this user.
     let my_id = NEXT_USER_ID.fetch_add(1, Ordering::Relaxed);
 
@@ -51,13 +70,17 @@ async fn user_connected(ws: WebSocket, app_state: Arc<RwLock<AppState>>) {
         }
     });
 
-    let app_state = app_state.read().await.clone();
+    let (ws, event_tx) = {
+        let app_state = app_state.read().await;
+        let event_tx = app_state.event_tx.with_source(EventSource::WebSocket);
+        (app_state.ws.clone(), event_tx)
+    };
 
     // Save the sender in our list of connected users.
-    app_state.ws.user_connected(my_id, tx).await;
+    ws.user_connected(my_id, tx).await;
 
     // Send snapshot of current state
-    app_state.send_state_ws(Some(my_id)).await;
+    app_state.read().await.send_state_ws(Some(my_id)).await;
 
     // Let AppState handle incoming user messages
     while let Some(result) = user_ws_rx.next().await {
@@ -69,21 +92,54 @@ async fn user_connected(ws: WebSocket, app_state: Arc<RwLock<AppState>>) {
             }
         };
 
-        let json = msg.to_str();
+        let Ok(json) = msg.to_str() else {
+            continue;
+        };
 
-        if let Ok(json) = json {
-            let msg = serde_json::from_str::<WebSocketRequest>(json);
+        let mut request = match serde_json::from_str::<WebSocketRequest>(json) {
+            Ok(request) => request,
+            Err(e) => {
+                let message = format!("Invalid websocket message: {e}");
+                send_error(&ws, my_id, WebSocketErrorKind::InvalidMessage, message).await;
+                continue;
+            }
+        };
 
-            match msg {
-                Ok(WebSocketRequest::EventMessage(event)) => {
-                    app_state.event_tx.send(event);
+        let required = required_scope(&request);
+        if scope < required {
+            let message = format!("API token lacks the required scope {required}");
+            send_error(&ws, my_id, WebSocketErrorKind::Forbidden, message).await;
+            continue;
+        }
+
+        let app_state = app_state.read().await;
+
+        if let Err(message) = validate_request(&app_state, &mut request) {
+            send_error(&ws, my_id, WebSocketErrorKind::InvalidRequest, message).await;
+            continue;
+        }
+
+        match request {
+            WebSocketRequest::Action(action) => event_tx.send(Event::Action(action)),
+            WebSocketRequest::StoreScene { scene_id, config } => {
+                event_tx.send(Event::DbStoreScene { scene_id, config })
+            }
+            WebSocketRequest::EditScene { scene_id, name } => {
+                event_tx.send(Event::DbEditScene { scene_id, name })
+            }
+            WebSocketRequest::DeleteScene { scene_id } => {
+                event_tx.send(Event::DbDeleteScene { scene_id })
+            }
+            WebSocketRequest::Subscribe(subscription) => {
+                if ws.subscribe(my_id, subscription).await {
+                    app_state.send_state_ws(Some(my_id)).await;
                 }
-                Err(e) => warn!("Error while deserializing websocket message: {e}"),
             }
+            WebSocketRequest::GetState => app_state.send_state_ws(Some(my_id)).await,
         }
     }
 
     // user_ws_rx stream will keep processing as long as the user stays
     // connected. Once they disconnect, then...
-    app_state.ws.user_disconnected(my_id).await;
+    ws.user_disconnected(my_id).await;
 }
@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+
+use eyre::Result;
+use rand::{distributions::Alphanumeric, Rng};
+use sha2::{Digest, Sha256};
+
+use crate::db::actions::{db_delete_api_token, db_get_api_tokens, db_store_api_token};
+use crate::types::auth::{ApiScope, ApiToken};
+
+use super::config::ApiConfig;
+
+const TOKEN_LENGTH: usize = 40;
+
+#[derive(Clone, Debug)]
+struct TokenEntry {
+    name: String,
+    scope: ApiScope,
+    from_config: bool,
+}
+
+/// Keeps track of API tokens, both from the config file and those created
+/// through the API. Only SHA-256 hashes of tokens are kept in memory and in
+/// the database.
+#[derive(Clone, Default)]
+pub struct Auth {
+    require_auth: bool,
+    config_tokens: HashMap<String, TokenEntry>,
+    db_tokens: HashMap<String, TokenEntry>,
+}
+
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+impl Auth {
+    pub fn new(config: &ApiConfig) -> Self {
+        let mut auth = Self::default();
+        auth.set_config(config);
+        auth
+    }
+
+    pub fn set_config(&mut self, config: &ApiConfig) {
+        self.require_auth = config.require_auth.unwrap_or(false);
+        self.config_tokens = config
+            .tokens
+            .iter()
+            .flatten()
+            .map(|token| {
+                (
+                    hash_token(&token.token),
+                    TokenEntry {
+                        name: token.name.clone(),
+                        scope: token.scope,
+                        from_config: true,
+                    },
+                )
+            })
+            .collect();
+    }
+
+    pub async fn refresh_db_tokens(&mut self) {
+        match db_get_api_tokens().await {
+            Ok(tokens) => {
+                self.db_tokens = tokens
+                    .into_iter()
+                    .map(|(name, token_hash, scope)| {
+                        (
+                            token_hash,
+                            TokenEntry {
+                                name,
+                                scope,
+                                from_config: false,
+                            },
+                        )
+                    })
+                    .collect();
+            }
+            Err(e) => debug!("Not loading API tokens from DB: {e}"),
+        }
+    }
+
+    /// Only the config decides whether authentication is required. Tokens
+    /// from the database are ignored while it isn't, so that creating one
+    /// can't lock out clients that don't have a token.
+    fn auth_required(&self) -> bool {
+        self.require_auth || !self.config_tokens.is_empty()
+    }
+
+    /// Returns the scope granted by the given token, or None if the token is
+    /// missing or invalid. If authentication isn't required, every request is
+    /// granted the admin scope.
+    pub fn authenticate(&self, token: Option<&str>) -> Option<ApiScope> {
+        if !self.auth_required() {
+            return Some(ApiScope::Admin);
+        }
+
+        let token_hash = hash_token(token?);
+
+        self.config_tokens
+            .get(&token_hash)
+            .or_else(|| self.db_tokens.get(&token_hash))
+            .map(|entry| entry.scope)
+    }
+
+    pub fn get_tokens(&self) -> Vec<ApiToken> {
+        let mut tokens: Vec<ApiToken> = self
+            .config_tokens
+            .values()
+            .chain(self.db_tokens.values())
+            .map(|entry| ApiToken {
+                name: entry.name.clone(),
+                scope: entry.scope,
+                from_config: entry.from_config,
+            })
+            .collect();
+
+        tokens.sort_by(|a, b| a.name.cmp(&b.name));
+        tokens
+    }
+
+    /// Creates a new random token and stores its hash in the database. The
+    /// returned token can't be retrieved later.
+    pub async fn create_token(&mut self, name: String, scope: ApiScope) -> Result<String> {
+        if !self.auth_required() {
+            return Err(eyre!(
+                "API tokens can only be created when authentication is enabled with require_auth or config tokens"
+            ));
+        }
+
+        if self.get_tokens().iter().any(|token| token.name == name) {
+            return Err(eyre!("Token named {name} already exists"));
+        }
+
+        let token: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(TOKEN_LENGTH)
+            .map(char::from)
+            .collect();
+        let token_hash = hash_token(&token);
+
+        db_store_api_token(&name, &token_hash, scope).await?;
+        self.db_tokens.insert(
+            token_hash,
+            TokenEntry {
+                name,
+                scope,
+                from_config: false,
+            },
+        );
+
+        Ok(token)
+    }
+
+    /// Deletes a token created through the API. Returns false if no such
+    /// token exists.
+    pub async fn delete_token(&mut self, name: &str) -> Result<bool> {
+        if self.config_tokens.values().any(|entry| entry.name == name) {
+            return Err(eyre!(
+                "Token {name} is defined in the config file and can't be deleted"
+            ));
+        }
+
+        let deleted = db_delete_api_token(name).await?;
+        self.db_tokens.retain(|_, entry| entry.name != name);
+
+        Ok(deleted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::config::ApiTokenConfig;
+
+    fn mk_config(require_auth: Option<bool>, tokens: Vec<(&str, &str, ApiScope)>) -> ApiConfig {
+        ApiConfig {
+            require_auth,
+            tokens: Some(
+                tokens
+                    .into_iter()
+                    .map(|(name, token, scope)| ApiTokenConfig {
+                        name: name.to_string(),
+                        token: token.to_string(),
+                        scope,
+                    })
+                    .collect(),
+            ),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_no_auth_required() {
+        let auth = Auth::new(&ApiConfig::default());
+        assert_eq!(auth.authenticate(None), Some(ApiScope::Admin));
+
+        let auth = Auth::new(&mk_config(Some(true), vec![]));
+        assert_eq!(auth.authenticate(None), None);
+    }
+
+    #[test]
+    fn test_config_tokens() {
+        let auth = Auth::new(&mk_config(
+            None,
+            vec![
+                ("dashboard", "secret1", ApiScope::ReadOnly),
+                ("automation", "secret2", ApiScope::Actions),
+            ],
+        ));
+
+        assert_eq!(auth.authenticate(None), None);
+        assert_eq!(auth.authenticate(Some("wrong")), None);
+        assert_eq!(auth.authenticate(Some("secret1")), Some(ApiScope::ReadOnly));
+        assert_eq!(auth.authenticate(Some("secret2")), Some(ApiScope::Actions));
+    }
+
+    #[tokio::test]
+    async fn test_db_tokens_need_auth_enabled() {
+        let mut auth = Auth::new(&ApiConfig::default());
+        assert!(auth
+            .create_token("tablet".to_string(), ApiScope::Actions)
+            .await
+            .is_err());
+
+        // Leftover DB tokens don't turn on authentication by themselves
+        auth.db_tokens.insert(
+            hash_token("secret"),
+            TokenEntry {
+                name: "tablet".to_string(),
+                scope: ApiScope::Actions,
+                from_config: false,
+            },
+        );
+        assert_eq!(auth.authenticate(None), Some(ApiScope::Admin));
+
+        auth.set_config(&mk_config(Some(true), vec![]));
+        assert_eq!(auth.authenticate(None), None);
+        assert_eq!(auth.authenticate(Some("secret")), Some(ApiScope::Actions));
+    }
+
+    #[test]
+    fn test_scope_ordering() {
+        assert!(ApiScope::ReadOnly < ApiScope::Actions);
+        assert!(ApiScope::Actions < ApiScope::Admin);
+    }
+}
@@ -1,17 +1,141 @@
 use crate::types::{
+    auth::ApiScope,
     group::GroupsConfig,
     integration::{IntegrationId, IntegrationsConfig},
     rule::RoutinesConfig,
     scene::ScenesConfig,
 };
+use crate::utils::solar::Location;
 use color_eyre::Result;
 use eyre::Context;
-use serde::Deserialize;
-use std::{collections::HashMap, fs::File, io::Read};
+use serde::{de, Deserialize};
+use std::{
+    collections::{HashMap, HashSet},
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::RwLock,
+};
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Default)]
 pub struct CoreConfig {
     pub warmup_time_seconds: Option<u64>,
+
+    /// If set, all handled events are appended to this file. The file can
+    /// later be replayed using the `--replay` command line option.
+    pub event_journal_path: Option<PathBuf>,
+
+    /// Rotates the event journal once it grows beyond this many megabytes.
+    /// Without a limit the journal grows forever.
+    pub event_journal_max_size_mb: Option<u64>,
+
+    /// Enables recording device state history to the database.
+    pub device_history: Option<DeviceHistoryConfig>,
+
+    /// Used for calculating sunrise, sunset and other solar events.
+    pub location: Option<Location>,
+
+    /// Where the REST and WebSocket API listens for connections.
+    pub api: Option<ApiConfig>,
+
+    /// File where integrations store their state when no database is
+    /// configured. Defaults to `integration_storage.json` next to the main
+    /// config file.
+    pub integration_storage_path: Option<PathBuf>,
+}
+
+/// Address for the API to listen on, either an IP address with an optional
+/// port ("127.0.0.1", "::1", "[::1]:8080") or a Unix socket path
+/// ("unix:/run/homectl.sock").
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ListenAddress {
+    Tcp { ip: IpAddr, port: Option<u16> },
+    Unix(PathBuf),
+}
+
+impl FromStr for ListenAddress {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            return Ok(ListenAddress::Unix(PathBuf::from(path)));
+        }
+
+        if let Ok(addr) = s.parse::<SocketAddr>() {
+            return Ok(ListenAddress::Tcp {
+                ip: addr.ip(),
+                port: Some(addr.port()),
+            });
+        }
+
+        let ip = s
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse()
+            .map_err(|_| eyre!("Expected an IP address or unix:<path>, got '{s}'"))?;
+
+        Ok(ListenAddress::Tcp { ip, port: None })
+    }
+}
+
+impl<'de> Deserialize<'de> for ListenAddress {
+    fn deserialize<D>(d: D) -> Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        let s = String::deserialize(d)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct TlsConfig {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct ApiConfig {
+    /// Addresses to listen on. Defaults to all IPv4 interfaces.
+    pub listen: Option<Vec<ListenAddress>>,
+
+    /// Port used for addresses that don't specify one. Defaults to 45289.
+    pub port: Option<u16>,
+
+    /// Serves the API over HTTPS on TCP addresses. Unix sockets are always
+    /// served over plain HTTP.
+    pub tls: Option<TlsConfig>,
+
+    /// Rejects requests without a valid token. Implied if any tokens are
+    /// configured here. Tokens can only be created through the API when
+    /// either is set.
+    pub require_auth: Option<bool>,
+
+    /// Tokens that grant access to the API.
+    pub tokens: Option<Vec<ApiTokenConfig>>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ApiTokenConfig {
+    pub name: String,
+    pub token: String,
+    pub scope: ApiScope,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct DeviceHistoryConfig {
+    /// History entries older than this are periodically deleted. History is
+    /// kept forever if omitted.
+    pub retention_days: Option<u64>,
+
+    /// Minimum time between two recorded entries of the same device. Of the
+    /// state changes in between, only the latest one is recorded once the
+    /// interval has passed.
+    pub min_interval_seconds: Option<u64>,
+
+    /// Default for the `interval` parameter of history queries, which
+    /// downsamples the returned history to one entry per interval.
+    pub downsample_interval_seconds: Option<u64>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -23,41 +147,504 @@ pub struct Config {
     pub routines: Option<RoutinesConfig>,
 }
 
-type OpaqueIntegrationsConfigs = HashMap<IntegrationId, config::Value>;
+pub type OpaqueIntegrationsConfigs = HashMap<IntegrationId, config::Value>;
 
-pub fn read_config() -> Result<(Config, OpaqueIntegrationsConfigs)> {
-    let builder = config::Config::builder();
+static CONFIG_PATH: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+/// Overrides the path of the main config file, as given by `--config`.
+pub fn set_config_path(path: Option<PathBuf>) {
+    *CONFIG_PATH.write().unwrap() = path;
+}
+
+/// Path of the main config file, Settings.toml in the current directory
+/// unless overridden with `--config`.
+pub fn config_path() -> PathBuf {
+    CONFIG_PATH.read().unwrap().clone().unwrap_or_else(|| {
+        let root = std::env::current_dir().unwrap();
+        root.join("Settings.toml")
+    })
+}
+
+/// Returns the 1-based line and column of a byte offset within `contents`.
+fn line_col(contents: &str, offset: usize) -> (usize, usize) {
+    let before = &contents[..offset.min(contents.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rfind('\n')
+        .map_or(before, |newline| &before[newline + 1..])
+        .chars()
+        .count()
+        + 1;
+
+    (line, column)
+}
+
+/// Deserializes the config, pointing out the exact location of any errors.
+fn parse_config(contents: &str, path: &Path) -> Result<Config> {
+    let deserializer = toml::Deserializer::new(contents);
+
+    serde_path_to_error::deserialize(deserializer).map_err(|e| {
+        let location = e
+            .inner()
+            .span()
+            .map(|span| {
+                let (line, column) = line_col(contents, span.start);
+                format!(":{line}:{column}")
+            })
+            .unwrap_or_default();
+
+        let message = e.inner().message().trim_end();
+
+        match e.path().to_string().as_str() {
+            "." => eyre!("{}{location}: {message}", path.display()),
+            key => eyre!("{}{location}: {message} (at `{key}`)", path.display()),
+        }
+    })
+}
+
+/// Reads a config file and any files it includes, in load order. Paths in
+/// `include` are relative to the including file. Directories include all
+/// `.toml` files within them in alphabetical order.
+fn load_config_files(
+    path: &Path,
+    files: &mut Vec<(PathBuf, toml::Table)>,
+    visited: &mut HashSet<PathBuf>,
+) -> Result<()> {
+    let canonical_path = path
+        .canonicalize()
+        .wrap_err_with(|| format!("Failed to read config file {}", path.display()))?;
+
+    if !visited.insert(canonical_path) {
+        return Err(eyre!("{} is included more than once", path.display()));
+    }
+
+    let contents = std::fs::read_to_string(path)
+        .wrap_err_with(|| format!("Failed to read config file {}", path.display()))?;
+
+    // Deserialize each file on its own first, so that errors point to the
+    // right file and location
+    parse_config(&contents, path)?;
+
+    let mut table: toml::Table = toml::from_str(&contents)?;
+    let includes = table.remove("include");
+    files.push((path.to_path_buf(), table));
+
+    let Some(includes) = includes else {
+        return Ok(());
+    };
+
+    let includes: Vec<PathBuf> = includes.try_into().wrap_err_with(|| {
+        format!(
+            "Expected include in {} to be a list of paths",
+            path.display()
+        )
+    })?;
+    let dir = path.parent().unwrap_or(Path::new("."));
+
+    for include in includes {
+        let include = dir.join(include);
+
+        if include.is_dir() {
+            let mut entries = std::fs::read_dir(&include)
+                .wrap_err_with(|| format!("Failed to read directory {}", include.display()))?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<_>, _>>()?;
+            entries.retain(|entry| entry.extension().is_some_and(|ext| ext == "toml"));
+            entries.sort();
+
+            for entry in entries {
+                load_config_files(&entry, files, visited)?;
+            }
+        } else {
+            load_config_files(&include, files, visited)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn conflict_error(key: &str, origin: &Path, path: &Path) -> eyre::Report {
+    eyre!(
+        "`{key}` is defined in both {} and {}",
+        origin.display(),
+        path.display()
+    )
+}
+
+/// Merges config files into one table. Sections such as `[scenes]` are
+/// merged entry by entry, and defining the same entry (e.g.
+/// `scenes.evening`) in more than one file is an error.
+fn merge_config_files(files: Vec<(PathBuf, toml::Table)>) -> Result<toml::Table> {
+    let mut merged = toml::Table::new();
+
+    // Which file each section and entry was first defined in
+    let mut origins: HashMap<String, PathBuf> = HashMap::new();
+
+    for (path, table) in files {
+        for (section, value) in table {
+            match (merged.get_mut(&section), value) {
+                (None, value) => {
+                    if let toml::Value::Table(entries) = &value {
+                        for key in entries.keys() {
+                            origins.insert(format!("{section}.{key}"), path.clone());
+                        }
+                    }
+
+                    origins.insert(section.clone(), path.clone());
+                    merged.insert(section, value);
+                }
+                (Some(toml::Value::Table(merged_entries)), toml::Value::Table(entries)) => {
+                    for (key, value) in entries {
+                        let key_path = format!("{section}.{key}");
+
+                        if let Some(origin) = origins.get(&key_path) {
+                            return Err(conflict_error(&key_path, origin, &path));
+                        }
+
+                        origins.insert(key_path, path.clone());
+                        merged_entries.insert(key, value);
+                    }
+                }
+                (Some(_), _) => {
+                    return Err(conflict_error(&section, &origins[&section], &path));
+                }
+            }
+        }
+    }
 
+    Ok(merged)
+}
+
+/// Replaces `${VAR}` references in a string with values of environment
+/// variables, and `${file:PATH}` references with the contents of the file at
+/// PATH (without trailing newlines), e.g. for reading Docker secrets. PATH may
+/// itself contain `${VAR}` references. `$${` is written out as a literal `${`.
+fn substitute_str(s: &str) -> Result<String> {
+    let mut result = String::new();
+    let mut rest = s;
+
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            result.push_str(&rest[..start - 1]);
+            result.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+
+        result.push_str(&rest[..start]);
+
+        let after = &rest[start + 2..];
+        let end = closing_brace(after).ok_or_else(|| eyre!("Missing closing brace in '{s}'"))?;
+        let reference = &after[..end];
+
+        let value = match reference.strip_prefix("file:") {
+            Some(path) => {
+                let path = substitute_str(path)?;
+                let contents = std::fs::read_to_string(&path)
+                    .wrap_err_with(|| format!("Failed to read secret from {path}"))?;
+
+                contents.trim_end_matches(['\r', '\n']).to_string()
+            }
+            None => std::env::var(reference)
+                .map_err(|_| eyre!("Environment variable {reference} is not set"))?,
+        };
+
+        result.push_str(&value);
+        rest = &after[end + 1..];
+    }
+
+    result.push_str(rest);
+
+    Ok(result)
+}
+
+/// Finds the brace closing a `${` reference, skipping over nested references.
+fn closing_brace(s: &str) -> Option<usize> {
+    let mut depth = 0;
+
+    for (i, c) in s.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(i),
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Substitutes environment variables and file contents into all strings
+/// within an integration's config, see [substitute_str].
+fn substitute_secrets(value: &mut config::Value, key: &str) -> Result<()> {
+    match &mut value.kind {
+        config::ValueKind::String(s) => {
+            *s = substitute_str(s).wrap_err_with(|| format!("Invalid value for {key}"))?;
+        }
+        config::ValueKind::Table(table) => {
+            for (name, value) in table.iter_mut() {
+                substitute_secrets(value, &format!("{key}.{name}"))?;
+            }
+        }
+        config::ValueKind::Array(array) => {
+            for (i, value) in array.iter_mut().enumerate() {
+                substitute_secrets(value, &format!("{key}[{i}]"))?;
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Path of the main config file along with all files it includes.
+pub fn config_files() -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    load_config_files(&config_path(), &mut files, &mut HashSet::new())?;
+
+    Ok(files.into_iter().map(|(path, _)| path).collect())
+}
+
+pub fn read_config() -> Result<(Config, OpaqueIntegrationsConfigs)> {
     let root = std::env::current_dir().unwrap();
     let sample_path = root.join("Settings.toml.example");
 
-    let path = root.join("Settings.toml");
+    let path = config_path();
+    let is_default_path = CONFIG_PATH.read().unwrap().is_none();
 
-    if !path.exists() && std::env::var("SKIP_SAMPLE_CONFIG").is_err() {
+    if !path.exists() && is_default_path && std::env::var("SKIP_SAMPLE_CONFIG").is_err() {
         error!("Settings.toml not found, generating sample configuration.");
         error!("Set SKIP_SAMPLE_CONFIG environment variable to opt out of this behavior.");
         std::fs::copy(sample_path, path.clone()).unwrap();
     }
 
-    let builder = builder.add_source(config::File::with_name("Settings"));
-
-    let settings = builder.build()?;
+    let mut files = vec![];
+    load_config_files(&path, &mut files, &mut HashSet::new())?;
+    let merged = merge_config_files(files)?;
 
     // TODO: until https://github.com/mehcode/config-rs/issues/531 is fixed
-    let config = {
-        let mut file = File::open(&path)?;
-        let mut contents = Default::default();
-        file.read_to_string(&mut contents)?;
-        toml::from_str(&contents)?
-    };
+    let config: Config = toml::Value::Table(merged.clone())
+        .try_into()
+        .wrap_err("Failed to deserialize merged config")?;
 
-    // let config: Config = serde_path_to_error::deserialize(settings.clone()).wrap_err(
-    //     "Failed to deserialize config, compare your config file to Settings.toml.example!",
-    // )?;
+    let settings = config::Config::builder()
+        .add_source(config::File::from_str(
+            &toml::to_string(&merged)?,
+            config::FileFormat::Toml,
+        ))
+        .build()?;
 
-    let integrations_config = settings
+    let mut integrations_config = settings
         .get::<OpaqueIntegrationsConfigs>("integrations")
         .wrap_err("Expected to find integrations key in config")?;
 
+    for (integration_id, config) in &mut integrations_config {
+        substitute_secrets(config, &format!("integrations.{integration_id}"))?;
+    }
+
     Ok((config, integrations_config))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_error(contents: &str) -> String {
+        parse_config(contents, Path::new("Settings.toml"))
+            .unwrap_err()
+            .to_string()
+    }
+
+    #[test]
+    fn test_parse_error_location() {
+        let contents = r#"
+[scenes.evening]
+name = "Evening"
+
+[scenes.evening.devices.hue.lamp]
+brightness = "bright"
+"#;
+
+        let error = parse_error(contents);
+        assert!(error.starts_with("Settings.toml:5:1:"), "{error}");
+        assert!(
+            error.contains("(at `scenes.evening.devices.hue.lamp`)"),
+            "{error}"
+        );
+
+        let error = parse_error("[core]\nwarmup_time_seconds = \"soon\"\n");
+        assert!(error.starts_with("Settings.toml:2:23:"), "{error}");
+        assert!(error.contains("(at `core.warmup_time_seconds`)"), "{error}");
+    }
+
+    #[test]
+    fn test_syntax_error_location() {
+        let error = parse_error("[core]\nwarmup_time_seconds = = 1\n");
+        assert!(error.starts_with("Settings.toml:2:"), "{error}");
+    }
+
+    #[test]
+    fn test_line_col() {
+        assert_eq!(line_col("abc", 0), (1, 1));
+        assert_eq!(line_col("abc\ndef", 5), (2, 2));
+    }
+
+    fn mk_files(files: &[(&str, &str)]) -> Vec<(PathBuf, toml::Table)> {
+        files
+            .iter()
+            .map(|(path, contents)| (PathBuf::from(path), toml::from_str(contents).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_merge_config_files() {
+        let merged = merge_config_files(mk_files(&[
+            (
+                "Settings.toml",
+                "[core]\nwarmup_time_seconds = 1\n[scenes.a]\nname = \"A\"",
+            ),
+            (
+                "kitchen.toml",
+                "[scenes.b]\nname = \"B\"\n[groups.kitchen]\nname = \"K\"",
+            ),
+        ]))
+        .unwrap();
+
+        let scenes = merged["scenes"].as_table().unwrap();
+        assert!(scenes.contains_key("a"));
+        assert!(scenes.contains_key("b"));
+        assert!(merged["groups"].as_table().unwrap().contains_key("kitchen"));
+    }
+
+    #[test]
+    fn test_merge_conflict() {
+        let error = merge_config_files(mk_files(&[
+            ("Settings.toml", "[scenes.a]\nname = \"A\""),
+            ("kitchen.toml", "[scenes.b]\nname = \"B\""),
+            ("hallway.toml", "[scenes.a]\nname = \"Other A\""),
+        ]))
+        .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "`scenes.a` is defined in both Settings.toml and hallway.toml"
+        );
+    }
+
+    #[test]
+    fn test_include_directory() {
+        let dir = std::env::temp_dir().join(format!("homectl-config-test-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("conf.d")).unwrap();
+        std::fs::write(dir.join("Settings.toml"), "include = [\"conf.d\"]\n").unwrap();
+        std::fs::write(dir.join("conf.d/b.toml"), "[groups.b]\nname = \"B\"\n").unwrap();
+        std::fs::write(dir.join("conf.d/a.toml"), "[groups.a]\nname = \"A\"\n").unwrap();
+        std::fs::write(dir.join("conf.d/notes.txt"), "not a config file").unwrap();
+
+        let mut files = vec![];
+        let result = load_config_files(&dir.join("Settings.toml"), &mut files, &mut HashSet::new());
+        std::fs::remove_dir_all(&dir).unwrap();
+        result.unwrap();
+
+        let names: Vec<_> = files
+            .iter()
+            .map(|(path, _)| path.file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(names, vec!["Settings.toml", "a.toml", "b.toml"]);
+        assert!(!files[0].1.contains_key("include"));
+    }
+
+    #[test]
+    fn test_substitute_env() {
+        std::env::set_var("HOMECTL_TEST_MQTT_USER", "homectl");
+
+        assert_eq!(
+            substitute_str("${HOMECTL_TEST_MQTT_USER}").unwrap(),
+            "homectl"
+        );
+        assert_eq!(
+            substitute_str("user=${HOMECTL_TEST_MQTT_USER};").unwrap(),
+            "user=homectl;"
+        );
+        assert_eq!(substitute_str("plain $value").unwrap(), "plain $value");
+        assert_eq!(
+            substitute_str("${HOMECTL_TEST_UNSET}")
+                .unwrap_err()
+                .to_string(),
+            "Environment variable HOMECTL_TEST_UNSET is not set"
+        );
+        assert!(substitute_str("${HOMECTL_TEST_MQTT_USER").is_err());
+    }
+
+    #[test]
+    fn test_substitute_file() {
+        let path = std::env::temp_dir().join(format!("homectl-secret-{}", std::process::id()));
+        std::fs::write(&path, "hunter2\n").unwrap();
+
+        let mut value = config::Value::new(
+            None,
+            config::ValueKind::String(format!("${{file:{}}}", path.display())),
+        );
+        let result = substitute_secrets(&mut value, "integrations.mqtt.password");
+
+        std::env::set_var("HOMECTL_TEST_SECRET_DIR", path.parent().unwrap());
+        let name = path.file_name().unwrap().to_str().unwrap();
+        let nested = substitute_str(&format!("pw=${{file:${{HOMECTL_TEST_SECRET_DIR}}/{name}}}"));
+
+        std::fs::remove_file(&path).unwrap();
+        result.unwrap();
+
+        assert_eq!(value.into_string().unwrap(), "hunter2");
+        assert_eq!(nested.unwrap(), "pw=hunter2");
+        assert!(substitute_str("${file:/nonexistent/homectl-secret}").is_err());
+    }
+
+    #[test]
+    fn test_substitute_literal() {
+        std::env::set_var("HOMECTL_TEST_FILE_PREFIX", "file:/etc/passwd");
+
+        // Only explicit `${file:...}` references read files
+        assert_eq!(
+            substitute_str("file:/etc/passwd").unwrap(),
+            "file:/etc/passwd"
+        );
+        assert_eq!(
+            substitute_str("${HOMECTL_TEST_FILE_PREFIX}").unwrap(),
+            "file:/etc/passwd"
+        );
+
+        assert_eq!(substitute_str("$${HOME}").unwrap(), "${HOME}");
+        assert_eq!(substitute_str("a$${b}c").unwrap(), "a${b}c");
+        assert_eq!(
+            substitute_str("$${file:/etc/passwd}").unwrap(),
+            "${file:/etc/passwd}"
+        );
+    }
+
+    #[test]
+    fn test_parse_listen_address() {
+        let tcp = |ip: &str, port| ListenAddress::Tcp {
+            ip: ip.parse().unwrap(),
+            port,
+        };
+
+        assert_eq!(
+            "127.0.0.1".parse::<ListenAddress>().unwrap(),
+            tcp("127.0.0.1", None)
+        );
+        assert_eq!("::1".parse::<ListenAddress>().unwrap(), tcp("::1", None));
+        assert_eq!("[::]".parse::<ListenAddress>().unwrap(), tcp("::", None));
+        assert_eq!(
+            "[::1]:8080".parse::<ListenAddress>().unwrap(),
+            tcp("::1", Some(8080))
+        );
+        assert_eq!(
+            "0.0.0.0:80".parse::<ListenAddress>().unwrap(),
+            tcp("0.0.0.0", Some(80))
+        );
+        assert_eq!(
+            "unix:/run/homectl.sock".parse::<ListenAddress>().unwrap(),
+            ListenAddress::Unix(PathBuf::from("/run/homectl.sock"))
+        );
+        assert!("localhost".parse::<ListenAddress>().is_err());
+    }
+}
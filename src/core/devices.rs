@@ -1,5 +1,8 @@
-use crate::db::actions::{db_get_devices, db_update_device};
-use crate::types::integration::IntegrationId;
+use crate::db::actions::{
+    db_delete_device, db_delete_device_history, db_get_devices, db_update_device,
+};
+use crate::db::spawn_device_db_write;
+use crate::types::integration::{IntegrationId, IntegrationsConfig};
 use crate::utils::cli::Cli;
 
 use super::expr::EvalContext;
@@ -12,9 +15,11 @@ use crate::types::{
     event::{Event, TxEventChannel},
     scene::{ActivateSceneDescriptor, SceneId},
 };
+use chrono::{DateTime, Utc};
 use color_eyre::Result;
 use ordered_float::OrderedFloat;
-use std::collections::{BTreeMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::Duration;
 
 #[derive(Clone)]
 pub struct Devices {
@@ -22,6 +27,8 @@ pub struct Devices {
     state: DevicesState,
     keys_by_name: BTreeMap<(IntegrationId, String), DeviceKey>,
     cli: Cli,
+    offline_timeouts: HashMap<IntegrationId, Duration>,
+    started_at: DateTime<Utc>,
 }
 
 impl Devices {
@@ -31,9 +38,22 @@ impl Devices {
             state: Default::default(),
             keys_by_name: Default::default(),
             cli: cli.clone(),
+            offline_timeouts: Default::default(),
+            started_at: Utc::now(),
         }
     }
 
+    /// Reads `offline_timeout_seconds` of each integration from config.
+    pub fn set_offline_timeouts(&mut self, integrations_config: &IntegrationsConfig) {
+        self.offline_timeouts = integrations_config
+            .iter()
+            .filter_map(|(id, integration_config)| {
+                let timeout = integration_config.offline_timeout_seconds?;
+                Some((id.clone(), Duration::from_secs(timeout)))
+            })
+            .collect();
+    }
+
     pub fn get_state(&self) -> &DevicesState {
         &self.state
     }
@@ -176,6 +196,12 @@ impl Devices {
     ) -> Result<()> {
         trace!("handle_external_state_update {incoming:?}");
 
+        let now = Utc::now();
+        let mut incoming = incoming.clone();
+        incoming.last_seen = Some(now);
+        incoming.available = true;
+        let incoming = &incoming;
+
         let device_key = incoming.get_device_key();
 
         self.keys_by_name.insert(
@@ -205,9 +231,61 @@ impl Devices {
             }
         }
 
+        self.mark_seen(&device_key, now);
+
         Ok(())
     }
 
+    /// Updates the last seen timestamp of a device, and marks it as available
+    /// again if it was previously offline.
+    fn mark_seen(&mut self, device_key: &DeviceKey, now: DateTime<Utc>) {
+        let Some(device) = self.state.0.get_mut(device_key) else {
+            return;
+        };
+
+        device.last_seen = Some(now);
+
+        if !device.available {
+            let mut device = device.clone();
+            device.available = true;
+
+            info!("Device {device} is available again");
+            self.set_state(&device, true, true);
+        }
+    }
+
+    /// Marks devices as unavailable if their integration has not reported
+    /// state for them within the integration's offline timeout.
+    pub fn check_availability(&mut self) {
+        let now = Utc::now();
+
+        let stale_devices: Vec<Device> = self
+            .state
+            .0
+            .values()
+            .filter(|device| device.available)
+            .filter(|device| {
+                let Some(timeout) = self.offline_timeouts.get(&device.integration_id) else {
+                    return false;
+                };
+
+                // Devices that haven't been seen since startup are given the
+                // same grace period as any other device
+                let last_seen = device.last_seen.unwrap_or(self.started_at);
+
+                (now - last_seen).to_std().unwrap_or_default() > *timeout
+            })
+            .cloned()
+            .collect();
+
+        for mut device in stale_devices {
+            device.available = false;
+
+            warn!("Device {device} has not been seen for a while, marking as unavailable");
+            self.set_state(&device, true, true);
+        }
+    }
+
     /// Sets internal (and possibly external) state for given device
     pub fn set_state(&mut self, device: &Device, skip_external_update: bool, skip_db_update: bool) {
         let device_key = device.get_device_key();
@@ -249,7 +327,7 @@ impl Devices {
 
         if !skip_db_update {
             if !self.cli.dry_run {
-                tokio::spawn(async move {
+                spawn_device_db_write(&device.get_device_key(), async move {
                     db_update_device(&device).await.ok();
                 });
             } else {
@@ -284,6 +362,66 @@ impl Devices {
         Ok(())
     }
 
+    /// Forgets a device, e.g. after it has been unpaired from its
+    /// integration, along with its recorded history. Returns the removed
+    /// device, or None if it wasn't known.
+    pub fn remove_device(&mut self, device_key: &DeviceKey) -> Option<Device> {
+        let device = self.state.0.remove(device_key)?;
+        self.keys_by_name.retain(|_, key| key != device_key);
+
+        if !self.cli.dry_run {
+            let device_key = device_key.clone();
+            spawn_device_db_write(&device_key.clone(), async move {
+                if let Err(e) = db_delete_device(&device_key).await {
+                    warn!("Failed to delete device {device_key}: {e}");
+                }
+                if let Err(e) = db_delete_device_history(&device_key).await {
+                    warn!("Failed to delete history of device {device_key}: {e}");
+                }
+            });
+        } else {
+            debug!("(dry run) would delete device: {device}");
+        }
+
+        Some(device)
+    }
+
+    /// Gives a device a new name. Returns the previous name, or None if the
+    /// device wasn't known or already had the given name.
+    pub fn rename_device(&mut self, device_key: &DeviceKey, name: &str) -> Option<String> {
+        let device = self.state.0.get_mut(device_key)?;
+
+        if device.name == name {
+            return None;
+        }
+
+        let old_name = std::mem::replace(&mut device.name, name.to_string());
+        let device = device.clone();
+
+        self.keys_by_name
+            .retain(|(integration_id, device_name), key| {
+                !(key == device_key
+                    && integration_id == &device_key.integration_id
+                    && device_name == &old_name)
+            });
+        self.keys_by_name.insert(
+            (device.integration_id.clone(), device.name.clone()),
+            device_key.clone(),
+        );
+
+        if !self.cli.dry_run {
+            spawn_device_db_write(device_key, async move {
+                if let Err(e) = db_update_device(&device).await {
+                    warn!("Failed to store renamed device {device}: {e}");
+                }
+            });
+        } else {
+            debug!("(dry run) would store device: {device}");
+        }
+
+        Some(old_name)
+    }
+
     pub fn get_device(&self, device_key: &DeviceKey) -> Option<&Device> {
         self.state.0.get(device_key)
     }
@@ -419,3 +557,97 @@ impl Devices {
         self.state.0.get(&device_key)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{
+        device::{DeviceId, SensorDevice},
+        event::{mk_event_channel, RxEventChannel},
+    };
+    use clap::Parser;
+
+    fn mk_devices() -> (Devices, RxEventChannel) {
+        let (event_tx, event_rx) = mk_event_channel();
+        let cli = Cli::parse_from(["homectl-server", "--dry-run"]);
+
+        (Devices::new(event_tx, &cli), event_rx)
+    }
+
+    fn mk_sensor(name: &str) -> Device {
+        Device::new(
+            IntegrationId::from("test".to_string()),
+            DeviceId::new("sensor"),
+            name.to_string(),
+            DeviceData::Sensor(SensorDevice::Boolean { value: true }),
+            None,
+        )
+    }
+
+    fn name_ref(name: &str) -> DeviceRef {
+        DeviceRef::new_with_name(IntegrationId::from("test".to_string()), name.to_string())
+    }
+
+    #[tokio::test]
+    async fn test_rename_and_remove_device() {
+        let (mut devices, _event_rx) = mk_devices();
+        let device = mk_sensor("Old name");
+        let device_key = device.get_device_key();
+
+        devices
+            .handle_external_state_update(&device, &Scenes::default())
+            .await
+            .unwrap();
+        assert!(devices.get_device_by_ref(&name_ref("Old name")).is_some());
+
+        let old_name = devices.rename_device(&device_key, "New name");
+        assert_eq!(old_name.as_deref(), Some("Old name"));
+        assert_eq!(devices.rename_device(&device_key, "New name"), None);
+        assert!(devices.get_device_by_ref(&name_ref("Old name")).is_none());
+        assert_eq!(
+            devices
+                .get_device_by_ref(&name_ref("New name"))
+                .map(|d| d.name.as_str()),
+            Some("New name")
+        );
+
+        assert!(devices.remove_device(&device_key).is_some());
+        assert!(devices.remove_device(&device_key).is_none());
+        assert!(devices.get_device(&device_key).is_none());
+        assert!(devices.get_device_by_ref(&name_ref("New name")).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_availability() {
+        let (mut devices, _event_rx) = mk_devices();
+        let device = mk_sensor("Sensor");
+        let device_key = device.get_device_key();
+        devices
+            .offline_timeouts
+            .insert(device.integration_id.clone(), Duration::from_secs(60));
+
+        devices
+            .handle_external_state_update(&device, &Scenes::default())
+            .await
+            .unwrap();
+        devices.check_availability();
+        assert!(devices.get_device(&device_key).unwrap().available);
+
+        // Pretend the device was last seen before the offline timeout
+        devices.state.0.get_mut(&device_key).unwrap().last_seen =
+            Some(Utc::now() - chrono::Duration::seconds(61));
+        devices.check_availability();
+        assert!(!devices.get_device(&device_key).unwrap().available);
+
+        // The next report makes the device available again
+        devices
+            .handle_external_state_update(&device, &Scenes::default())
+            .await
+            .unwrap();
+        let device = devices.get_device(&device_key).unwrap();
+        assert!(device.available);
+        assert!(device
+            .last_seen
+            .is_some_and(|t| Utc::now() - t < chrono::Duration::seconds(5)));
+    }
+}
@@ -8,41 +8,82 @@ use crate::types::{
     dim::DimDescriptor,
     event::*,
     integration::CustomActionDescriptor,
-    rule::ForceTriggerRoutineDescriptor,
+    rule::{ForceTriggerRoutineDescriptor, RoutineDescriptor},
     scene::{ActivateSceneDescriptor, CycleScenesDescriptor},
     ui::UiActionDescriptor,
 };
 
 use crate::db::actions::{db_delete_scene, db_edit_scene, db_store_scene};
 
-use super::{expr::eval_action_expr, state::AppState};
+use super::{expr::eval_action_expr, reload::reload_config, state::AppState};
 
 pub async fn handle_event(state: &mut AppState, event: &Event) -> Result<()> {
     match event {
         Event::ExternalStateUpdate { device } => {
+            let device_key = device.get_device_key();
+            let renamed = state
+                .devices
+                .get_device(&device_key)
+                .is_some_and(|current| current.name != device.name);
+
+            // Integrations may rename devices simply by reporting them with a
+            // new name
+            if renamed {
+                rename_device(state, &device_key, &device.name).await;
+            }
+
             state
                 .devices
                 .handle_external_state_update(device, &state.scenes)
                 .await?;
         }
         Event::StartupCompleted => {
-            state.groups.force_invalidate(&state.devices);
-
-            state
-                .expr
-                .invalidate(state.devices.get_state(), &state.groups, &state.scenes);
-
-            state
-                .scenes
-                .force_invalidate(&state.devices, &state.groups, state.expr.get_context());
+            state.warming_up = false;
 
-            state
-                .expr
-                .invalidate(state.devices.get_state(), &state.groups, &state.scenes);
+            state.force_invalidate();
 
             let device_count = state.devices.get_state().0.len();
             info!("Startup completed, discovered {device_count} devices");
         }
+        Event::DeviceRemoved { device_key } => {
+            let Some(device) = state.devices.remove_device(device_key) else {
+                debug!("Ignoring removal of unknown device {device_key}");
+                return Ok(());
+            };
+            info!("Removed device {device}");
+
+            state.history.forget(device_key);
+            state.scenes.remove_device_overrides(device_key).await?;
+            state.force_invalidate();
+            state.send_state_ws(None).await;
+        }
+        Event::DeviceRenamed { device_key, name } => {
+            rename_device(state, device_key, name).await;
+        }
+        Event::Tick => {
+            state.integrations.supervise();
+            state.history.write_pending();
+
+            if state.warming_up {
+                return Ok(());
+            }
+
+            state.devices.check_availability();
+            state.expr.refresh_solar();
+
+            state
+                .rules
+                .handle_tick(&state.devices, &state.groups, &state.expr);
+        }
+        Event::IntegrationRestarted {
+            integration_id,
+            restart_id,
+            error,
+        } => {
+            state
+                .integrations
+                .finish_restart(integration_id, *restart_id, error.clone());
+        }
         Event::InternalStateUpdate {
             old_state,
             new_state,
@@ -56,6 +97,8 @@ pub async fn handle_event(state: &mut AppState, event: &Event) -> Result<()> {
             let invalidated_device = new;
             debug!("invalidating {name}", name = invalidated_device.name);
 
+            state.history.record(invalidated_device);
+
             let _groups_invalidated = state
                 .groups
                 .invalidate(old_state, new_state, &state.devices);
@@ -123,32 +166,25 @@ pub async fn handle_event(state: &mut AppState, event: &Event) -> Result<()> {
                 .set_integration_device_state(device)
                 .await?;
         }
+        Event::ReloadConfig => {
+            if let Err(e) = reload_config(state).await {
+                error!("Rejected config reload, keeping previous config:\n    {e:?}");
+            }
+        }
         Event::WsBroadcastState => {
             state.send_state_ws(None).await;
         }
         Event::DbStoreScene { scene_id, config } => {
             db_store_scene(scene_id, config).await?;
-            state.scenes.refresh_db_scenes().await;
-            state
-                .scenes
-                .force_invalidate(&state.devices, &state.groups, state.expr.get_context());
-            state.send_state_ws(None).await;
+            state.refresh_db_scenes().await;
         }
         Event::DbDeleteScene { scene_id } => {
             db_delete_scene(scene_id).await?;
-            state.scenes.refresh_db_scenes().await;
-            state
-                .scenes
-                .force_invalidate(&state.devices, &state.groups, state.expr.get_context());
-            state.send_state_ws(None).await;
+            state.refresh_db_scenes().await;
         }
         Event::DbEditScene { scene_id, name } => {
             db_edit_scene(scene_id, name).await?;
-            state.scenes.refresh_db_scenes().await;
-            state
-                .scenes
-                .force_invalidate(&state.devices, &state.groups, state.expr.get_context());
-            state.send_state_ws(None).await;
+            state.refresh_db_scenes().await;
         }
         Event::Action(Action::ActivateScene(ActivateSceneDescriptor {
             scene_id,
@@ -212,6 +248,24 @@ pub async fn handle_event(state: &mut AppState, event: &Event) -> Result<()> {
         })) => {
             state.rules.force_trigger_routine(routine_id)?;
         }
+        Event::Action(Action::EnableRoutine(RoutineDescriptor { routine_id })) => {
+            state
+                .rules
+                .set_enabled(routine_id, true, &state.devices, &state.groups, &state.expr)
+                .await?;
+        }
+        Event::Action(Action::DisableRoutine(RoutineDescriptor { routine_id })) => {
+            state
+                .rules
+                .set_enabled(
+                    routine_id,
+                    false,
+                    &state.devices,
+                    &state.groups,
+                    &state.expr,
+                )
+                .await?;
+        }
         Event::Action(Action::SetDeviceState(device)) => {
             state.event_tx.send(Event::SetInternalState {
                 device: device.clone(),
@@ -259,3 +313,14 @@ pub async fn handle_event(state: &mut AppState, event: &Event) -> Result<()> {
 
     Ok(())
 }
+
+async fn rename_device(state: &mut AppState, device_key: &DeviceKey, name: &str) {
+    let Some(old_name) = state.devices.rename_device(device_key, name) else {
+        debug!("Ignoring rename of unknown device {device_key}");
+        return;
+    };
+    info!("Renamed device {device_key} from {old_name} to {name}");
+
+    state.force_invalidate();
+    state.send_state_ws(None).await;
+}
@@ -1,8 +1,10 @@
 use std::{
     collections::{HashMap, HashSet},
     sync::{Arc, RwLock},
+    time::Duration,
 };
 
+use chrono::{DateTime, Local};
 use evalexpr::*;
 use eyre::{OptionExt, Result};
 use jsonptr::Assign;
@@ -17,6 +19,10 @@ use crate::types::{
     rule::{ForceTriggerRoutineDescriptor, RoutineId},
     scene::{ActivateSceneDescriptor, FlattenedScenesConfig, SceneDeviceConfig, SceneId},
 };
+use crate::utils::{
+    clock::Clock,
+    solar::{get_location, solar_elevation, solar_event_time, Location, SolarEvent},
+};
 
 use super::{
     groups::{flattened_groups_to_eval_context_values, Groups},
@@ -25,6 +31,10 @@ use super::{
 
 pub type EvalContext = HashMapContext;
 
+/// How often `solar.*` values are recomputed when nothing else causes the
+/// eval context to be recomputed.
+const SOLAR_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
 fn value_kv_pairs_deep(
     value: &serde_json::Value,
     prefix: &str,
@@ -82,7 +92,7 @@ fn evalexpr_value_to_serde(value: &Value) -> Result<serde_json::Value> {
     }
 }
 
-fn name_to_evalexpr(device_name: &str) -> String {
+pub fn name_to_evalexpr(device_name: &str) -> String {
     device_name.to_lowercase().replace(' ', "_")
 }
 
@@ -90,6 +100,7 @@ pub fn state_to_eval_context(
     devices: &DevicesState,
     flattened_scenes: &FlattenedScenesConfig,
     flattened_groups: &FlattenedGroupsConfig,
+    now: DateTime<Local>,
 ) -> Result<HashMapContext> {
     let mut context = HashMapContext::new();
     context.set_type_safety_checks_disabled(true)?;
@@ -113,12 +124,18 @@ pub fn state_to_eval_context(
         );
 
         set_values(&prefix, &device.get_value())?;
+        set_values(
+            &format!("{prefix}.available"),
+            &serde_json::Value::Bool(device.available),
+        )?;
         if let Some(raw_value) = device.get_raw_value() {
             let raw_prefix = format!("{prefix}.raw");
             set_values(&raw_prefix, raw_value)?;
         }
     }
 
+    set_solar_values(&mut context, now)?;
+
     for (scene_id, scene) in &flattened_scenes.0 {
         let prefix = format!("scenes.{}", name_to_evalexpr(&scene_id.to_string()));
 
@@ -167,6 +184,40 @@ pub fn state_to_eval_context(
     Ok(context)
 }
 
+/// Sets `solar.*` values if a location has been configured.
+fn set_solar_values(context: &mut HashMapContext, now: DateTime<Local>) -> Result<()> {
+    let Some(location) = get_location() else {
+        return Ok(());
+    };
+
+    for (key, value) in value_kv_pairs_deep(&solar_eval_context_value(&location, now), "solar") {
+        context.set_value(key, serde_value_to_evalexpr(&value)?)?;
+    }
+
+    Ok(())
+}
+
+/// Current position of the sun and today's solar event times (as "HH:MM"
+/// strings in local time, or null if the event doesn't occur today).
+fn solar_eval_context_value(location: &Location, now: DateTime<Local>) -> serde_json::Value {
+    let date = now.date_naive();
+    let elevation = solar_elevation(location, &now.to_utc());
+
+    let event_time = |event: SolarEvent| {
+        solar_event_time(location, date, event)
+            .map(|time| time.with_timezone(&Local).format("%H:%M").to_string())
+    };
+
+    serde_json::json!({
+        "elevation": elevation,
+        "is_day": elevation > 0.0,
+        "dawn": event_time(SolarEvent::Dawn),
+        "sunrise": event_time(SolarEvent::Sunrise),
+        "sunset": event_time(SolarEvent::Sunset),
+        "dusk": event_time(SolarEvent::Dusk),
+    })
+}
+
 fn tuple_value_to_vec_string(value: &Value) -> EvalexprResult<Vec<String>> {
     let tuple = value.as_tuple()?;
     let vec: Vec<String> = tuple
@@ -413,12 +464,16 @@ pub fn debug_print_context(context: &HashMapContext) {
 #[derive(Clone)]
 pub struct Expr {
     context: HashMapContext,
+    solar_refreshed_at: Option<DateTime<Local>>,
+    clock: Arc<dyn Clock>,
 }
 
 impl Expr {
-    pub fn new() -> Self {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
         Expr {
             context: HashMapContext::new(),
+            solar_refreshed_at: None,
+            clock,
         }
     }
 
@@ -439,13 +494,39 @@ impl Expr {
         let flattened_scenes = scenes.get_flattened_scenes();
         let flattened_groups = groups.get_flattened_groups();
 
-        state_to_eval_context(devices_state, flattened_scenes, flattened_groups)
-            .expect("Failed to create eval context")
+        state_to_eval_context(
+            devices_state,
+            flattened_scenes,
+            flattened_groups,
+            self.clock.now(),
+        )
+        .expect("Failed to create eval context")
     }
 
     pub fn invalidate(&mut self, devices_state: &DevicesState, groups: &Groups, scenes: &Scenes) {
         let context = self.recompute(devices_state, groups, scenes);
         self.context = context;
+        self.solar_refreshed_at = Some(self.clock.now());
+    }
+
+    /// Recomputes `solar.*` values if they haven't been for a while, so that
+    /// they follow the sun even when no device state changes. Called on every
+    /// tick.
+    pub fn refresh_solar(&mut self) {
+        let now = self.clock.now();
+
+        if self
+            .solar_refreshed_at
+            .is_some_and(|t| (now - t).to_std().is_ok_and(|d| d < SOLAR_REFRESH_INTERVAL))
+        {
+            return;
+        }
+
+        self.solar_refreshed_at = Some(now);
+
+        if let Err(e) = set_solar_values(&mut self.context, now) {
+            warn!("Failed to refresh solar values: {e}");
+        }
     }
 }
 
@@ -504,3 +585,31 @@ pub fn get_expr_scene_deps(expr: &Node) -> HashSet<SceneId> {
         })
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    const HELSINKI: Location = Location {
+        latitude: 60.17,
+        longitude: 24.94,
+    };
+
+    #[test]
+    fn test_solar_values_follow_clock() {
+        let at = |month, day, hour| {
+            Utc.with_ymd_and_hms(2024, month, day, hour, 0, 0)
+                .unwrap()
+                .with_timezone(&Local)
+        };
+
+        let noon = solar_eval_context_value(&HELSINKI, at(6, 21, 10));
+        let midnight = solar_eval_context_value(&HELSINKI, at(12, 21, 22));
+
+        assert_eq!(noon["is_day"], true);
+        assert_eq!(midnight["is_day"], false);
+        assert!(noon["elevation"].as_f64().unwrap() > 50.0);
+        assert!(midnight["elevation"].as_f64().unwrap() < -30.0);
+    }
+}
@@ -157,6 +157,10 @@ impl Groups {
         }
     }
 
+    pub fn get_config(&self) -> &GroupsConfig {
+        &self.config
+    }
+
     /// Returns a flattened version of the groups config, with any contained
     /// groups expanded.
     pub fn get_flattened_groups(&self) -> &FlattenedGroupsConfig {
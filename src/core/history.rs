@@ -0,0 +1,342 @@
+use std::{collections::HashMap, time::Duration};
+
+use chrono::{DateTime, Utc};
+use tokio::time::Instant;
+
+use crate::{
+    db::{
+        actions::{db_insert_device_history, db_prune_device_history},
+        spawn_device_db_write,
+    },
+    types::device::{Device, DeviceHistoryEntry, DeviceKey},
+    utils::cli::Cli,
+};
+
+use super::config::DeviceHistoryConfig;
+
+static PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Entries recorded before the returned time are deleted when pruning.
+fn prune_cutoff(now: DateTime<Utc>, retention_days: u64) -> DateTime<Utc> {
+    now - chrono::Duration::days(retention_days as i64)
+}
+
+/// Downsamples history to at most one entry per `interval_seconds`, keeping
+/// the latest entry within each interval. Intervals are aligned to the Unix
+/// epoch so that overlapping queries return the same entries.
+pub fn downsample(
+    entries: Vec<DeviceHistoryEntry>,
+    interval_seconds: u64,
+) -> Vec<DeviceHistoryEntry> {
+    if interval_seconds == 0 {
+        return entries;
+    }
+
+    let bucket = |entry: &DeviceHistoryEntry| {
+        entry
+            .recorded_at
+            .timestamp()
+            .div_euclid(interval_seconds as i64)
+    };
+
+    let mut result: Vec<DeviceHistoryEntry> = vec![];
+
+    for entry in entries {
+        match result.last_mut() {
+            Some(last) if bucket(last) == bucket(&entry) => *last = entry,
+            _ => result.push(entry),
+        }
+    }
+
+    result
+}
+
+#[derive(Clone)]
+pub struct DeviceHistory {
+    config: Option<DeviceHistoryConfig>,
+    last_recorded: HashMap<DeviceKey, Instant>,
+
+    /// Latest state of devices that changed too soon after their previous
+    /// entry, written once `min_interval_seconds` has passed.
+    pending: HashMap<DeviceKey, Device>,
+    cli: Cli,
+}
+
+impl DeviceHistory {
+    pub fn new(config: Option<DeviceHistoryConfig>, cli: &Cli) -> Self {
+        DeviceHistory {
+            config,
+            last_recorded: Default::default(),
+            pending: Default::default(),
+            cli: cli.clone(),
+        }
+    }
+
+    /// Interval used for downsampling queried history, unless the query
+    /// specifies one.
+    pub fn downsample_interval_seconds(&self) -> Option<u64> {
+        self.config.as_ref()?.downsample_interval_seconds
+    }
+
+    /// Records current state of given device, unless history is disabled.
+    /// If the device's previous entry is too recent, the state is recorded
+    /// by [DeviceHistory::write_pending] once enough time has passed instead.
+    pub fn record(&mut self, device: &Device) {
+        if let Some(device) = self.record_at(device, Instant::now()) {
+            self.write(device);
+        }
+    }
+
+    /// Writes pending states of devices whose `min_interval_seconds` has
+    /// passed since their previous entry. Called on every tick.
+    pub fn write_pending(&mut self) {
+        for device in self.take_due_at(Instant::now()) {
+            self.write(device);
+        }
+    }
+
+    /// Writes all pending states regardless of when the previous entries
+    /// were recorded, used when shutting down.
+    pub fn flush(&mut self) {
+        let pending = std::mem::take(&mut self.pending);
+
+        for device in pending.into_values() {
+            self.write(device);
+        }
+    }
+
+    /// Drops the pending state of a removed device, so that it isn't written
+    /// after the device's history has been deleted.
+    pub fn forget(&mut self, device_key: &DeviceKey) {
+        self.pending.remove(device_key);
+        self.last_recorded.remove(device_key);
+    }
+
+    /// Returns the device if it should be written right away.
+    fn record_at(&mut self, device: &Device, now: Instant) -> Option<Device> {
+        let config = self.config.as_ref()?;
+        let device_key = device.get_device_key();
+
+        if let (Some(min_interval), Some(last_recorded)) = (
+            config.min_interval_seconds,
+            self.last_recorded.get(&device_key),
+        ) {
+            if now.duration_since(*last_recorded) < Duration::from_secs(min_interval) {
+                self.pending.insert(device_key, device.clone());
+                return None;
+            }
+        }
+
+        self.pending.remove(&device_key);
+        self.last_recorded.insert(device_key, now);
+
+        Some(device.clone())
+    }
+
+    /// Removes and returns pending states that are due to be written.
+    fn take_due_at(&mut self, now: Instant) -> Vec<Device> {
+        let min_interval = Duration::from_secs(
+            self.config
+                .as_ref()
+                .and_then(|c| c.min_interval_seconds)
+                .unwrap_or_default(),
+        );
+
+        let due: Vec<DeviceKey> = self
+            .pending
+            .keys()
+            .filter(|device_key| {
+                self.last_recorded
+                    .get(*device_key)
+                    .is_none_or(|last_recorded| now.duration_since(*last_recorded) >= min_interval)
+            })
+            .cloned()
+            .collect();
+
+        due.into_iter()
+            .filter_map(|device_key| {
+                let device = self.pending.remove(&device_key)?;
+                self.last_recorded.insert(device_key, now);
+                Some(device)
+            })
+            .collect()
+    }
+
+    fn write(&self, device: Device) {
+        if self.cli.dry_run {
+            debug!("(dry run) would record device history: {device}");
+            return;
+        }
+
+        spawn_device_db_write(&device.get_device_key(), async move {
+            if let Err(e) = db_insert_device_history(&device).await {
+                warn!("Failed to record device history for {device}: {e}");
+            }
+        });
+    }
+
+    /// Periodically deletes history entries older than the configured
+    /// retention period.
+    pub fn start_pruning(&self) {
+        let Some(retention_days) = self.config.as_ref().and_then(|c| c.retention_days) else {
+            return;
+        };
+
+        if self.cli.dry_run {
+            return;
+        }
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+
+            loop {
+                interval.tick().await;
+
+                let older_than = prune_cutoff(chrono::Utc::now(), retention_days);
+
+                match db_prune_device_history(older_than).await {
+                    Ok(count) => debug!("Pruned {count} device history entries"),
+                    Err(e) => warn!("Failed to prune device history: {e}"),
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{
+        device::{DeviceData, DeviceId, SensorDevice},
+        integration::IntegrationId,
+    };
+    use clap::Parser;
+
+    fn mk_history(min_interval_seconds: Option<u64>) -> DeviceHistory {
+        let config = DeviceHistoryConfig {
+            retention_days: Some(30),
+            min_interval_seconds,
+            downsample_interval_seconds: None,
+        };
+        let cli = Cli::parse_from(["homectl-server", "--dry-run"]);
+
+        DeviceHistory::new(Some(config), &cli)
+    }
+
+    fn mk_sensor(value: bool) -> Device {
+        Device::new(
+            IntegrationId::from("test".to_string()),
+            DeviceId::new("sensor"),
+            "Sensor".to_string(),
+            DeviceData::Sensor(SensorDevice::Boolean { value }),
+            None,
+        )
+    }
+
+    fn value(device: &Device) -> DeviceData {
+        device.data.clone()
+    }
+
+    #[test]
+    fn test_record_without_min_interval() {
+        let mut history = mk_history(None);
+        let now = Instant::now();
+
+        assert!(history.record_at(&mk_sensor(true), now).is_some());
+        assert!(history.record_at(&mk_sensor(false), now).is_some());
+        assert!(history.take_due_at(now).is_empty());
+    }
+
+    #[test]
+    fn test_throttled_state_is_written_later() {
+        let mut history = mk_history(Some(10));
+        let now = Instant::now();
+
+        assert!(history.record_at(&mk_sensor(true), now).is_some());
+
+        // Changes within the interval are held back, only the latest one is
+        // kept
+        let soon = now + Duration::from_secs(2);
+        assert!(history.record_at(&mk_sensor(false), soon).is_none());
+        assert!(history.record_at(&mk_sensor(true), soon).is_none());
+        assert!(history.record_at(&mk_sensor(false), soon).is_none());
+        assert!(history.take_due_at(soon).is_empty());
+
+        let later = now + Duration::from_secs(10);
+        let due = history.take_due_at(later);
+        assert_eq!(
+            due.iter().map(value).collect::<Vec<_>>(),
+            vec![value(&mk_sensor(false))]
+        );
+        assert!(history.take_due_at(later).is_empty());
+
+        // The interval starts over from when the pending state was written
+        let after = later + Duration::from_secs(5);
+        assert!(history.record_at(&mk_sensor(true), after).is_none());
+        assert!(history
+            .record_at(&mk_sensor(true), later + Duration::from_secs(10))
+            .is_some());
+        assert!(history
+            .take_due_at(later + Duration::from_secs(20))
+            .is_empty());
+
+        // Pending states of removed devices are dropped
+        let removed_at = later + Duration::from_secs(15);
+        assert!(history.record_at(&mk_sensor(false), removed_at).is_none());
+        history.forget(&mk_sensor(false).get_device_key());
+        assert!(history
+            .take_due_at(removed_at + Duration::from_secs(10))
+            .is_empty());
+    }
+
+    #[test]
+    fn test_flush_writes_pending() {
+        let mut history = mk_history(Some(10));
+        let now = Instant::now();
+
+        history.record_at(&mk_sensor(true), now);
+        history.record_at(&mk_sensor(false), now);
+        history.flush();
+
+        assert!(history.pending.is_empty());
+    }
+
+    #[test]
+    fn test_downsample() {
+        let entry = |time: &str, on| DeviceHistoryEntry {
+            recorded_at: DateTime::parse_from_rfc3339(time)
+                .unwrap()
+                .with_timezone(&Utc),
+            data: value(&mk_sensor(on)),
+            raw: None,
+        };
+        let entries = vec![
+            entry("2026-10-18T12:00:00Z", true),
+            entry("2026-10-18T12:00:30Z", false),
+            entry("2026-10-18T12:01:10Z", true),
+            entry("2026-10-18T12:05:59Z", false),
+        ];
+
+        assert_eq!(downsample(entries.clone(), 0), entries);
+        assert_eq!(
+            downsample(entries.clone(), 60),
+            vec![entries[1].clone(), entries[2].clone(), entries[3].clone()]
+        );
+        assert_eq!(
+            downsample(entries.clone(), 300),
+            vec![entries[2].clone(), entries[3].clone()]
+        );
+    }
+
+    #[test]
+    fn test_prune_cutoff() {
+        let now = DateTime::parse_from_rfc3339("2026-10-18T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let cutoff = DateTime::parse_from_rfc3339("2026-09-18T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        assert_eq!(prune_cutoff(now, 30), cutoff);
+    }
+}
@@ -1,22 +1,126 @@
 use crate::integrations::cron::Cron;
 use crate::integrations::{
-    circadian::Circadian, dummy::Dummy, mqtt::Mqtt, random::Random, timer::Timer,
+    circadian::Circadian, dummy::Dummy, mqtt::Mqtt, process::Process, random::Random, timer::Timer,
 };
 use crate::types::{
     device::Device,
-    event::TxEventChannel,
-    integration::{Integration, IntegrationActionPayload, IntegrationId},
+    event::{Event, EventSource, TxEventChannel},
+    integration::{
+        Integration, IntegrationActionPayload, IntegrationHealth, IntegrationId, IntegrationState,
+        IntegrationStatus, IntegrationsConfig,
+    },
 };
-use crate::utils::cli::Cli;
+use crate::{core::storage::IntegrationStorage, utils::cli::Cli};
+
+use super::{config::OpaqueIntegrationsConfigs, storage::Storage};
+use chrono::{DateTime, Utc};
 use color_eyre::Result;
 use eyre::eyre;
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 use tokio::sync::Mutex;
 
+/// Delay before the first restart of a failed integration. The delay doubles
+/// with each consecutive failure, up to [MAX_RESTART_BACKOFF].
+const MIN_RESTART_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(300);
+
+/// Integrations that have been running for this long are considered stable
+/// again, and the restart backoff is reset.
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+
+/// Time an integration gets to stop, to register and to start. A hanging
+/// integration must not hold up startup, config reloads or restarts
+/// indefinitely.
+const RESTART_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn restart_backoff(consecutive_failures: u32) -> Duration {
+    let exponent = consecutive_failures.saturating_sub(1).min(16);
+
+    MIN_RESTART_BACKOFF
+        .saturating_mul(1 << exponent)
+        .min(MAX_RESTART_BACKOFF)
+}
+
+type IntegrationInstance = Arc<Mutex<Box<dyn Integration>>>;
+
 #[derive(Clone)]
 pub struct LoadedIntegration {
-    integration: Arc<Mutex<Box<dyn Integration>>>,
+    integration: IntegrationInstance,
     module_name: String,
+    config: config::Value,
+    required: bool,
+    state: IntegrationState,
+    last_error: Option<String>,
+    last_error_at: Option<DateTime<Utc>>,
+    restarts: u32,
+    consecutive_failures: u32,
+    running_since: Option<DateTime<Utc>>,
+    next_restart_at: Option<DateTime<Utc>>,
+
+    /// Id of the restart running in the background, if any. See
+    /// [Integrations::restart].
+    pending_restart: Option<u64>,
+}
+
+impl LoadedIntegration {
+    fn set_error(&mut self, error: String) {
+        self.last_error = Some(error);
+        self.last_error_at = Some(Utc::now());
+    }
+
+    fn mark_running(&mut self) {
+        self.state = IntegrationState::Running;
+        self.running_since = Some(Utc::now());
+        self.next_restart_at = None;
+    }
+
+    fn mark_failed(&mut self, error: String) {
+        self.consecutive_failures += 1;
+        let backoff = restart_backoff(self.consecutive_failures);
+
+        self.state = IntegrationState::Failed;
+        self.running_since = None;
+        self.next_restart_at = Some(Utc::now() + backoff);
+        self.set_error(error);
+    }
+
+    fn get_status(&self, integration_id: &IntegrationId) -> IntegrationStatus {
+        IntegrationStatus {
+            id: integration_id.clone(),
+            plugin: self.module_name.clone(),
+            required: self.required,
+            state: self.state,
+            last_error: self.last_error.clone(),
+            last_error_at: self.last_error_at,
+            restarts: self.restarts,
+            next_restart_at: self.next_restart_at,
+        }
+    }
+
+    /// Runs register and start on the integration, and records the result in
+    /// the integration status. Taking longer than `timeout` counts as failing.
+    async fn register_and_start(
+        &mut self,
+        integration_id: &IntegrationId,
+        timeout: Duration,
+    ) -> Result<()> {
+        let result = register_and_start(&self.integration, timeout).await;
+
+        match result {
+            Ok(()) => {
+                self.mark_running();
+                info!("started {} integration {integration_id}", self.module_name);
+                Ok(())
+            }
+            Err(e) => {
+                self.mark_failed(format!("{e:#}"));
+                Err(e.wrap_err(format!(
+                    "Failed to start {} integration {integration_id}",
+                    self.module_name
+                )))
+            }
+        }
+    }
 }
 
 pub type CustomIntegrationsMap = HashMap<IntegrationId, LoadedIntegration>;
@@ -24,68 +128,467 @@ pub type CustomIntegrationsMap = HashMap<IntegrationId, LoadedIntegration>;
 #[derive(Clone)]
 pub struct Integrations {
     custom_integrations: CustomIntegrationsMap,
+
+    /// Ids of loaded integrations in the order they were loaded in.
+    /// Integrations are registered and started in this order, and stopped in
+    /// reverse order.
+    load_order: Vec<IntegrationId>,
     event_tx: TxEventChannel,
+    cli: Cli,
+    storage: Storage,
+
+    /// See [RESTART_TIMEOUT]
+    restart_timeout: Duration,
+
+    /// Id of the most recently started background restart.
+    last_restart_id: u64,
 }
 
 impl Integrations {
-    pub fn new(event_tx: TxEventChannel) -> Self {
+    pub fn new(event_tx: TxEventChannel, cli: &Cli, storage: Storage) -> Self {
         let integrations = Default::default();
 
         Integrations {
             custom_integrations: integrations,
+            load_order: Vec::new(),
             event_tx,
+            cli: cli.clone(),
+            storage,
+            restart_timeout: RESTART_TIMEOUT,
+            last_restart_id: 0,
         }
     }
 
-    pub async fn load_integration(
-        &mut self,
+    fn mk_integration(
+        &self,
         module_name: &str,
         integration_id: &IntegrationId,
         config: &config::Value,
-        cli: &Cli,
-    ) -> Result<()> {
+    ) -> Result<Box<dyn Integration>> {
         info!("loading integration with module_name {module_name}");
 
-        let event_tx = self.event_tx.clone();
-        let integration =
-            load_custom_integration(module_name, integration_id, config, cli, event_tx)?;
+        let event_tx = self
+            .event_tx
+            .with_source(EventSource::Integration(integration_id.clone()));
+
+        let storage = self.storage.for_integration(integration_id);
+
+        load_custom_integration(
+            module_name,
+            integration_id,
+            config,
+            &self.cli,
+            event_tx,
+            storage,
+        )
+    }
 
-        let loaded_integration = LoadedIntegration {
+    fn mk_loaded_integration(
+        &self,
+        module_name: &str,
+        integration_id: &IntegrationId,
+        config: &config::Value,
+        required: bool,
+    ) -> Result<LoadedIntegration> {
+        let integration = self.mk_integration(module_name, integration_id, config)?;
+
+        Ok(LoadedIntegration {
             integration: Arc::new(Mutex::new(integration)),
             module_name: module_name.to_string(),
-        };
+            config: config.clone(),
+            required,
+            state: IntegrationState::Starting,
+            last_error: None,
+            last_error_at: None,
+            restarts: 0,
+            consecutive_failures: 0,
+            running_since: None,
+            next_restart_at: None,
+            pending_restart: None,
+        })
+    }
 
-        self.custom_integrations
+    pub async fn load_integration(
+        &mut self,
+        module_name: &str,
+        integration_id: &IntegrationId,
+        config: &config::Value,
+        required: bool,
+    ) -> Result<()> {
+        let loaded_integration =
+            self.mk_loaded_integration(module_name, integration_id, config, required)?;
+
+        let prev = self
+            .custom_integrations
             .insert(integration_id.clone(), loaded_integration);
 
+        if prev.is_none() {
+            self.load_order.push(integration_id.clone());
+        }
+
         Ok(())
     }
 
-    pub async fn run_register_pass(&self) -> Result<()> {
-        for (integration_id, li) in self.custom_integrations.iter() {
-            let mut integration = li.integration.lock().await;
+    /// Brings loaded integrations in line with a reloaded config. Integrations
+    /// whose plugin and config are unchanged keep running. Removed integrations
+    /// are stopped, and changed integrations are stopped and loaded again.
+    ///
+    /// All new integration instances are constructed before anything is
+    /// stopped, so if any config fails to deserialize, currently running
+    /// integrations are left untouched. Integrations that fail to start are
+    /// marked as failed and restarted later.
+    pub async fn apply_config(
+        &mut self,
+        integrations_config: &IntegrationsConfig,
+        opaque_integrations_configs: &OpaqueIntegrationsConfigs,
+    ) -> Result<()> {
+        // Integrations are substituted with dummy integrations during replay
+        if self.cli.replay.is_some() {
+            return Err(eyre!(
+                "Integrations cannot be reloaded while replaying events"
+            ));
+        }
 
-            integration.register().await.unwrap();
-            info!(
-                "registered {} integration {}",
-                li.module_name, integration_id
-            );
+        let mut loaded_integrations = HashMap::new();
+
+        for (id, integration_config) in integrations_config {
+            let module_name = integration_config.plugin.as_str();
+            let config = opaque_integrations_configs
+                .get(id)
+                .ok_or_else(|| eyre!("Expected to find config for integration with id {id}"))?;
+
+            let required = integration_config.required.unwrap_or(false);
+
+            match self.custom_integrations.get_mut(id) {
+                Some(li) if li.module_name == module_name && &li.config == config => {
+                    li.required = required;
+                }
+                _ => {
+                    let loaded_integration =
+                        self.mk_loaded_integration(module_name, id, config, required)?;
+                    loaded_integrations.insert(id.clone(), loaded_integration);
+                }
+            }
+        }
+
+        let stale_ids: Vec<IntegrationId> = self
+            .custom_integrations
+            .keys()
+            .filter(|id| {
+                !integrations_config.contains_key(id) || loaded_integrations.contains_key(id)
+            })
+            .cloned()
+            .collect();
+
+        for integration_id in stale_ids {
+            if let Some(li) = self.custom_integrations.remove(&integration_id) {
+                stop_integration(
+                    &li.integration,
+                    &li.module_name,
+                    &integration_id,
+                    self.restart_timeout,
+                )
+                .await;
+            }
+            self.load_order.retain(|id| *id != integration_id);
+        }
+
+        for (integration_id, mut li) in loaded_integrations {
+            if let Err(e) = li
+                .register_and_start(&integration_id, self.restart_timeout)
+                .await
+            {
+                error!("{e:?}");
+            }
+
+            self.custom_integrations.insert(integration_id.clone(), li);
+            self.load_order.push(integration_id);
+        }
+
+        Ok(())
+    }
+
+    /// Runs the registration pass for all loaded integrations. Returns an
+    /// error if a required integration fails, other failing integrations are
+    /// marked as failed and restarted later.
+    pub async fn run_register_pass(&mut self) -> Result<()> {
+        let timeout = self.restart_timeout;
+
+        for integration_id in &self.load_order {
+            let Some(li) = self.custom_integrations.get_mut(integration_id) else {
+                continue;
+            };
+
+            let result = tokio::time::timeout(timeout, async {
+                li.integration.lock().await.register().await
+            })
+            .await
+            .unwrap_or_else(|_| Err(eyre!("Timed out after {timeout:?}")));
+
+            match result {
+                Ok(()) => info!(
+                    "registered {} integration {}",
+                    li.module_name, integration_id
+                ),
+                Err(e) => {
+                    li.mark_failed(format!("{e:#}"));
+                    let e = e.wrap_err(format!(
+                        "Failed to register {} integration {integration_id}",
+                        li.module_name
+                    ));
+
+                    if li.required {
+                        return Err(e);
+                    }
+                    error!("{e:?}");
+                }
+            }
         }
 
         Ok(())
     }
 
-    pub async fn run_start_pass(&self) -> Result<()> {
-        for (integration_id, li) in self.custom_integrations.iter() {
-            let mut integration = li.integration.lock().await;
+    /// Starts all integrations that registered successfully, with the same
+    /// error handling as [Integrations::run_register_pass].
+    pub async fn run_start_pass(&mut self) -> Result<()> {
+        let timeout = self.restart_timeout;
+
+        for integration_id in &self.load_order {
+            let Some(li) = self.custom_integrations.get_mut(integration_id) else {
+                continue;
+            };
+
+            if li.state == IntegrationState::Failed {
+                continue;
+            }
 
-            integration.start().await.unwrap();
-            info!("started {} integration {}", li.module_name, integration_id);
+            let result =
+                tokio::time::timeout(timeout, async { li.integration.lock().await.start().await })
+                    .await
+                    .unwrap_or_else(|_| Err(eyre!("Timed out after {timeout:?}")));
+
+            match result {
+                Ok(()) => {
+                    li.mark_running();
+                    info!("started {} integration {}", li.module_name, integration_id);
+                }
+                Err(e) => {
+                    li.mark_failed(format!("{e:#}"));
+                    let e = e.wrap_err(format!(
+                        "Failed to start {} integration {integration_id}",
+                        li.module_name
+                    ));
+
+                    if li.required {
+                        return Err(e);
+                    }
+                    error!("{e:?}");
+                }
+            }
         }
 
         Ok(())
     }
 
+    /// Replaces an integration with a fresh instance using the same config.
+    /// The old instance is stopped and the new one registered and started in
+    /// a background task, so that a slow integration doesn't hold up the app
+    /// state. The outcome is reported with [Event::IntegrationRestarted].
+    pub fn restart(&mut self, integration_id: &IntegrationId) -> Result<()> {
+        let (module_name, config) = {
+            let li = self
+                .custom_integrations
+                .get(integration_id)
+                .ok_or_else(|| eyre!("Expected to find integration by id {integration_id}"))?;
+
+            if li.pending_restart.is_some() {
+                return Err(eyre!("Integration {integration_id} is already restarting"));
+            }
+
+            (li.module_name.clone(), li.config.clone())
+        };
+
+        let integration = self.mk_integration(&module_name, integration_id, &config);
+
+        self.last_restart_id += 1;
+        let restart_id = self.last_restart_id;
+        let timeout = self.restart_timeout;
+        let event_tx = self.event_tx.clone();
+
+        let li = self
+            .custom_integrations
+            .get_mut(integration_id)
+            .ok_or_else(|| eyre!("Expected to find integration by id {integration_id}"))?;
+
+        li.restarts += 1;
+        li.state = IntegrationState::Starting;
+
+        let integration = match integration {
+            Ok(integration) => integration,
+            Err(e) => {
+                li.mark_failed(format!("{e:#}"));
+                return Err(e);
+            }
+        };
+
+        // The old instance may still hold its lock if it's hanging
+        let old = std::mem::replace(&mut li.integration, Arc::new(Mutex::new(integration)));
+        let new = li.integration.clone();
+        li.pending_restart = Some(restart_id);
+
+        let integration_id = integration_id.clone();
+
+        tokio::spawn(async move {
+            stop_integration(&old, &module_name, &integration_id, timeout).await;
+
+            let error = register_and_start(&new, timeout)
+                .await
+                .err()
+                .map(|e| format!("{e:#}"));
+
+            event_tx.send(Event::IntegrationRestarted {
+                integration_id,
+                restart_id,
+                error,
+            });
+        });
+
+        Ok(())
+    }
+
+    /// Records the outcome of a background restart, see
+    /// [Integrations::restart]. Outcomes of restarts that have since been
+    /// superseded, e.g. by a config reload, are ignored.
+    pub fn finish_restart(
+        &mut self,
+        integration_id: &IntegrationId,
+        restart_id: u64,
+        error: Option<String>,
+    ) {
+        let Some(li) = self.custom_integrations.get_mut(integration_id) else {
+            return;
+        };
+
+        if li.pending_restart != Some(restart_id) {
+            return;
+        }
+
+        li.pending_restart = None;
+
+        match error {
+            None => {
+                li.mark_running();
+                info!("restarted {} integration {integration_id}", li.module_name);
+            }
+            Some(e) => {
+                li.mark_failed(e.clone());
+                let backoff = restart_backoff(li.consecutive_failures);
+
+                error!(
+                    "Failed to restart {} integration {integration_id}: {e}\nRetrying in {}s",
+                    li.module_name,
+                    backoff.as_secs()
+                );
+            }
+        }
+    }
+
+    /// Checks the health of running integrations and restarts failed
+    /// integrations whose restart backoff has elapsed. Called on every tick.
+    pub fn supervise(&mut self) {
+        let now = Utc::now();
+        let mut restart_ids = vec![];
+
+        for (integration_id, li) in self.custom_integrations.iter_mut() {
+            match li.state {
+                IntegrationState::Starting => {}
+                IntegrationState::Failed => {
+                    if li.next_restart_at.is_none_or(|t| t <= now) {
+                        restart_ids.push(integration_id.clone());
+                    }
+                }
+                IntegrationState::Running | IntegrationState::Degraded => {
+                    // The integration is busy, check again on the next tick
+                    let Ok(integration) = li.integration.try_lock() else {
+                        continue;
+                    };
+                    let health = integration.health();
+                    drop(integration);
+
+                    match health {
+                        IntegrationHealth::Healthy => {
+                            if li.state == IntegrationState::Degraded {
+                                info!("integration {integration_id} has recovered");
+                                li.state = IntegrationState::Running;
+                            }
+
+                            let stable = li.running_since.is_some_and(|t| {
+                                (now - t).to_std().is_ok_and(|d| d >= STABLE_AFTER)
+                            });
+                            if stable {
+                                li.consecutive_failures = 0;
+                            }
+                        }
+                        IntegrationHealth::Degraded(e) => {
+                            if li.state != IntegrationState::Degraded {
+                                warn!("integration {integration_id} is degraded: {e}");
+                                li.state = IntegrationState::Degraded;
+                            }
+                            li.set_error(e);
+                        }
+                        IntegrationHealth::Failed(e) => {
+                            error!("integration {integration_id} has failed: {e}");
+                            li.mark_failed(e);
+                        }
+                    }
+                }
+            }
+        }
+
+        for integration_id in restart_ids {
+            info!("restarting failed integration {integration_id}");
+
+            if let Err(e) = self.restart(&integration_id) {
+                let backoff = self
+                    .custom_integrations
+                    .get(&integration_id)
+                    .map(|li| restart_backoff(li.consecutive_failures))
+                    .unwrap_or_default();
+
+                error!("{e:?}\nRetrying in {}s", backoff.as_secs());
+            }
+        }
+    }
+
+    /// Stops all integrations in the reverse of the order they were started
+    /// in. Each integration gets at most `timeout` to stop, after which it's
+    /// left behind so that one misbehaving integration can't hold up shutdown.
+    pub async fn stop_all(&self, timeout: Duration) {
+        for integration_id in self.load_order.iter().rev() {
+            let Some(li) = self.custom_integrations.get(integration_id) else {
+                continue;
+            };
+
+            stop_integration(&li.integration, &li.module_name, integration_id, timeout).await;
+        }
+    }
+
+    /// Returns the status of each loaded integration, sorted by id.
+    pub fn get_integrations(&self) -> Vec<IntegrationStatus> {
+        let mut integrations: Vec<IntegrationStatus> = self
+            .custom_integrations
+            .iter()
+            .map(|(id, li)| li.get_status(id))
+            .collect();
+
+        integrations.sort_by(|a, b| a.id.cmp(&b.id));
+        integrations
+    }
+
+    pub fn has_integration(&self, integration_id: &IntegrationId) -> bool {
+        self.custom_integrations.contains_key(integration_id)
+    }
+
     pub async fn set_integration_device_state(&self, device: Device) -> Result<()> {
         if device.is_readonly() {
             debug!(
@@ -100,16 +603,7 @@ impl Integrations {
             return Ok(());
         }
 
-        let li = self
-            .custom_integrations
-            .get(&device.integration_id)
-            .ok_or_else(|| {
-                eyre!(
-                    "Expected to find integration by id {}",
-                    device.integration_id
-                )
-            })?;
-
+        let li = self.get_available(&device.integration_id)?;
         let mut integration = li.integration.lock().await;
 
         integration
@@ -122,32 +616,241 @@ impl Integrations {
         integration_id: &IntegrationId,
         payload: &IntegrationActionPayload,
     ) -> Result<()> {
+        let li = self.get_available(integration_id)?;
+        let mut integration = li.integration.lock().await;
+
+        integration.run_integration_action(payload).await
+    }
+
+    /// Returns an integration that can be called into. Integrations that are
+    /// restarting in the background may hold their lock for a while, and
+    /// waiting for it would hold up the app state.
+    fn get_available(&self, integration_id: &IntegrationId) -> Result<&LoadedIntegration> {
         let li = self
             .custom_integrations
             .get(integration_id)
             .ok_or_else(|| eyre!("Expected to find integration by id {integration_id}"))?;
-        let mut integration = li.integration.lock().await;
 
-        integration.run_integration_action(payload).await
+        if li.pending_restart.is_some() {
+            return Err(eyre!("Integration {integration_id} is restarting"));
+        }
+
+        Ok(li)
     }
 }
 
-// TODO: Load integrations dynamically as plugins:
-// https://michael-f-bryan.github.io/rust-ffi-guide/dynamic_loading.html
+/// Registers and starts an integration instance. Taking longer than
+/// `timeout` counts as failing.
+async fn register_and_start(integration: &IntegrationInstance, timeout: Duration) -> Result<()> {
+    tokio::time::timeout(timeout, async {
+        let mut integration = integration.lock().await;
+
+        integration.register().await?;
+        integration.start().await
+    })
+    .await
+    .unwrap_or_else(|_| Err(eyre!("Timed out after {timeout:?}")))
+}
+
+/// Stops an integration instance, errors are logged and otherwise ignored
+/// since the instance may already be in a broken state. An instance that
+/// doesn't stop within `timeout` is left behind.
+async fn stop_integration(
+    integration: &IntegrationInstance,
+    module_name: &str,
+    integration_id: &IntegrationId,
+    timeout: Duration,
+) {
+    let result =
+        tokio::time::timeout(timeout, async { integration.lock().await.stop().await }).await;
+
+    match result {
+        Ok(Ok(())) => info!("stopped {module_name} integration {integration_id}"),
+        Ok(Err(e)) => {
+            warn!("Error while stopping {module_name} integration {integration_id}: {e:?}")
+        }
+        Err(_) => warn!(
+            "Timed out after {timeout:?} while stopping {module_name} integration {integration_id}"
+        ),
+    }
+}
+
+/// Integrations that aren't built into homectl can be written in any language
+/// and run out of process with the `process` plugin.
 fn load_custom_integration(
     module_name: &str,
     id: &IntegrationId,
     config: &config::Value,
     cli: &Cli,
     event_tx: TxEventChannel,
+    storage: IntegrationStorage,
 ) -> Result<Box<dyn Integration>> {
     match module_name {
-        "circadian" => Ok(Box::new(Circadian::new(id, config, cli, event_tx)?)),
-        "cron" => Ok(Box::new(Cron::new(id, config, cli, event_tx)?)),
-        "random" => Ok(Box::new(Random::new(id, config, cli, event_tx)?)),
-        "timer" => Ok(Box::new(Timer::new(id, config, cli, event_tx)?)),
-        "dummy" => Ok(Box::new(Dummy::new(id, config, cli, event_tx)?)),
-        "mqtt" => Ok(Box::new(Mqtt::new(id, config, cli, event_tx)?)),
+        "circadian" => Ok(Box::new(Circadian::new(
+            id, config, cli, event_tx, storage,
+        )?)),
+        "cron" => Ok(Box::new(Cron::new(id, config, cli, event_tx, storage)?)),
+        "random" => Ok(Box::new(Random::new(id, config, cli, event_tx, storage)?)),
+        "timer" => Ok(Box::new(Timer::new(id, config, cli, event_tx, storage)?)),
+        "dummy" => Ok(Box::new(Dummy::new(id, config, cli, event_tx, storage)?)),
+        "mqtt" => Ok(Box::new(Mqtt::new(id, config, cli, event_tx, storage)?)),
+        "process" => Ok(Box::new(Process::new(id, config, cli, event_tx, storage)?)),
         _ => Err(eyre!("Unknown module name {module_name}!")),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::event::{mk_event_channel, RxEventChannel};
+    use async_trait::async_trait;
+    use clap::Parser;
+
+    /// Integration that never finishes registering or stopping.
+    struct Hanging;
+
+    #[async_trait]
+    impl Integration for Hanging {
+        fn new(
+            _id: &IntegrationId,
+            _config: &config::Value,
+            _cli: &Cli,
+            _event_tx: TxEventChannel,
+            _storage: IntegrationStorage,
+        ) -> Result<Self> {
+            Ok(Hanging)
+        }
+
+        async fn register(&mut self) -> Result<()> {
+            std::future::pending().await
+        }
+
+        async fn stop(&mut self) -> Result<()> {
+            std::future::pending().await
+        }
+    }
+
+    fn mk_integrations() -> (Integrations, RxEventChannel) {
+        let (event_tx, event_rx) = mk_event_channel();
+        let cli = Cli::parse_from(["homectl-server", "--dry-run"]);
+        let storage = Storage::new(std::env::temp_dir().join("homectl-test-storage.json"), &cli);
+
+        let mut integrations = Integrations::new(event_tx, &cli, storage);
+        integrations.restart_timeout = Duration::from_millis(50);
+        (integrations, event_rx)
+    }
+
+    /// Loads a dummy integration, with a hanging instance in its place.
+    fn load_hanging(integrations: &mut Integrations, integration_id: &IntegrationId) {
+        let config = config::Value::new(None, config::ValueKind::Table(Default::default()));
+        let mut li = integrations
+            .mk_loaded_integration("dummy", integration_id, &config, false)
+            .unwrap();
+        li.integration = Arc::new(Mutex::new(Box::new(Hanging)));
+
+        integrations
+            .custom_integrations
+            .insert(integration_id.clone(), li);
+        integrations.load_order.push(integration_id.clone());
+    }
+
+    #[tokio::test]
+    async fn test_start_timeout() {
+        let (mut integrations, _event_rx) = mk_integrations();
+        let integration_id = IntegrationId::from("hanging".to_string());
+        load_hanging(&mut integrations, &integration_id);
+
+        let li = integrations
+            .custom_integrations
+            .get_mut(&integration_id)
+            .unwrap();
+        let result = li
+            .register_and_start(&integration_id, Duration::from_millis(50))
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(li.state, IntegrationState::Failed);
+        assert_eq!(li.last_error.as_deref(), Some("Timed out after 50ms"));
+    }
+
+    #[tokio::test]
+    async fn test_restart_timeout() {
+        let (mut integrations, mut event_rx) = mk_integrations();
+        let integration_id = IntegrationId::from("hanging".to_string());
+        load_hanging(&mut integrations, &integration_id);
+
+        // The restart happens in the background
+        integrations.restart(&integration_id).unwrap();
+        assert_eq!(
+            integrations.get_integrations()[0].state,
+            IntegrationState::Starting
+        );
+        assert!(integrations.restart(&integration_id).is_err());
+
+        // The hanging instance is abandoned, and a fresh one is started
+        let (_, event) = tokio::time::timeout(Duration::from_secs(5), event_rx.recv())
+            .await
+            .expect("restart should not hang")
+            .unwrap();
+        let Event::IntegrationRestarted {
+            restart_id, error, ..
+        } = event
+        else {
+            panic!("Unexpected event {event:?}");
+        };
+        assert_eq!(error, None);
+
+        // Outcomes of superseded restarts are ignored
+        integrations.finish_restart(&integration_id, restart_id + 1, Some("stale".to_string()));
+        integrations.finish_restart(&integration_id, restart_id, error);
+
+        let status = &integrations.get_integrations()[0];
+        assert_eq!(status.state, IntegrationState::Running);
+        assert_eq!(status.restarts, 1);
+        assert_eq!(status.last_error, None);
+    }
+
+    #[tokio::test]
+    async fn test_register_pass_timeout() {
+        let (mut integrations, _event_rx) = mk_integrations();
+        let integration_id = IntegrationId::from("hanging".to_string());
+        load_hanging(&mut integrations, &integration_id);
+
+        tokio::time::timeout(Duration::from_secs(5), integrations.run_register_pass())
+            .await
+            .expect("register pass should not hang")
+            .unwrap();
+
+        let status = &integrations.get_integrations()[0];
+        assert_eq!(status.state, IntegrationState::Failed);
+        assert_eq!(status.last_error.as_deref(), Some("Timed out after 50ms"));
+    }
+
+    #[tokio::test]
+    async fn test_apply_config_stop_timeout() {
+        let (mut integrations, _event_rx) = mk_integrations();
+        let integration_id = IntegrationId::from("hanging".to_string());
+        load_hanging(&mut integrations, &integration_id);
+
+        // The hanging instance is abandoned, and the integration is removed
+        tokio::time::timeout(
+            Duration::from_secs(5),
+            integrations.apply_config(&Default::default(), &Default::default()),
+        )
+        .await
+        .expect("apply_config should not hang")
+        .unwrap();
+
+        assert!(!integrations.has_integration(&integration_id));
+        assert!(integrations.load_order.is_empty());
+    }
+
+    #[test]
+    fn test_restart_backoff() {
+        assert_eq!(restart_backoff(1), Duration::from_secs(1));
+        assert_eq!(restart_backoff(2), Duration::from_secs(2));
+        assert_eq!(restart_backoff(5), Duration::from_secs(16));
+        assert_eq!(restart_backoff(10), MAX_RESTART_BACKOFF);
+        assert_eq!(restart_backoff(u32::MAX), MAX_RESTART_BACKOFF);
+    }
+}
@@ -0,0 +1,414 @@
+use std::{
+    ffi::OsString,
+    fs::File,
+    io::{BufRead, BufReader},
+    iter::Peekable,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use chrono::{DateTime, Local, Utc};
+use color_eyre::Result;
+use eyre::Context;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::OpenOptions,
+    io::{AsyncWriteExt, BufWriter},
+    sync::mpsc::{unbounded_channel, UnboundedSender},
+    task::JoinHandle,
+};
+
+use crate::{
+    types::event::{Event, EventSource, RxEventChannel},
+    utils::clock::FixedClock,
+};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct JournalEntry {
+    pub timestamp: DateTime<Utc>,
+    pub source: EventSource,
+    pub event: Event,
+}
+
+/// Append-only log of events passing through the core event loop, stored as
+/// newline delimited JSON.
+pub struct EventJournal {
+    tx: UnboundedSender<JournalEntry>,
+    writer: JoinHandle<()>,
+}
+
+async fn open_journal_file(path: &Path) -> std::io::Result<tokio::fs::File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+}
+
+/// Path that a full journal is moved to, e.g. `events.jsonl.1`.
+fn rotated_path(path: &Path) -> PathBuf {
+    let mut rotated = OsString::from(path.as_os_str());
+    rotated.push(".1");
+    PathBuf::from(rotated)
+}
+
+impl EventJournal {
+    /// Opens the journal for appending. If `max_size` is given, the journal
+    /// is moved aside to `<path>.1` once it would grow beyond `max_size`
+    /// bytes, replacing any previously rotated journal.
+    pub async fn open(path: &Path, max_size: Option<u64>) -> Result<Self> {
+        let file = open_journal_file(path)
+            .await
+            .wrap_err_with(|| format!("Failed to open event journal {}", path.display()))?;
+        let mut size = file.metadata().await.map(|m| m.len()).unwrap_or(0);
+
+        info!("Recording events to journal {}", path.display());
+
+        let path = path.to_path_buf();
+
+        let (tx, mut rx) = unbounded_channel::<JournalEntry>();
+
+        // Write entries in a separate task so that file I/O never blocks the
+        // event loop
+        let writer = tokio::spawn(async move {
+            let mut writer = BufWriter::new(file);
+
+            while let Some(entry) = rx.recv().await {
+                let line = match serde_json::to_string(&entry) {
+                    Ok(line) => line,
+                    Err(e) => {
+                        debug!("Skipping unserializable journal entry: {e}");
+                        continue;
+                    }
+                };
+
+                let result = async {
+                    let len = line.len() as u64 + 1;
+
+                    if max_size.is_some_and(|max_size| size > 0 && size + len > max_size) {
+                        writer.flush().await?;
+                        tokio::fs::rename(&path, rotated_path(&path)).await?;
+                        writer = BufWriter::new(open_journal_file(&path).await?);
+                        size = 0;
+                    }
+
+                    writer.write_all(line.as_bytes()).await?;
+                    writer.write_all(b"\n").await?;
+                    size += len;
+
+                    // Only flush once we've caught up with the event loop
+                    if rx.is_empty() {
+                        writer.flush().await?;
+                    }
+
+                    Ok::<(), std::io::Error>(())
+                }
+                .await;
+
+                if let Err(e) = result {
+                    error!("Failed to write event journal entry: {e}");
+                }
+            }
+        });
+
+        Ok(EventJournal { tx, writer })
+    }
+
+    /// Waits until all recorded events have been written to the journal.
+    pub async fn close(self) {
+        drop(self.tx);
+        self.writer.await.ok();
+    }
+
+    /// Appends the event to the journal.
+    ///
+    /// Purely internal bookkeeping events are skipped, as these are derived
+    /// from other events and would otherwise dominate the journal.
+    /// Integrations aren't run during replay, so their restarts are skipped as
+    /// well.
+    pub fn record(&self, source: &EventSource, event: &Event) {
+        if matches!(
+            event,
+            Event::InternalStateUpdate { .. }
+                | Event::WsBroadcastState
+                | Event::Tick
+                | Event::IntegrationRestarted { .. }
+        ) {
+            return;
+        }
+
+        let entry = JournalEntry {
+            timestamp: Utc::now(),
+            source: source.clone(),
+            event: event.clone(),
+        };
+
+        self.tx.send(entry).ok();
+    }
+}
+
+/// Waits for the next event to handle. When replaying, the next event from the
+/// journal is only returned once all queued events have been handled. Returns
+/// None when replay has finished.
+pub async fn next_event(
+    event_rx: &mut RxEventChannel,
+    replay: &mut Option<Replay>,
+) -> Option<(EventSource, Event)> {
+    match replay.as_mut() {
+        // Only replay the next event once all events caused by the
+        // previous one have been handled
+        Some(r) => match event_rx.try_recv() {
+            Ok(event) => Some(event),
+            Err(_) => match r.next_event() {
+                Some(event) => Some(event),
+                None => {
+                    info!("Finished replaying {}", r.path().display());
+                    *replay = None;
+                    None
+                }
+            },
+        },
+        None => Some(
+            event_rx
+                .recv()
+                .await
+                .expect("Expected sender end of channel to never be dropped"),
+        ),
+    }
+}
+
+/// Feeds events from a previously recorded journal back into the event loop.
+/// The clock follows the recorded timestamps, and ticks are sent as often as
+/// they were while recording.
+pub struct Replay {
+    path: PathBuf,
+    entries: Peekable<std::vec::IntoIter<JournalEntry>>,
+    clock: Arc<FixedClock>,
+    tick_interval: Duration,
+
+    /// Recorded time of the previous tick
+    last_tick: Option<DateTime<Utc>>,
+}
+
+impl Replay {
+    pub fn open(path: &Path, clock: Arc<FixedClock>, tick_interval: Duration) -> Result<Self> {
+        let file = File::open(path)
+            .wrap_err_with(|| format!("Failed to open event journal {}", path.display()))?;
+
+        let entries = BufReader::new(file)
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !matches!(line, Ok(line) if line.trim().is_empty()))
+            .map(|(index, line)| {
+                let entry = serde_json::from_str(&line?).wrap_err_with(|| {
+                    format!("Failed to parse event journal entry on line {}", index + 1)
+                })?;
+
+                Ok(entry)
+            })
+            .collect::<Result<Vec<JournalEntry>>>()?;
+
+        info!(
+            "Replaying {} events from journal {}",
+            entries.len(),
+            path.display()
+        );
+
+        if let Some(entry) = entries.first() {
+            clock.set(entry.timestamp.with_timezone(&Local));
+        }
+
+        Ok(Replay {
+            path: path.to_path_buf(),
+            entries: entries.into_iter().peekable(),
+            clock,
+            tick_interval,
+            last_tick: None,
+        })
+    }
+
+    /// Returns the next event to be replayed, or a tick if one is due before
+    /// it, and moves the clock to when the event was recorded.
+    ///
+    /// Events sent by core are skipped since handling the replayed events
+    /// will produce them again, with the exception of
+    /// [Event::StartupCompleted] which marks the end of the warmup period.
+    pub fn next_event(&mut self) -> Option<(EventSource, Event)> {
+        loop {
+            let timestamp = self.entries.peek()?.timestamp;
+
+            if let Some(last_tick) = self.last_tick {
+                let next_tick = last_tick + self.tick_interval;
+
+                if next_tick <= timestamp {
+                    self.last_tick = Some(next_tick);
+                    self.clock.set(next_tick.with_timezone(&Local));
+                    return Some((EventSource::Core, Event::Tick));
+                }
+            }
+
+            let entry = self.entries.next()?;
+            self.last_tick.get_or_insert(entry.timestamp);
+            self.clock.set(entry.timestamp.with_timezone(&Local));
+
+            match (&entry.source, &entry.event) {
+                (EventSource::Core, Event::StartupCompleted) => {
+                    return Some((EventSource::Replay, entry.event))
+                }
+                (EventSource::Core, _) => {}
+                _ => {
+                    debug!(
+                        "Replaying event recorded at {} from {:?}",
+                        entry.timestamp, entry.source
+                    );
+                    return Some((EventSource::Replay, entry.event));
+                }
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{event::mk_event_channel, integration::IntegrationId, scene::SceneId};
+    use crate::utils::clock::Clock;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "homectl-journal-test-{name}-{}.jsonl",
+            std::process::id()
+        ))
+    }
+
+    fn delete_scene(scene_id: &str) -> Event {
+        Event::DbDeleteScene {
+            scene_id: SceneId::from(scene_id.to_string()),
+        }
+    }
+
+    fn scene_id(event: Option<(EventSource, Event)>) -> Option<(EventSource, String)> {
+        match event {
+            Some((source, Event::DbDeleteScene { scene_id })) => {
+                Some((source, scene_id.to_string()))
+            }
+            Some((source, Event::StartupCompleted)) => Some((source, "startup".to_string())),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_record_and_replay() {
+        let path = temp_path("replay");
+        std::fs::remove_file(&path).ok();
+        let integration = EventSource::Integration(IntegrationId::from("d".to_string()));
+
+        let journal = EventJournal::open(&path, None).await.unwrap();
+        journal.record(&integration, &delete_scene("a"));
+        journal.record(&EventSource::Core, &Event::Tick);
+        journal.record(&EventSource::Core, &delete_scene("derived"));
+        journal.record(&EventSource::Core, &Event::StartupCompleted);
+        journal.record(&EventSource::Api, &delete_scene("b"));
+        journal.close().await;
+
+        let clock = Arc::new(FixedClock::new(Local::now()));
+        let mut replay = Some(Replay::open(&path, clock, Duration::from_secs(1)).unwrap());
+        let (event_tx, mut event_rx) = mk_event_channel();
+
+        // Events caused by a replayed event are handled before the next one
+        // is read from the journal
+        event_tx.send(delete_scene("queued"));
+        let replayed = [
+            (EventSource::Core, "queued"),
+            (EventSource::Replay, "a"),
+            (EventSource::Replay, "startup"),
+            (EventSource::Replay, "b"),
+        ];
+        for (source, scene_id_) in replayed {
+            let event = next_event(&mut event_rx, &mut replay).await;
+            assert_eq!(scene_id(event), Some((source, scene_id_.to_string())));
+        }
+
+        assert!(next_event(&mut event_rx, &mut replay).await.is_none());
+        assert!(replay.is_none());
+
+        std::fs::remove_file(path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_replay_clock_and_ticks() {
+        let path = temp_path("ticks");
+        let at = |time: &str| DateTime::parse_from_rfc3339(time).unwrap().to_utc();
+        let entries = [
+            ("2026-10-18T12:00:00Z", delete_scene("a")),
+            ("2026-10-18T12:00:02.500Z", delete_scene("b")),
+        ];
+        let lines: Vec<String> = entries
+            .into_iter()
+            .map(|(timestamp, event)| {
+                let entry = JournalEntry {
+                    timestamp: at(timestamp),
+                    source: EventSource::Api,
+                    event,
+                };
+                serde_json::to_string(&entry).unwrap()
+            })
+            .collect();
+        std::fs::write(&path, lines.join("\n")).unwrap();
+
+        let clock = Arc::new(FixedClock::new(Local::now()));
+        let mut replay = Replay::open(&path, clock.clone(), Duration::from_secs(1)).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(clock.now(), at("2026-10-18T12:00:00Z"));
+
+        let mut replayed = vec![];
+        while let Some((_, event)) = replay.next_event() {
+            let event = match event {
+                Event::Tick => "tick".to_string(),
+                Event::DbDeleteScene { scene_id } => scene_id.to_string(),
+                _ => panic!("Unexpected event {event:?}"),
+            };
+            replayed.push((event, clock.now().to_utc()));
+        }
+
+        let expected = [
+            ("a", "2026-10-18T12:00:00Z"),
+            ("tick", "2026-10-18T12:00:01Z"),
+            ("tick", "2026-10-18T12:00:02Z"),
+            ("b", "2026-10-18T12:00:02.500Z"),
+        ];
+        assert_eq!(
+            replayed,
+            expected
+                .map(|(event, time)| (event.to_string(), at(time)))
+                .to_vec()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rotate() {
+        let path = temp_path("rotate");
+        std::fs::remove_file(&path).ok();
+        std::fs::remove_file(rotated_path(&path)).ok();
+
+        let journal = EventJournal::open(&path, Some(200)).await.unwrap();
+        for i in 0..10 {
+            journal.record(&EventSource::Api, &delete_scene(&i.to_string()));
+        }
+        journal.close().await;
+
+        let current = std::fs::read_to_string(&path).unwrap();
+        let rotated = std::fs::read_to_string(rotated_path(&path)).unwrap();
+        assert!(current.len() <= 200);
+        assert!(rotated.len() <= 200);
+        assert!(current.ends_with("\"9\"}}}\n"));
+
+        std::fs::remove_file(rotated_path(&path)).ok();
+        std::fs::remove_file(path).ok();
+    }
+}
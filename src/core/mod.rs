@@ -1,11 +1,17 @@
+pub mod auth;
 pub mod config;
 pub mod devices;
 pub mod event;
 pub mod expr;
 pub mod groups;
+pub mod history;
 pub mod integrations;
+pub mod journal;
+pub mod reload;
 pub mod routines;
 pub mod scenes;
 pub mod state;
+pub mod storage;
 pub mod ui;
+pub mod validation;
 pub mod websockets;
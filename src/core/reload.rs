@@ -0,0 +1,122 @@
+use std::{path::PathBuf, time::Duration, time::SystemTime};
+
+use color_eyre::Result;
+use eyre::Context;
+
+use crate::types::event::{Event, TxEventChannel};
+use crate::utils::solar::{get_location, set_location};
+
+use super::{
+    config::{config_files, config_path, read_config},
+    groups::Groups,
+    state::AppState,
+    validation::{validate_config, Severity},
+};
+
+static WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Re-reads the config files and applies them to the running server. Integrations
+/// are updated first, as that's where most errors are likely to occur. If the
+/// config fails to parse or validate, or any integration fails to load, the
+/// previous config stays in effect.
+///
+/// Groups, scenes and routines are then replaced while keeping device state,
+/// DB stored scenes and routine trigger history intact.
+pub async fn reload_config(state: &mut AppState) -> Result<()> {
+    let (config, opaque_integrations_configs) = read_config().wrap_err("Failed to read config")?;
+
+    let issues = validate_config(
+        &config,
+        &state.scenes.get_db_scene_ids(),
+        state.devices.get_state(),
+    );
+
+    let errors: Vec<String> = issues
+        .iter()
+        .filter(|issue| issue.severity == Severity::Error)
+        .map(|issue| issue.to_string())
+        .collect();
+
+    if !errors.is_empty() {
+        return Err(eyre!("Invalid config:\n{}", errors.join("\n")));
+    }
+
+    for issue in issues {
+        warn!("{issue}");
+    }
+
+    let core_config = config.core.unwrap_or_default();
+    let integrations_config = config.integrations.unwrap_or_default();
+
+    // Integrations may depend on the location during construction
+    let prev_location = get_location();
+    set_location(core_config.location);
+
+    let result = state
+        .integrations
+        .apply_config(&integrations_config, &opaque_integrations_configs)
+        .await;
+
+    if let Err(e) = result {
+        set_location(prev_location);
+        return Err(e);
+    }
+
+    state.auth.set_config(&core_config.api.unwrap_or_default());
+    state.devices.set_offline_timeouts(&integrations_config);
+    state.groups = Groups::new(config.groups.unwrap_or_default());
+    state.scenes.set_config(config.scenes.unwrap_or_default());
+
+    state.force_invalidate();
+
+    state.rules.set_config(
+        config.routines.unwrap_or_default(),
+        &state.devices,
+        &state.groups,
+        &state.expr,
+    );
+
+    state.send_state_ws(None).await;
+
+    info!("Reloaded config from {}", config_path().display());
+
+    Ok(())
+}
+
+type ConfigFilesState = Vec<(PathBuf, Option<SystemTime>)>;
+
+/// Modification times of the main config file and all included files. Falls
+/// back to only the main config file if includes can't be resolved, e.g.
+/// while a file is being edited.
+fn config_files_state() -> ConfigFilesState {
+    let files = config_files().unwrap_or_else(|_| vec![config_path()]);
+
+    files
+        .into_iter()
+        .map(|path| {
+            let modified_at = std::fs::metadata(&path).and_then(|m| m.modified()).ok();
+            (path, modified_at)
+        })
+        .collect()
+}
+
+/// Polls config files for changes, and sends [Event::ReloadConfig] whenever
+/// any of them is modified, added or removed.
+pub fn watch_config(event_tx: TxEventChannel) {
+    tokio::spawn(async move {
+        let mut prev_state = config_files_state();
+        let mut interval = tokio::time::interval(WATCH_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            let state = config_files_state();
+            if state != prev_state {
+                info!("Config files changed, reloading config");
+                event_tx.send(Event::ReloadConfig);
+            }
+
+            prev_state = state;
+        }
+    });
+}
@@ -1,29 +1,140 @@
 use evalexpr::HashMapContext;
 use eyre::{ContextCompat, Result};
 
+use crate::db::actions::{db_get_routine_states, db_store_routine_enabled};
 use crate::types::{
     action::Actions,
     device::{Device, DevicesState, SensorDevice},
     event::{Event, TxEventChannel},
-    rule::{AnyRule, DeviceRule, GroupRule, Routine, RoutineId, RoutinesConfig, Rule},
+    rule::{
+        AnyRule, DeviceRule, GroupRule, Routine, RoutineId, RoutineStatus, RoutinesConfig, Rule,
+        RuleStatus,
+    },
+};
+use crate::utils::clock::Clock;
+use chrono::{DateTime, Local, Utc};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt::{self, Display},
+    sync::Arc,
+    time::Duration,
 };
-use std::collections::HashSet;
 
 use super::{devices::Devices, expr::Expr, groups::Groups};
 
+/// Identifies a rule by the routine it belongs to, and its index within the
+/// routine's rules. Rules nested within `any` rules have one index per level.
+type RulePath = (RoutineId, Vec<usize>);
+
+/// Keeps track of when rules with a `for` duration started matching.
+type RuleTimers = HashMap<RulePath, DateTime<Local>>;
+
+#[derive(Debug, PartialEq)]
+enum SuppressReason {
+    Cooldown,
+    Debounce,
+    RateLimit,
+}
+
+impl Display for SuppressReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SuppressReason::Cooldown => f.write_str("cooldown"),
+            SuppressReason::Debounce => f.write_str("debounce"),
+            SuppressReason::RateLimit => f.write_str("rate limit"),
+        }
+    }
+}
+
+/// Keeps track of recent triggers of a routine, used for enforcing the
+/// routine's cooldown, debounce and rate limit options.
+#[derive(Clone, Debug, Default)]
+struct TriggerHistory {
+    last_fired: Option<DateTime<Local>>,
+    last_attempt: Option<DateTime<Local>>,
+    recent_fires: VecDeque<DateTime<Local>>,
+    suppressed_count: u64,
+    trigger_count: u64,
+    last_triggered: Option<DateTime<Utc>>,
+    last_error: Option<String>,
+
+    /// Whether the routine fired when it was last triggered, and has not been
+    /// released since.
+    fired: bool,
+}
+
+impl TriggerHistory {
+    /// Records an attempt to trigger the routine at `now`. Returns the reason
+    /// if the trigger should be suppressed.
+    fn try_trigger(
+        &mut self,
+        routine: &Routine,
+        now: DateTime<Local>,
+    ) -> Result<(), SuppressReason> {
+        let prev_attempt = self.last_attempt.replace(now);
+        let elapsed_since = |prev: DateTime<Local>| (now - prev).to_std().unwrap_or_default();
+        let within =
+            |prev: Option<DateTime<Local>>, duration: Option<Duration>| match (prev, duration) {
+                (Some(prev), Some(duration)) => elapsed_since(prev) < duration,
+                _ => false,
+            };
+
+        if let Some(rate_limit) = &routine.rate_limit {
+            while let Some(fired) = self.recent_fires.front() {
+                if elapsed_since(*fired) < rate_limit.period {
+                    break;
+                }
+                self.recent_fires.pop_front();
+            }
+        }
+
+        let reason = if within(self.last_fired, routine.cooldown) {
+            Some(SuppressReason::Cooldown)
+        } else if within(prev_attempt, routine.debounce) {
+            Some(SuppressReason::Debounce)
+        } else if matches!(&routine.rate_limit, Some(r) if self.recent_fires.len() >= r.max) {
+            Some(SuppressReason::RateLimit)
+        } else {
+            None
+        };
+
+        if let Some(reason) = reason {
+            self.suppressed_count += 1;
+            self.fired = false;
+            return Err(reason);
+        }
+
+        self.fired = true;
+        self.last_fired = Some(now);
+        if routine.rate_limit.is_some() {
+            self.recent_fires.push_back(now);
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Clone)]
 pub struct Routines {
     config: RoutinesConfig,
     event_tx: TxEventChannel,
     prev_triggered_routine_ids: Option<HashSet<RoutineId>>,
+    rule_timers: RuleTimers,
+    trigger_history: HashMap<RoutineId, TriggerHistory>,
+    disabled_routine_ids: HashSet<RoutineId>,
+    clock: Arc<dyn Clock>,
 }
 
 impl Routines {
-    pub fn new(config: RoutinesConfig, event_tx: TxEventChannel) -> Self {
+    pub fn new(config: RoutinesConfig, event_tx: TxEventChannel, clock: Arc<dyn Clock>) -> Self {
         Routines {
             config,
             event_tx,
             prev_triggered_routine_ids: Default::default(),
+            rule_timers: Default::default(),
+            trigger_history: Default::default(),
+            disabled_routine_ids: Default::default(),
+            clock,
         }
     }
 
@@ -48,6 +159,123 @@ impl Routines {
         }
     }
 
+    /// Replaces the routines config, e.g. after a config reload. Trigger
+    /// history of routines that still exist is retained. Newly added routines
+    /// that already match are treated as previously triggered, so that they
+    /// don't fire just because they were loaded. Pending `for` timers are
+    /// restarted, as the rules they refer to may have changed.
+    pub fn set_config(
+        &mut self,
+        config: RoutinesConfig,
+        devices: &Devices,
+        groups: &Groups,
+        expr: &Expr,
+    ) {
+        let new_routine_ids: HashSet<RoutineId> = config
+            .keys()
+            .filter(|routine_id| !self.config.contains_key(routine_id))
+            .cloned()
+            .collect();
+
+        self.config = config;
+        self.rule_timers.clear();
+        self.trigger_history
+            .retain(|routine_id, _| self.config.contains_key(routine_id));
+
+        if let Some(prev_triggered_routine_ids) = self.prev_triggered_routine_ids.clone() {
+            let triggered_routine_ids = self.get_triggered_routine_ids(devices, groups, expr);
+
+            let prev_triggered_routine_ids = prev_triggered_routine_ids
+                .into_iter()
+                .filter(|routine_id| self.config.contains_key(routine_id))
+                .chain(
+                    triggered_routine_ids
+                        .intersection(&new_routine_ids)
+                        .cloned(),
+                )
+                .collect();
+
+            self.prev_triggered_routine_ids = Some(prev_triggered_routine_ids);
+        }
+    }
+
+    /// Restores enabled state of routines from the DB.
+    pub async fn refresh_db_state(&mut self) {
+        let routine_states = db_get_routine_states().await.unwrap_or_default();
+
+        self.disabled_routine_ids = routine_states
+            .into_iter()
+            .filter(|(_, enabled)| !enabled)
+            .map(|(routine_id, _)| routine_id)
+            .collect();
+    }
+
+    /// Enables or disables a routine. Like newly added routines in
+    /// [Routines::set_config], a routine that already matches when it's
+    /// enabled is treated as previously triggered, so that it doesn't fire
+    /// just because it was enabled.
+    pub async fn set_enabled(
+        &mut self,
+        routine_id: &RoutineId,
+        enabled: bool,
+        devices: &Devices,
+        groups: &Groups,
+        expr: &Expr,
+    ) -> Result<()> {
+        let routine = self
+            .config
+            .get(routine_id)
+            .with_context(|| eyre!("Routine not found"))?;
+
+        if enabled {
+            info!("Enabling routine {name}", name = routine.name);
+            self.disabled_routine_ids.remove(routine_id);
+
+            let triggered = self.prev_triggered_routine_ids.is_some()
+                && self
+                    .get_triggered_routine_ids(devices, groups, expr)
+                    .contains(routine_id);
+
+            if let Some(prev_triggered_routine_ids) = &mut self.prev_triggered_routine_ids {
+                if triggered {
+                    prev_triggered_routine_ids.insert(routine_id.clone());
+                }
+            }
+        } else {
+            info!("Disabling routine {name}", name = routine.name);
+            self.disabled_routine_ids.insert(routine_id.clone());
+        }
+
+        if let Err(e) = db_store_routine_enabled(routine_id, enabled).await {
+            warn!("Failed to persist enabled state of routine {routine_id}: {e}");
+        }
+
+        Ok(())
+    }
+
+    /// Periodically re-evaluates routines, as rules with a `for` duration may
+    /// become triggered without any state changing.
+    pub fn handle_tick(&mut self, devices: &Devices, groups: &Groups, expr: &Expr) {
+        let has_timed_rules = self
+            .config
+            .values()
+            .any(|routine| routine.rules.iter().any(Rule::is_timed));
+
+        if !has_timed_rules {
+            return;
+        }
+
+        let matching_actions = self.find_newly_triggered_actions(devices, groups, expr);
+
+        for action in matching_actions {
+            self.event_tx.send(Event::Action(action.clone()));
+        }
+    }
+
+    pub fn has_routine(&self, routine_id: &RoutineId) -> bool {
+        self.config.contains_key(routine_id)
+    }
+
     pub fn force_trigger_routine(&self, routine_id: &RoutineId) -> Result<()> {
         let routine = self
             .config
@@ -78,6 +306,19 @@ impl Routines {
             return vec![];
         }
 
+        self.find_newly_triggered_actions(devices, groups, expr)
+    }
+
+    /// Returns actions of all routines that are triggered now, but were not
+    /// triggered when routines were previously evaluated. Also returns
+    /// `on_release` actions of routines that previously fired, but are no
+    /// longer triggered.
+    fn find_newly_triggered_actions(
+        &mut self,
+        devices: &Devices,
+        groups: &Groups,
+        expr: &Expr,
+    ) -> Actions {
         let prev_triggered_routine_ids =
             self.prev_triggered_routine_ids.clone().unwrap_or_default();
         let new_triggered_routine_ids = self.get_triggered_routine_ids(devices, groups, expr);
@@ -91,61 +332,188 @@ impl Routines {
         let triggered_routine_ids =
             new_triggered_routine_ids.difference(&prev_triggered_routine_ids);
 
-        triggered_routine_ids.flat_map(|id| {
+        // Routines that were triggered previously, but aren't anymore
+        let released_routine_ids =
+            prev_triggered_routine_ids.difference(&new_triggered_routine_ids);
+
+        let now = self.clock.now();
+
+        let release_actions: Actions = released_routine_ids
+            .flat_map(|id| {
+                let history = self.trigger_history.get_mut(id)?;
+
+                // Suppressed triggers don't have anything to release, and
+                // disabling a routine should not cause any actions to run
+                if !std::mem::take(&mut history.fired) || self.disabled_routine_ids.contains(id) {
+                    return None;
+                }
+
+                let routine = self.config.get(id)?;
+                routine.on_release.clone()
+            })
+            .flatten()
+            .collect();
+
+        let trigger_actions: Actions = triggered_routine_ids.flat_map(|id| {
                 let routine = self
                     .config
                     .get(id)
                     .expect("Expected triggered_routine_ids to only contain ids of routines existing in the RoutinesConfig");
+
+                let history = self.trigger_history.entry(id.clone()).or_default();
+
+                if let Err(reason) = history.try_trigger(routine, now) {
+                    info!(
+                        "Suppressed trigger of routine {name} due to {reason} ({count} suppressed triggers so far)",
+                        name = routine.name,
+                        count = history.suppressed_count
+                    );
+
+                    return vec![];
+                }
+
+                history.trigger_count += 1;
+                history.last_triggered = Some(now.with_timezone(&Utc));
+
                 routine.actions.clone()
             })
-            .collect()
+            .collect();
+
+        [release_actions, trigger_actions].concat()
     }
 
     /// Returns a set of routine ids that are currently triggered with the given
     /// state.
     fn get_triggered_routine_ids(
-        &self,
+        &mut self,
         devices: &Devices,
         groups: &Groups,
         expr: &Expr,
     ) -> HashSet<RoutineId> {
-        let eval_context = expr.get_context();
+        let ctx = RuleContext {
+            devices,
+            groups,
+            eval_context: expr.get_context(),
+            now: self.clock.now(),
+        };
 
-        let triggered_routine_ids: HashSet<RoutineId> = self
-            .config
-            .iter()
-            .filter(|(_, routine)| is_routine_triggered(devices, groups, routine, eval_context))
-            .map(|(routine_id, _)| routine_id.clone())
-            .collect();
+        let mut triggered_routine_ids = HashSet::new();
 
-        triggered_routine_ids
-    }
-}
+        for (routine_id, routine) in &self.config {
+            if self.disabled_routine_ids.contains(routine_id) {
+                continue;
+            }
 
-/// Returns true if all rules of the given routine are triggered.
-fn is_routine_triggered(
-    devices: &Devices,
-    groups: &Groups,
-    routine: &Routine,
-    eval_context: &HashMapContext,
-) -> bool {
-    if routine.rules.is_empty() {
-        return false;
-    }
+            let statuses = evaluate_routine(&ctx, routine_id, routine, &mut self.rule_timers);
 
-    routine.rules.iter().all(|rule| {
-        let result = is_rule_triggered(devices, groups, rule, eval_context);
-        match result {
-            Ok(result) => result,
-            Err(error) => {
+            if let Some(error) = find_error(&statuses) {
                 error!(
                     "Error while checking routine {name}: {error}",
                     name = routine.name
                 );
-                false
+
+                let history = self.trigger_history.entry(routine_id.clone()).or_default();
+                history.last_error = Some(error.to_string());
+            } else if let Some(history) = self.trigger_history.get_mut(routine_id) {
+                history.last_error = None;
+            }
+
+            if is_all_triggered(&statuses) {
+                triggered_routine_ids.insert(routine_id.clone());
             }
         }
-    })
+
+        triggered_routine_ids
+    }
+
+    /// Returns the current status of all routines and their rules.
+    ///
+    /// Rules are evaluated against current state, without affecting timers of
+    /// rules with a `for` duration.
+    pub fn get_status(
+        &self,
+        devices: &Devices,
+        groups: &Groups,
+        expr: &Expr,
+    ) -> Vec<RoutineStatus> {
+        let ctx = RuleContext {
+            devices,
+            groups,
+            eval_context: expr.get_context(),
+            now: self.clock.now(),
+        };
+
+        let mut timers = self.rule_timers.clone();
+        let prev_triggered_routine_ids =
+            self.prev_triggered_routine_ids.clone().unwrap_or_default();
+
+        let mut statuses: Vec<RoutineStatus> = self
+            .config
+            .iter()
+            .map(|(routine_id, routine)| {
+                let rules = evaluate_routine(&ctx, routine_id, routine, &mut timers);
+                let history = self
+                    .trigger_history
+                    .get(routine_id)
+                    .cloned()
+                    .unwrap_or_default();
+
+                RoutineStatus {
+                    id: routine_id.clone(),
+                    name: routine.name.clone(),
+                    enabled: !self.disabled_routine_ids.contains(routine_id),
+                    triggered: prev_triggered_routine_ids.contains(routine_id),
+                    rules,
+                    last_triggered: history.last_triggered,
+                    last_error: history.last_error,
+                    trigger_count: history.trigger_count,
+                    suppressed_count: history.suppressed_count,
+                }
+            })
+            .collect();
+
+        statuses.sort_by(|a, b| a.id.to_string().cmp(&b.id.to_string()));
+
+        statuses
+    }
+}
+
+/// State and point in time that rules are evaluated against.
+struct RuleContext<'a> {
+    devices: &'a Devices,
+    groups: &'a Groups,
+    eval_context: &'a HashMapContext,
+    now: DateTime<Local>,
+}
+
+/// Evaluates all rules of the given routine. Every rule is evaluated (instead
+/// of stopping at the first one that doesn't match) so that timers of all
+/// rules stay up to date.
+fn evaluate_routine(
+    ctx: &RuleContext,
+    routine_id: &RoutineId,
+    routine: &Routine,
+    timers: &mut RuleTimers,
+) -> Vec<RuleStatus> {
+    routine
+        .rules
+        .iter()
+        .enumerate()
+        .map(|(index, rule)| evaluate_rule(ctx, rule, timers, (routine_id.clone(), vec![index])))
+        .collect()
+}
+
+/// Returns true if there is at least one rule, and all rules are triggered.
+fn is_all_triggered(statuses: &[RuleStatus]) -> bool {
+    !statuses.is_empty() && statuses.iter().all(|status| status.triggered)
+}
+
+/// Returns the first error encountered while evaluating rules, including
+/// nested rules.
+fn find_error(statuses: &[RuleStatus]) -> Option<&str> {
+    statuses
+        .iter()
+        .find_map(|status| status.error.as_deref().or_else(|| find_error(&status.any)))
 }
 
 /// Returns true if rule state matches device state
@@ -153,8 +521,15 @@ fn compare_rule_device_state(rule: &Rule, device: &Device) -> Result<bool> {
     let sensor_state: Option<&SensorDevice> = device.get_sensor_state();
 
     match rule {
-        Rule::Any(_) | Rule::EvalExpr(_) => {
-            unreachable!("compare_rule_device_state() cannot be called for Any or EvalExpr rules");
+        Rule::Any(_)
+        | Rule::Time(_)
+        | Rule::Weekday(_)
+        | Rule::Date(_)
+        | Rule::Expr(_)
+        | Rule::EvalExpr(_) => {
+            unreachable!(
+                "compare_rule_device_state() can only be called for Sensor, Device or Group rules"
+            );
         }
         // Check for sensor value matches
         Rule::Sensor(rule) => match (&rule.state, sensor_state) {
@@ -193,22 +568,67 @@ fn compare_rule_device_state(rule: &Rule, device: &Device) -> Result<bool> {
     }
 }
 
-/// Returns true if rule is triggered
-fn is_rule_triggered(
-    devices: &Devices,
-    groups: &Groups,
+/// Evaluates whether rule is triggered, i.e. the rule matches and has been
+/// matching for at least its `for` duration (if any)
+fn evaluate_rule(
+    ctx: &RuleContext,
     rule: &Rule,
-    eval_context: &HashMapContext,
+    timers: &mut RuleTimers,
+    path: RulePath,
+) -> RuleStatus {
+    let mut any = vec![];
+    let result = is_rule_matching(ctx, rule, timers, &path, &mut any);
+
+    let result = match (rule.for_duration(), result) {
+        (Some(for_duration), Ok(true)) => {
+            let started = timers.entry(path).or_insert(ctx.now);
+            Ok((ctx.now - *started).to_std().unwrap_or_default() >= for_duration)
+        }
+        (Some(_), result) => {
+            timers.remove(&path);
+            result
+        }
+        (None, result) => result,
+    };
+
+    RuleStatus {
+        triggered: matches!(result, Ok(true)),
+        error: result.err().map(|e| e.to_string()),
+        any,
+    }
+}
+
+/// Returns true if rule currently matches. Statuses of rules nested within
+/// `any` rules are written to `any`.
+fn is_rule_matching(
+    ctx: &RuleContext,
+    rule: &Rule,
+    timers: &mut RuleTimers,
+    path: &RulePath,
+    any: &mut Vec<RuleStatus>,
 ) -> Result<bool> {
+    let RuleContext {
+        devices,
+        groups,
+        eval_context,
+        now,
+    } = ctx;
+
     // Try finding matching device
     let devices = match rule {
         Rule::Any(AnyRule { any: rules }) => {
-            let any_triggered = rules
+            // Evaluate all nested rules so that their timers stay up to date
+            *any = rules
                 .iter()
-                .map(|rule| is_rule_triggered(devices, groups, rule, eval_context))
-                .any(|result| matches!(result, Ok(true)));
+                .enumerate()
+                .map(|(index, rule)| {
+                    let (routine_id, indices) = path;
+                    let path = (routine_id.clone(), [indices.as_slice(), &[index]].concat());
+                    evaluate_rule(ctx, rule, timers, path)
+                })
+                .collect();
 
-            return Ok(any_triggered);
+            return Ok(any.iter().any(|status| status.triggered));
         }
         Rule::Sensor(rule) => {
             vec![devices
@@ -221,8 +641,15 @@ fn is_rule_triggered(
                 .ok_or(eyre!("Could not find matching device for rule: {rule:?}"))?]
         }
         Rule::Group(rule) => groups.find_group_devices(devices.get_state(), &rule.group_id),
+        Rule::Time(_) | Rule::Weekday(_) | Rule::Date(_) => {
+            return rule.matches_time(now);
+        }
+        Rule::Expr(rule) => {
+            let result = rule.expr.eval_boolean_with_context(*eval_context)?;
+            return Ok(result);
+        }
         Rule::EvalExpr(expr) => {
-            let result = expr.eval_boolean_with_context(eval_context)?;
+            let result = expr.eval_boolean_with_context(*eval_context)?;
             return Ok(result);
         }
     };
@@ -242,3 +669,272 @@ fn is_rule_triggered(
 
     Ok(true)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::scenes::Scenes;
+    use crate::types::{
+        action::Action,
+        device::{DeviceData, DeviceId, DeviceRef},
+        event::{mk_event_channel, RxEventChannel, TxEventChannel},
+        integration::IntegrationId,
+        rule::{RateLimit, RoutineDescriptor, SensorRule, TimeRange, TimeRule, WeekdayRule},
+    };
+    use crate::utils::{
+        cli::Cli,
+        clock::{FixedClock, SystemClock},
+    };
+    use chrono::{TimeZone, Weekday};
+    use clap::Parser;
+
+    fn mk_routine() -> Routine {
+        Routine {
+            name: "Test routine".to_string(),
+            rules: vec![],
+            actions: vec![],
+            on_release: None,
+            cooldown: None,
+            debounce: None,
+            rate_limit: None,
+        }
+    }
+
+    /// Routine that triggers on every day of the week, disabling itself.
+    fn mk_always_routine(routine_id: &RoutineId) -> Routine {
+        Routine {
+            rules: vec![Rule::Weekday(WeekdayRule {
+                weekdays: vec![
+                    Weekday::Mon,
+                    Weekday::Tue,
+                    Weekday::Wed,
+                    Weekday::Thu,
+                    Weekday::Fri,
+                    Weekday::Sat,
+                    Weekday::Sun,
+                ],
+            })],
+            actions: vec![Action::DisableRoutine(RoutineDescriptor {
+                routine_id: routine_id.clone(),
+            })],
+            ..mk_routine()
+        }
+    }
+
+    /// Empty state for routines to be evaluated against.
+    fn mk_state() -> (TxEventChannel, RxEventChannel, Devices, Groups, Expr) {
+        let (event_tx, event_rx) = mk_event_channel();
+        let cli = Cli::parse_from(["homectl-server", "--dry-run"]);
+        let devices = Devices::new(event_tx.clone(), &cli);
+
+        (
+            event_tx,
+            event_rx,
+            devices,
+            Groups::default(),
+            Expr::new(Arc::new(SystemClock)),
+        )
+    }
+
+    #[test]
+    fn test_cooldown() {
+        let routine = Routine {
+            cooldown: Some(Duration::from_secs(60)),
+            ..mk_routine()
+        };
+        let start = Local::now();
+        let mut history = TriggerHistory::default();
+
+        assert_eq!(history.try_trigger(&routine, start), Ok(()));
+        assert_eq!(
+            history.try_trigger(&routine, start + Duration::from_secs(30)),
+            Err(SuppressReason::Cooldown)
+        );
+        assert_eq!(
+            history.try_trigger(&routine, start + Duration::from_secs(60)),
+            Ok(())
+        );
+        assert_eq!(history.suppressed_count, 1);
+    }
+
+    #[test]
+    fn test_debounce() {
+        let routine = Routine {
+            debounce: Some(Duration::from_secs(10)),
+            ..mk_routine()
+        };
+        let start = Local::now();
+        let mut history = TriggerHistory::default();
+
+        assert_eq!(history.try_trigger(&routine, start), Ok(()));
+
+        // Each suppressed attempt extends the debounce window
+        for secs in [5, 10, 15] {
+            assert_eq!(
+                history.try_trigger(&routine, start + Duration::from_secs(secs)),
+                Err(SuppressReason::Debounce)
+            );
+        }
+
+        assert_eq!(
+            history.try_trigger(&routine, start + Duration::from_secs(30)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let routine = Routine {
+            rate_limit: Some(RateLimit {
+                max: 2,
+                period: Duration::from_secs(60),
+            }),
+            ..mk_routine()
+        };
+        let start = Local::now();
+        let mut history = TriggerHistory::default();
+
+        assert_eq!(history.try_trigger(&routine, start), Ok(()));
+        assert_eq!(
+            history.try_trigger(&routine, start + Duration::from_secs(1)),
+            Ok(())
+        );
+        assert_eq!(
+            history.try_trigger(&routine, start + Duration::from_secs(2)),
+            Err(SuppressReason::RateLimit)
+        );
+        assert_eq!(
+            history.try_trigger(&routine, start + Duration::from_secs(61)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_set_config_does_not_fire_new_routines() {
+        let (event_tx, _event_rx, devices, groups, expr) = mk_state();
+
+        let mut routines = Routines::new(Default::default(), event_tx, Arc::new(SystemClock));
+        assert!(routines
+            .find_newly_triggered_actions(&devices, &groups, &expr)
+            .is_empty());
+
+        let routine_id: RoutineId = "always".parse().unwrap();
+        let config = RoutinesConfig::from([(routine_id.clone(), mk_always_routine(&routine_id))]);
+
+        routines.set_config(config, &devices, &groups, &expr);
+
+        assert!(routines
+            .find_newly_triggered_actions(&devices, &groups, &expr)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_cooldown_uses_clock() {
+        let (event_tx, _event_rx, devices, groups, expr) = mk_state();
+
+        let routine_id: RoutineId = "morning".parse().unwrap();
+        let morning = Routine {
+            rules: vec![Rule::Time(TimeRule {
+                time: TimeRange {
+                    after: "10:00".parse().unwrap(),
+                    before: "10:10".parse().unwrap(),
+                },
+            })],
+            cooldown: Some(Duration::from_secs(2 * 24 * 60 * 60)),
+            ..mk_always_routine(&routine_id)
+        };
+        let config = RoutinesConfig::from([(routine_id, morning)]);
+
+        let at = |day, hour, min| Local.with_ymd_and_hms(2024, 1, day, hour, min, 0).unwrap();
+        let clock = Arc::new(FixedClock::new(at(1, 9, 0)));
+        let mut routines = Routines::new(config, event_tx, clock.clone());
+
+        let mut fires_at = |day, hour, min| {
+            clock.set(at(day, hour, min));
+            !routines
+                .find_newly_triggered_actions(&devices, &groups, &expr)
+                .is_empty()
+        };
+
+        assert!(!fires_at(1, 9, 0));
+        assert!(fires_at(1, 10, 0));
+        assert!(!fires_at(1, 10, 20));
+
+        // Triggered again the next day, but within the cooldown
+        assert!(!fires_at(2, 10, 5));
+        assert!(!fires_at(2, 10, 20));
+
+        assert!(fires_at(3, 10, 5));
+    }
+
+    #[tokio::test]
+    async fn test_enable_does_not_fire_matching_routine() {
+        let (event_tx, _event_rx, devices, groups, expr) = mk_state();
+
+        let routine_id: RoutineId = "always".parse().unwrap();
+        let config = RoutinesConfig::from([(routine_id.clone(), mk_always_routine(&routine_id))]);
+        let mut routines = Routines::new(config, event_tx, Arc::new(SystemClock));
+
+        assert!(!routines
+            .find_newly_triggered_actions(&devices, &groups, &expr)
+            .is_empty());
+
+        routines
+            .set_enabled(&routine_id, false, &devices, &groups, &expr)
+            .await
+            .unwrap();
+        assert!(routines
+            .find_newly_triggered_actions(&devices, &groups, &expr)
+            .is_empty());
+
+        routines
+            .set_enabled(&routine_id, true, &devices, &groups, &expr)
+            .await
+            .unwrap();
+        assert!(routines
+            .find_newly_triggered_actions(&devices, &groups, &expr)
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_last_error_is_cleared() {
+        let (event_tx, _event_rx, mut devices, groups, expr) = mk_state();
+        let integration_id = IntegrationId::from("test".to_string());
+
+        let routine_id: RoutineId = "sensor".parse().unwrap();
+        let routine = Routine {
+            rules: vec![Rule::Sensor(SensorRule {
+                state: SensorDevice::Boolean { value: true },
+                for_duration: None,
+                device_ref: DeviceRef::new_with_name(integration_id.clone(), "Sensor".to_string()),
+            })],
+            ..mk_routine()
+        };
+        let config = RoutinesConfig::from([(routine_id, routine)]);
+        let mut routines = Routines::new(config, event_tx, Arc::new(SystemClock));
+
+        let last_error = |routines: &mut Routines, devices: &Devices| {
+            routines.find_newly_triggered_actions(devices, &groups, &expr);
+            routines.get_status(devices, &groups, &expr)[0]
+                .last_error
+                .clone()
+        };
+
+        // The sensor hasn't been discovered yet
+        assert!(last_error(&mut routines, &devices).is_some());
+
+        let sensor = Device::new(
+            integration_id,
+            DeviceId::new("sensor"),
+            "Sensor".to_string(),
+            DeviceData::Sensor(SensorDevice::Boolean { value: false }),
+            None,
+        );
+        devices
+            .handle_external_state_update(&sensor, &Scenes::default())
+            .await
+            .unwrap();
+
+        assert_eq!(last_error(&mut routines, &devices), None);
+    }
+}
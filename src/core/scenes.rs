@@ -26,7 +26,7 @@ use super::{
     },
     groups::Groups,
 };
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
 
 #[derive(Clone, Default, Debug)]
 pub struct Scenes {
@@ -244,6 +244,12 @@ impl Scenes {
         }
     }
 
+    /// Replaces scenes defined in the config file. Call
+    /// [Scenes::force_invalidate] afterwards to recompute derived state.
+    pub fn set_config(&mut self, config: ScenesConfig) {
+        self.config = config;
+    }
+
     pub async fn refresh_db_scenes(&mut self) {
         let db_scenes = db_get_scenes().await.unwrap_or_default();
         self.db_scenes = db_scenes;
@@ -280,6 +286,17 @@ impl Scenes {
         Ok(())
     }
 
+    /// Removes stored scene overrides of a device that no longer exists.
+    pub async fn remove_device_overrides(&mut self, device_key: &DeviceKey) -> Result<()> {
+        for (scene_id, overrides) in self.db_scene_overrides.iter_mut() {
+            if overrides.remove(device_key).is_some() {
+                db_store_scene_overrides(scene_id, overrides).await?;
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn has_override(&self, device: &Device) -> bool {
         let scene_id = device.get_scene_id();
 
@@ -293,6 +310,19 @@ impl Scenes {
             .unwrap_or_default()
     }
 
+    pub fn get_db_scene_ids(&self) -> BTreeSet<SceneId> {
+        self.db_scenes.keys().cloned().collect()
+    }
+
+    pub fn is_db_scene(&self, scene_id: &SceneId) -> bool {
+        self.db_scenes.contains_key(scene_id)
+    }
+
+    /// Scenes defined in the config file can't be modified at runtime.
+    pub fn is_config_scene(&self, scene_id: &SceneId) -> bool {
+        self.config.contains_key(scene_id) && !self.is_db_scene(scene_id)
+    }
+
     pub fn get_scenes(&self) -> ScenesConfig {
         let mut scenes = self.config.clone();
         scenes.extend(self.db_scenes.clone());
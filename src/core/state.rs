@@ -1,13 +1,10 @@
 use crate::types::{
-    color::ColorMode,
-    device::DevicesState,
-    event::TxEventChannel,
-    websockets::{StateUpdate, WebSocketResponse},
+    color::ColorMode, device::DevicesState, event::TxEventChannel, websockets::StateUpdate,
 };
 
 use super::{
-    devices::Devices, expr::Expr, groups::Groups, integrations::Integrations, routines::Routines,
-    scenes::Scenes, ui::Ui, websockets::WebSockets,
+    auth::Auth, devices::Devices, expr::Expr, groups::Groups, history::DeviceHistory,
+    integrations::Integrations, routines::Routines, scenes::Scenes, ui::Ui, websockets::WebSockets,
 };
 
 #[derive(Clone)]
@@ -22,20 +19,13 @@ pub struct AppState {
     pub expr: Expr,
     pub ws: WebSockets,
     pub ui: Ui,
+    pub history: DeviceHistory,
+    pub auth: Auth,
 }
 
 impl AppState {
-    /// Sends current state over WebSockets. If user_id is omitted, the message
-    /// is broadcast to all connected peers.
-    pub async fn send_state_ws(&self, user_id: Option<usize>) {
-        // Make sure there are any users connected before broadcasting
-        if user_id.is_none() {
-            let num_users = self.ws.num_users().await;
-            if num_users == 0 {
-                return;
-            }
-        }
-
+    /// Snapshot of the state that is shown to WebSocket peers.
+    pub fn mk_state_update(&self) -> StateUpdate {
         let devices = self.devices.get_state();
         let scenes = self.scenes.get_flattened_scenes().clone();
         let groups = self.groups.get_flattened_groups().clone();
@@ -53,13 +43,100 @@ impl AppState {
 
         let ui_state = self.ui.get_state().clone();
 
-        let message = WebSocketResponse::State(StateUpdate {
+        StateUpdate {
+            seq: 0,
             devices: DevicesState(devices_converted),
             scenes,
             groups,
             ui_state,
-        });
+        }
+    }
+
+    /// Recomputes groups, scenes and the expression context from scratch, for
+    /// when the set of devices or their names has changed.
+    pub fn force_invalidate(&mut self) {
+        self.groups.force_invalidate(&self.devices);
+
+        self.expr
+            .invalidate(self.devices.get_state(), &self.groups, &self.scenes);
+
+        self.scenes
+            .force_invalidate(&self.devices, &self.groups, self.expr.get_context());
+
+        self.expr
+            .invalidate(self.devices.get_state(), &self.groups, &self.scenes);
+    }
+
+    /// Reloads scenes from the DB after they have been modified, and sends
+    /// the resulting changes to WebSocket peers.
+    pub async fn refresh_db_scenes(&mut self) {
+        self.scenes.refresh_db_scenes().await;
+        self.scenes
+            .force_invalidate(&self.devices, &self.groups, self.expr.get_context());
+        self.send_state_ws(None).await;
+    }
+
+    /// Sends current state over WebSockets. If user_id is given, that peer
+    /// receives a full snapshot. Otherwise changes since the previous
+    /// broadcast are sent to all connected peers as patches.
+    pub async fn send_state_ws(&self, user_id: Option<usize>) {
+        match user_id {
+            Some(user_id) => self.ws.send_snapshot(user_id, self.mk_state_update()).await,
+            None => {
+                // Make sure there are any users connected before broadcasting
+                if self.ws.num_users().await == 0 {
+                    self.ws.forget_prev_state().await;
+                    return;
+                }
+
+                self.ws.broadcast_changes(self.mk_state_update()).await
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+impl AppState {
+    /// State with the given config, no integrations or devices and API
+    /// authentication disabled, along with the receiving end of its event
+    /// channel.
+    pub fn mk_test_state(
+        groups: crate::types::group::GroupsConfig,
+        scenes: crate::types::scene::ScenesConfig,
+        routines: crate::types::rule::RoutinesConfig,
+    ) -> (
+        std::sync::Arc<tokio::sync::RwLock<AppState>>,
+        crate::types::event::RxEventChannel,
+    ) {
+        use crate::utils::{cli::Cli, clock::SystemClock};
+        use clap::Parser;
+
+        let (event_tx, event_rx) = crate::types::event::mk_event_channel();
+        let cli = Cli::parse_from(["homectl-server", "--dry-run"]);
+        let storage = super::storage::Storage::new(
+            std::env::temp_dir().join("homectl-test-storage.json"),
+            &cli,
+        );
+
+        let mut state = AppState {
+            warming_up: false,
+            integrations: Integrations::new(event_tx.clone(), &cli, storage),
+            groups: Groups::new(groups),
+            scenes: Scenes::new(scenes),
+            devices: Devices::new(event_tx.clone(), &cli),
+            rules: Routines::new(routines, event_tx.clone(), std::sync::Arc::new(SystemClock)),
+            event_tx,
+            expr: Expr::new(std::sync::Arc::new(SystemClock)),
+            ws: WebSockets::default(),
+            ui: Ui::new(),
+            history: DeviceHistory::new(None, &cli),
+            auth: Auth::new(&Default::default()),
+        };
+        state.force_invalidate();
 
-        self.ws.send(user_id, &message).await;
+        (
+            std::sync::Arc::new(tokio::sync::RwLock::new(state)),
+            event_rx,
+        )
     }
 }
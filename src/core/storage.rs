@@ -0,0 +1,201 @@
+use std::{collections::BTreeMap, path::PathBuf, sync::Arc};
+
+use color_eyre::Result;
+use eyre::Context;
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::sync::Mutex;
+
+use crate::db::{
+    actions::{db_delete_integration_value, db_get_integration_value, db_store_integration_value},
+    get_db_connection,
+};
+use crate::types::integration::IntegrationId;
+use crate::utils::cli::Cli;
+
+type StorageFileData = BTreeMap<IntegrationId, BTreeMap<String, serde_json::Value>>;
+
+/// Key/value storage for integrations. Values are stored in PostgreSQL if a
+/// database is configured, otherwise in a local JSON file.
+#[derive(Clone)]
+pub struct Storage {
+    path: PathBuf,
+
+    /// Contents of the storage file, read on first use
+    file_data: Arc<Mutex<Option<StorageFileData>>>,
+    dry_run: bool,
+}
+
+impl Storage {
+    pub fn new(path: PathBuf, cli: &Cli) -> Self {
+        Storage {
+            path,
+            file_data: Default::default(),
+            dry_run: cli.dry_run,
+        }
+    }
+
+    /// Returns a handle to the part of the storage that belongs to the given
+    /// integration.
+    pub fn for_integration(&self, integration_id: &IntegrationId) -> IntegrationStorage {
+        IntegrationStorage {
+            integration_id: integration_id.clone(),
+            storage: self.clone(),
+        }
+    }
+
+    async fn read_file(&self) -> Result<StorageFileData> {
+        match tokio::fs::read_to_string(&self.path).await {
+            Ok(contents) => serde_json::from_str(&contents)
+                .wrap_err_with(|| format!("Failed to parse {}", self.path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Default::default()),
+            Err(e) => Err(e).wrap_err_with(|| format!("Failed to read {}", self.path.display())),
+        }
+    }
+
+    /// Writes the whole file to a temporary file first, so that a crash
+    /// halfway through doesn't lose previously stored values.
+    async fn write_file(&self, data: &StorageFileData) -> Result<()> {
+        let contents = serde_json::to_string_pretty(data)?;
+        let tmp_path = self.path.with_extension("json.tmp");
+
+        tokio::fs::write(&tmp_path, contents)
+            .await
+            .wrap_err_with(|| format!("Failed to write {}", tmp_path.display()))?;
+        tokio::fs::rename(&tmp_path, &self.path)
+            .await
+            .wrap_err_with(|| format!("Failed to write {}", self.path.display()))?;
+
+        Ok(())
+    }
+
+    async fn get(
+        &self,
+        integration_id: &IntegrationId,
+        key: &str,
+    ) -> Result<Option<serde_json::Value>> {
+        if get_db_connection().await.is_ok() {
+            return db_get_integration_value(integration_id, key).await;
+        }
+
+        let mut file_data = self.file_data.lock().await;
+        if file_data.is_none() {
+            *file_data = Some(self.read_file().await?);
+        }
+
+        Ok(file_data
+            .as_ref()
+            .and_then(|data| data.get(integration_id))
+            .and_then(|values| values.get(key))
+            .cloned())
+    }
+
+    /// Stores a value, or deletes it if value is None.
+    async fn set(
+        &self,
+        integration_id: &IntegrationId,
+        key: &str,
+        value: Option<serde_json::Value>,
+    ) -> Result<()> {
+        if self.dry_run {
+            debug!("(dry run) would store {integration_id} integration value {key}: {value:?}");
+            return Ok(());
+        }
+
+        if get_db_connection().await.is_ok() {
+            return match value {
+                Some(value) => db_store_integration_value(integration_id, key, &value).await,
+                None => db_delete_integration_value(integration_id, key).await,
+            };
+        }
+
+        let mut file_data = self.file_data.lock().await;
+        let mut data = match file_data.take() {
+            Some(data) => data,
+            None => self.read_file().await?,
+        };
+
+        match value {
+            Some(value) => {
+                data.entry(integration_id.clone())
+                    .or_default()
+                    .insert(key.to_string(), value);
+            }
+            None => {
+                if let Some(values) = data.get_mut(integration_id) {
+                    values.remove(key);
+                    if values.is_empty() {
+                        data.remove(integration_id);
+                    }
+                }
+            }
+        }
+
+        let result = self.write_file(&data).await;
+        *file_data = Some(data);
+
+        result
+    }
+}
+
+/// Key/value storage namespaced to a single integration, passed to
+/// integrations when they are constructed. Values survive restarts of both
+/// the integration and homectl.
+#[derive(Clone)]
+pub struct IntegrationStorage {
+    integration_id: IntegrationId,
+    storage: Storage,
+}
+
+impl IntegrationStorage {
+    pub async fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        let value = self.storage.get(&self.integration_id, key).await?;
+
+        value
+            .map(serde_json::from_value)
+            .transpose()
+            .wrap_err_with(|| format!("Failed to deserialize stored value {key}"))
+    }
+
+    pub async fn set<T: Serialize>(&self, key: &str, value: &T) -> Result<()> {
+        let value = serde_json::to_value(value)?;
+        self.storage
+            .set(&self.integration_id, key, Some(value))
+            .await
+    }
+
+    pub async fn delete(&self, key: &str) -> Result<()> {
+        self.storage.set(&self.integration_id, key, None).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[tokio::test]
+    async fn test_file_storage() {
+        let path =
+            std::env::temp_dir().join(format!("homectl-storage-test-{}.json", std::process::id()));
+        let cli = Cli::parse_from(["homectl-server"]);
+
+        let storage = Storage::new(path.clone(), &cli);
+        let cron = storage.for_integration(&IntegrationId::from("cron".to_string()));
+        let timer = storage.for_integration(&IntegrationId::from("timer".to_string()));
+
+        cron.set("enabled", &true).await.unwrap();
+        timer.set("enabled", &false).await.unwrap();
+        assert_eq!(cron.get::<bool>("enabled").await.unwrap(), Some(true));
+        assert_eq!(timer.get::<bool>("enabled").await.unwrap(), Some(false));
+
+        // Values are read back from the file by a fresh instance
+        let storage = Storage::new(path.clone(), &cli);
+        let cron = storage.for_integration(&IntegrationId::from("cron".to_string()));
+        assert_eq!(cron.get::<bool>("enabled").await.unwrap(), Some(true));
+
+        cron.delete("enabled").await.unwrap();
+        assert_eq!(cron.get::<bool>("enabled").await.unwrap(), None);
+
+        std::fs::remove_file(path).ok();
+    }
+}
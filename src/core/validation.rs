@@ -0,0 +1,700 @@
+use std::{
+    collections::{BTreeSet, HashSet},
+    fmt::{self, Display},
+};
+
+use evalexpr::Node;
+
+use crate::db::{
+    actions::{db_get_devices, db_get_scenes},
+    init_db,
+};
+use crate::types::{
+    action::{Action, Actions},
+    device::{DeviceKey, DeviceRef, DevicesState},
+    dim::DimDescriptor,
+    group::GroupId,
+    integration::{CustomActionDescriptor, IntegrationId},
+    rule::{
+        AnyRule, DeviceRule, ExprRule, ForceTriggerRoutineDescriptor, GroupRule, RoutineDescriptor,
+        RoutineId, Rule, Rules, SensorRule,
+    },
+    scene::{ActivateSceneDescriptor, CycleScenesDescriptor, SceneDeviceConfig, SceneId},
+};
+
+use super::{
+    config::{config_path, read_config, Config},
+    expr::name_to_evalexpr,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    /// The config refers to something that does not exist.
+    Error,
+
+    /// The config refers to a device that has not been seen yet. It may
+    /// still be discovered later, so this is not an error.
+    Warning,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigIssue {
+    pub severity: Severity,
+
+    /// Location of the issue within the config, e.g. `routines.foo.rules[0]`
+    pub path: String,
+    pub message: String,
+}
+
+impl Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+
+        write!(f, "{severity}: {}: {}", self.path, self.message)
+    }
+}
+
+/// Cross-checks references between config sections, such as routines
+/// activating scenes or scenes referring to groups.
+struct Validator<'a> {
+    config: &'a Config,
+
+    /// Scenes stored in the DB may be referenced like any other scene
+    db_scene_ids: &'a BTreeSet<SceneId>,
+
+    /// Devices we know of, used for warning about unknown device names
+    devices: &'a DevicesState,
+
+    issues: Vec<ConfigIssue>,
+}
+
+impl Validator<'_> {
+    fn error(&mut self, path: &str, message: String) {
+        self.issues.push(ConfigIssue {
+            severity: Severity::Error,
+            path: path.to_string(),
+            message,
+        });
+    }
+
+    fn warning(&mut self, path: &str, message: String) {
+        self.issues.push(ConfigIssue {
+            severity: Severity::Warning,
+            path: path.to_string(),
+            message,
+        });
+    }
+
+    fn has_integration(&self, integration_id: &IntegrationId) -> bool {
+        self.config
+            .integrations
+            .as_ref()
+            .is_some_and(|integrations| integrations.contains_key(integration_id))
+    }
+
+    fn has_group(&self, group_id: &GroupId) -> bool {
+        self.config
+            .groups
+            .as_ref()
+            .is_some_and(|groups| groups.contains_key(group_id))
+    }
+
+    fn scene_ids(&self) -> impl Iterator<Item = &SceneId> {
+        self.config
+            .scenes
+            .iter()
+            .flat_map(|scenes| scenes.keys())
+            .chain(self.db_scene_ids.iter())
+    }
+
+    fn has_scene(&self, scene_id: &SceneId) -> bool {
+        self.scene_ids().any(|id| id == scene_id)
+    }
+
+    fn has_routine(&self, routine_id: &RoutineId) -> bool {
+        self.config
+            .routines
+            .as_ref()
+            .is_some_and(|routines| routines.contains_key(routine_id))
+    }
+
+    /// Returns false if no devices of the integration are known, in which
+    /// case we can't tell whether a device exists or not.
+    fn knows_devices_of(&self, integration_id: &IntegrationId) -> bool {
+        self.devices
+            .0
+            .keys()
+            .any(|key| &key.integration_id == integration_id)
+    }
+
+    fn check_integration(&mut self, path: &str, integration_id: &IntegrationId) -> bool {
+        if self.has_integration(integration_id) {
+            true
+        } else {
+            self.error(path, format!("Unknown integration '{integration_id}'"));
+            false
+        }
+    }
+
+    fn check_group(&mut self, path: &str, group_id: &GroupId) {
+        if !self.has_group(group_id) {
+            self.error(path, format!("Unknown group '{group_id}'"));
+        }
+    }
+
+    fn check_scene(&mut self, path: &str, scene_id: &SceneId) {
+        if !self.has_scene(scene_id) {
+            self.error(path, format!("Unknown scene '{scene_id}'"));
+        }
+    }
+
+    fn check_routine(&mut self, path: &str, routine_id: &RoutineId) {
+        if !self.has_routine(routine_id) {
+            self.error(path, format!("Unknown routine '{routine_id}'"));
+        }
+    }
+
+    fn check_device_name(&mut self, path: &str, integration_id: &IntegrationId, name: &str) {
+        if !self.check_integration(path, integration_id) || !self.knows_devices_of(integration_id) {
+            return;
+        }
+
+        let found = self
+            .devices
+            .0
+            .values()
+            .any(|device| &device.integration_id == integration_id && device.name == name);
+
+        if !found {
+            self.warning(
+                path,
+                format!("No device named '{name}' has been seen in integration '{integration_id}'"),
+            );
+        }
+    }
+
+    fn check_device_key(&mut self, path: &str, device_key: &DeviceKey) {
+        let integration_id = &device_key.integration_id;
+
+        if !self.check_integration(path, integration_id) || !self.knows_devices_of(integration_id) {
+            return;
+        }
+
+        if !self.devices.0.contains_key(device_key) {
+            self.warning(
+                path,
+                format!("No device with id '{device_key}' has been seen"),
+            );
+        }
+    }
+
+    fn check_device_keys(&mut self, path: &str, device_keys: &Option<Vec<DeviceKey>>) {
+        for (i, device_key) in device_keys.iter().flatten().enumerate() {
+            self.check_device_key(&format!("{path}.device_keys[{i}]"), device_key);
+        }
+    }
+
+    fn check_group_keys(&mut self, path: &str, group_keys: &Option<Vec<GroupId>>) {
+        for (i, group_id) in group_keys.iter().flatten().enumerate() {
+            self.check_group(&format!("{path}.group_keys[{i}]"), group_id);
+        }
+    }
+
+    fn check_device_ref(&mut self, path: &str, device_ref: &DeviceRef) {
+        match device_ref {
+            DeviceRef::Id(id_ref) => {
+                self.check_device_key(path, &id_ref.clone().into_device_key());
+            }
+            DeviceRef::Name(name_ref) => {
+                self.check_device_name(path, &name_ref.integration_id, &name_ref.name);
+            }
+        }
+    }
+
+    fn check_expr(&mut self, path: &str, expr: &Node) {
+        let written: HashSet<&str> = expr.iter_write_variable_identifiers().collect();
+        let variables: BTreeSet<&str> = expr
+            .iter_read_variable_identifiers()
+            .chain(expr.iter_write_variable_identifiers())
+            .collect();
+
+        for variable in variables {
+            let segments: Vec<&str> = variable.split('.').collect();
+
+            match segments.as_slice() {
+                ["devices", integration_id, name, ..] => {
+                    let integration_id = IntegrationId::from(integration_id.to_string());
+                    if !self.has_integration(&integration_id) {
+                        self.error(
+                            path,
+                            format!("Unknown integration '{integration_id}' in '{variable}'"),
+                        );
+                        continue;
+                    }
+
+                    let found = !self.knows_devices_of(&integration_id)
+                        || self.devices.0.values().any(|device| {
+                            device.integration_id == integration_id
+                                && name_to_evalexpr(&device.name) == *name
+                        });
+
+                    if !found {
+                        self.warning(
+                            path,
+                            format!("No device matching '{variable}' has been seen"),
+                        );
+                    }
+                }
+                ["groups", group_id, ..] => {
+                    if !self.has_group(&GroupId(group_id.to_string())) {
+                        self.error(path, format!("Unknown group '{group_id}' in '{variable}'"));
+                    }
+                }
+                ["scenes", scene_id, ..] => {
+                    let found = self
+                        .scene_ids()
+                        .any(|id| name_to_evalexpr(&id.to_string()) == *scene_id);
+
+                    if !found {
+                        self.error(path, format!("Unknown scene '{scene_id}' in '{variable}'"));
+                    }
+                }
+                ["solar", ..] => {
+                    let has_location = self
+                        .config
+                        .core
+                        .as_ref()
+                        .is_some_and(|core| core.location.is_some());
+
+                    if !has_location {
+                        self.error(
+                            path,
+                            format!("'{variable}' requires [core.location] to be configured"),
+                        );
+                    }
+                }
+                // Local variables assigned within the expression itself
+                [_] if written.contains(variable) => {}
+                _ => self.error(path, format!("Unknown variable '{variable}'")),
+            }
+        }
+    }
+
+    fn check_scene_descriptor(&mut self, path: &str, descriptor: &ActivateSceneDescriptor) {
+        self.check_scene(&format!("{path}.scene_id"), &descriptor.scene_id);
+        self.check_device_keys(path, &descriptor.device_keys);
+        self.check_group_keys(path, &descriptor.group_keys);
+    }
+
+    fn check_scene_device_config(&mut self, path: &str, config: &SceneDeviceConfig) {
+        match config {
+            SceneDeviceConfig::DeviceLink(link) => self.check_device_ref(path, &link.device_ref),
+            SceneDeviceConfig::SceneLink(descriptor) => {
+                self.check_scene_descriptor(path, descriptor)
+            }
+            SceneDeviceConfig::DeviceState(_) => {}
+        }
+    }
+
+    fn check_action(&mut self, path: &str, action: &Action) {
+        match action {
+            Action::ActivateScene(descriptor) => self.check_scene_descriptor(path, descriptor),
+            Action::CycleScenes(CycleScenesDescriptor {
+                scenes,
+                device_keys,
+                group_keys,
+                ..
+            }) => {
+                for (i, descriptor) in scenes.iter().enumerate() {
+                    self.check_scene_descriptor(&format!("{path}.scenes[{i}]"), descriptor);
+                }
+                self.check_device_keys(path, device_keys);
+                self.check_group_keys(path, group_keys);
+            }
+            Action::Custom(CustomActionDescriptor { integration_id, .. }) => {
+                self.check_integration(&format!("{path}.integration_id"), integration_id);
+            }
+            Action::Dim(DimDescriptor {
+                device_keys,
+                group_keys,
+                ..
+            }) => {
+                self.check_device_keys(path, device_keys);
+                self.check_group_keys(path, group_keys);
+            }
+            Action::ForceTriggerRoutine(ForceTriggerRoutineDescriptor { routine_id })
+            | Action::EnableRoutine(RoutineDescriptor { routine_id })
+            | Action::DisableRoutine(RoutineDescriptor { routine_id }) => {
+                self.check_routine(&format!("{path}.routine_id"), routine_id);
+            }
+            Action::SetDeviceState(device) => {
+                self.check_device_key(path, &device.get_device_key());
+            }
+            Action::ToggleDeviceOverride { device_keys, .. } => {
+                self.check_device_keys(path, &Some(device_keys.clone()));
+            }
+            Action::Ui(_) => {}
+            Action::EvalExpr(expr) => self.check_expr(path, expr),
+        }
+    }
+
+    fn check_actions(&mut self, path: &str, actions: &Actions) {
+        for (i, action) in actions.iter().enumerate() {
+            self.check_action(&format!("{path}[{i}]"), action);
+        }
+    }
+
+    fn check_rules(&mut self, path: &str, rules: &Rules) {
+        for (i, rule) in rules.iter().enumerate() {
+            let path = format!("{path}[{i}]");
+
+            match rule {
+                Rule::Sensor(SensorRule { device_ref, .. }) => {
+                    self.check_device_ref(&path, device_ref);
+                }
+                Rule::Device(DeviceRule {
+                    device_ref, scene, ..
+                }) => {
+                    self.check_device_ref(&path, device_ref);
+                    if let Some(scene_id) = scene {
+                        self.check_scene(&format!("{path}.scene"), scene_id);
+                    }
+                }
+                Rule::Group(GroupRule {
+                    group_id, scene, ..
+                }) => {
+                    self.check_group(&format!("{path}.group_id"), group_id);
+                    if let Some(scene_id) = scene {
+                        self.check_scene(&format!("{path}.scene"), scene_id);
+                    }
+                }
+                Rule::Any(AnyRule { any }) => self.check_rules(&format!("{path}.any"), any),
+                Rule::Expr(ExprRule { expr, .. }) | Rule::EvalExpr(expr) => {
+                    self.check_expr(&path, expr)
+                }
+                Rule::Time(_) | Rule::Weekday(_) | Rule::Date(_) => {}
+            }
+        }
+    }
+
+    /// Returns the chain of group links leading from `group_id` to `target`,
+    /// if there is one.
+    fn find_group_link_chain(
+        &self,
+        group_id: &GroupId,
+        target: &GroupId,
+        visited: &mut HashSet<GroupId>,
+    ) -> Option<Vec<GroupId>> {
+        let group = self.config.groups.as_ref()?.get(group_id)?;
+
+        for link in group.groups.iter().flatten() {
+            if &link.group_id == target {
+                return Some(vec![link.group_id.clone()]);
+            }
+
+            if visited.insert(link.group_id.clone()) {
+                if let Some(mut chain) = self.find_group_link_chain(&link.group_id, target, visited)
+                {
+                    chain.insert(0, link.group_id.clone());
+                    return Some(chain);
+                }
+            }
+        }
+
+        None
+    }
+
+    fn validate(&mut self) {
+        let config = self.config;
+
+        for (group_id, group) in config.groups.iter().flatten() {
+            let path = format!("groups.{group_id}");
+
+            for (i, device_ref) in group.devices.iter().flatten().enumerate() {
+                self.check_device_ref(&format!("{path}.devices[{i}]"), device_ref);
+            }
+            for (i, link) in group.groups.iter().flatten().enumerate() {
+                self.check_group(&format!("{path}.groups[{i}]"), &link.group_id);
+            }
+        }
+
+        // Groups containing themselves would make flattening groups recurse
+        // forever
+        for group_id in config.groups.iter().flat_map(|groups| groups.keys()) {
+            if let Some(chain) = self.find_group_link_chain(group_id, group_id, &mut HashSet::new())
+            {
+                let chain = std::iter::once(group_id)
+                    .chain(chain.iter())
+                    .map(|id| id.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" -> ");
+
+                self.error(
+                    &format!("groups.{group_id}"),
+                    format!("Group contains itself: {chain}"),
+                );
+            }
+        }
+
+        for (scene_id, scene) in config.scenes.iter().flatten() {
+            let path = format!("scenes.{scene_id}");
+
+            for (integration_id, devices) in scene.devices.iter().flat_map(|d| d.0.iter()) {
+                for (name, device_config) in devices {
+                    let path = format!("{path}.devices.{integration_id}.{name}");
+                    self.check_device_name(&path, integration_id, name);
+                    self.check_scene_device_config(&path, device_config);
+                }
+            }
+            for (group_id, device_config) in scene.groups.iter().flat_map(|g| g.0.iter()) {
+                let path = format!("{path}.groups.{group_id}");
+                self.check_group(&path, group_id);
+                self.check_scene_device_config(&path, device_config);
+            }
+            if let Some(expr) = &scene.expr {
+                self.check_expr(&format!("{path}.expr"), expr);
+            }
+        }
+
+        let mut routines: Vec<_> = config.routines.iter().flatten().collect();
+        routines.sort_by(|(a, _), (b, _)| a.to_string().cmp(&b.to_string()));
+
+        for (routine_id, routine) in routines {
+            let path = format!("routines.{routine_id}");
+
+            self.check_rules(&format!("{path}.rules"), &routine.rules);
+            self.check_actions(&format!("{path}.actions"), &routine.actions);
+            if let Some(on_release) = &routine.on_release {
+                self.check_actions(&format!("{path}.on_release"), on_release);
+            }
+        }
+    }
+}
+
+/// Cross-checks all references to integrations, devices, groups, scenes and
+/// routines within the config, as well as variables used in expressions.
+pub fn validate_config(
+    config: &Config,
+    db_scene_ids: &BTreeSet<SceneId>,
+    devices: &DevicesState,
+) -> Vec<ConfigIssue> {
+    let mut validator = Validator {
+        config,
+        db_scene_ids,
+        devices,
+        issues: vec![],
+    };
+
+    validator.validate();
+    validator.issues
+}
+
+/// Implements the `check-config` subcommand. Prints any problems found in
+/// the config and returns whether the config is valid.
+pub async fn check_config() -> bool {
+    let path = config_path();
+
+    if !path.exists() {
+        eprintln!("error: {} not found", path.display());
+        return false;
+    }
+
+    let config = match read_config() {
+        Ok((config, _)) => config,
+        Err(e) => {
+            eprintln!("error: {e:#}");
+            return false;
+        }
+    };
+
+    // Scenes and devices stored in the DB are used for resolving references,
+    // if a database is configured
+    init_db().await;
+    let db_scene_ids = db_get_scenes()
+        .await
+        .unwrap_or_default()
+        .into_keys()
+        .collect();
+    let devices = DevicesState(
+        db_get_devices()
+            .await
+            .unwrap_or_default()
+            .into_iter()
+            .collect(),
+    );
+
+    let issues = validate_config(&config, &db_scene_ids, &devices);
+
+    for issue in &issues {
+        eprintln!("{issue}");
+    }
+
+    let errors = issues
+        .iter()
+        .filter(|issue| issue.severity == Severity::Error)
+        .count();
+
+    if errors == 0 {
+        println!("{}: OK", path.display());
+        true
+    } else {
+        eprintln!("{}: {errors} error(s) found", path.display());
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::device::{Device, DeviceData, DeviceId, SensorDevice};
+
+    fn validate(contents: &str) -> Vec<String> {
+        let config: Config = toml::from_str(contents).unwrap();
+
+        validate_config(&config, &BTreeSet::new(), &DevicesState::default())
+            .into_iter()
+            .map(|issue| issue.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_valid_config() {
+        let issues = validate(
+            r#"
+[integrations.dummy]
+plugin = "dummy"
+
+[groups.kitchen]
+name = "Kitchen"
+devices = [{ integration_id = "dummy", name = "Lamp" }]
+
+[scenes.evening]
+name = "Evening"
+groups = { kitchen = { power = true } }
+
+[routines.motion]
+name = "Motion"
+rules = [{ group_id = "kitchen", power = false }, "devices.dummy.sensor.value == true"]
+actions = [{ action = "ActivateScene", scene_id = "evening" }]
+"#,
+        );
+
+        assert_eq!(issues, Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_unknown_references() {
+        let issues = validate(
+            r#"
+[groups.kitchen]
+name = "Kitchen"
+devices = [{ integration_id = "hue", name = "Lamp" }]
+groups = [{ group_id = "pantry" }]
+
+[routines.motion]
+name = "Motion"
+rules = [{ any = [{ group_id = "hallway", power = true }] }]
+actions = [
+  { action = "ActivateScene", scene_id = "evening" },
+  { action = "DisableRoutine", routine_id = "other" },
+]
+"#,
+        );
+
+        assert_eq!(
+            issues,
+            vec![
+                "error: groups.kitchen.devices[0]: Unknown integration 'hue'",
+                "error: groups.kitchen.groups[0]: Unknown group 'pantry'",
+                "error: routines.motion.rules[0].any[0].group_id: Unknown group 'hallway'",
+                "error: routines.motion.actions[0].scene_id: Unknown scene 'evening'",
+                "error: routines.motion.actions[1].routine_id: Unknown routine 'other'",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_group_cycle() {
+        let issues = validate(
+            r#"
+[groups.a]
+name = "A"
+groups = [{ group_id = "b" }]
+
+[groups.b]
+name = "B"
+groups = [{ group_id = "a" }]
+"#,
+        );
+
+        assert_eq!(
+            issues,
+            vec![
+                "error: groups.a: Group contains itself: a -> b -> a",
+                "error: groups.b: Group contains itself: b -> a -> b",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expr_variables() {
+        let issues = validate(
+            r#"
+[routines.night]
+name = "Night"
+rules = ["solar.is_day == false", "x = 1; x == 1", "foo.bar == 1", "groups.missing.power"]
+actions = []
+"#,
+        );
+
+        assert_eq!(
+            issues,
+            vec![
+                "error: routines.night.rules[0]: 'solar.is_day' requires [core.location] to be configured",
+                "error: routines.night.rules[2]: Unknown variable 'foo.bar'",
+                "error: routines.night.rules[3]: Unknown group 'missing' in 'groups.missing.power'",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_db_scenes_and_unseen_devices() {
+        let config: Config = toml::from_str(
+            r#"
+[integrations.dummy]
+plugin = "dummy"
+
+[routines.motion]
+name = "Motion"
+rules = [{ integration_id = "dummy", name = "Missing", power = true }]
+actions = [{ action = "ActivateScene", scene_id = "from_db" }]
+"#,
+        )
+        .unwrap();
+
+        let db_scene_ids = BTreeSet::from([SceneId::new("from_db".to_string())]);
+        let device = Device::new(
+            "dummy".parse().unwrap(),
+            DeviceId::new("lamp"),
+            "Lamp".to_string(),
+            DeviceData::Sensor(SensorDevice::Boolean { value: true }),
+            None,
+        );
+        let devices = DevicesState([(device.get_device_key(), device)].into());
+
+        let issues: Vec<String> = validate_config(&config, &db_scene_ids, &devices)
+            .into_iter()
+            .map(|issue| issue.to_string())
+            .collect();
+
+        assert_eq!(
+            issues,
+            vec![
+                "warning: routines.motion.rules[0]: No device named 'Missing' has been seen in integration 'dummy'"
+            ]
+        );
+    }
+}
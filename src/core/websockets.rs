@@ -1,31 +1,330 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::Arc,
+};
 
-use crate::types::websockets::WebSocketResponse;
+use crate::types::{
+    action::Action,
+    device::{Device, DeviceKey},
+    event::{Event, EventSource},
+    group::{FlattenedGroupsConfig, GroupId},
+    websockets::{
+        DevicePatch, EventMessage, GroupPatch, LogMessage, MessageKind, ScenePatch, StateUpdate,
+        Subscription, UiStatePatch, WebSocketResponse,
+    },
+};
 use tokio::sync::{
     mpsc::{self, UnboundedSender},
-    RwLock,
+    Mutex, RwLock,
 };
 
-type Users = Arc<RwLock<HashMap<usize, mpsc::UnboundedSender<warp::ws::Message>>>>;
+struct User {
+    sender: mpsc::UnboundedSender<warp::ws::Message>,
+    subscription: Subscription,
+
+    /// Sequence number of the latest patch sent to this user.
+    seq: u64,
+}
+
+type Users = Arc<RwLock<HashMap<usize, User>>>;
 
 #[derive(Clone, Default)]
 pub struct WebSockets {
     users: Users,
+
+    /// State as of the latest broadcast, used for computing the next patches.
+    prev_state: Arc<Mutex<Option<StateUpdate>>>,
+}
+
+fn wants_kind(subscription: &Subscription, kind: MessageKind) -> bool {
+    match &subscription.kinds {
+        Some(kinds) => kinds.contains(&kind),
+        None => kind == MessageKind::State,
+    }
+}
+
+fn has_entity_filter(subscription: &Subscription) -> bool {
+    subscription.devices.is_some()
+        || subscription.groups.is_some()
+        || subscription.integrations.is_some()
+}
+
+fn includes_device(
+    subscription: &Subscription,
+    device_key: &DeviceKey,
+    groups: &FlattenedGroupsConfig,
+) -> bool {
+    if !has_entity_filter(subscription) {
+        return true;
+    }
+
+    subscription
+        .devices
+        .iter()
+        .flatten()
+        .any(|key| key == device_key)
+        || subscription
+            .integrations
+            .iter()
+            .flatten()
+            .any(|integration_id| *integration_id == device_key.integration_id)
+        || subscription.groups.iter().flatten().any(|group_id| {
+            groups
+                .0
+                .get(group_id)
+                .is_some_and(|group| group.device_keys.contains(device_key))
+        })
+}
+
+fn includes_group(subscription: &Subscription, group_id: &GroupId) -> bool {
+    !has_entity_filter(subscription)
+        || subscription
+            .groups
+            .iter()
+            .flatten()
+            .any(|id| id == group_id)
+}
+
+/// Removes devices and groups that aren't part of the subscription.
+fn filter_state(subscription: &Subscription, state: &StateUpdate) -> StateUpdate {
+    let mut state = state.clone();
+
+    if has_entity_filter(subscription) {
+        let groups = state.groups.clone();
+
+        state
+            .devices
+            .0
+            .retain(|device_key, _| includes_device(subscription, device_key, &groups));
+        state
+            .groups
+            .0
+            .retain(|group_id, _| includes_group(subscription, group_id));
+    }
+
+    state
+}
+
+/// Picks the patches that are part of the subscription. Device inclusion is
+/// checked against group memberships both before and after the change:
+/// devices that joined a subscribed group are sent in full even if they
+/// didn't change, and devices that left one are removed from the client.
+fn filter_patches(
+    subscription: &Subscription,
+    patches: &[WebSocketResponse],
+    prev: &StateUpdate,
+    next: &StateUpdate,
+) -> Vec<WebSocketResponse> {
+    let was_included = |device_key| includes_device(subscription, device_key, &prev.groups);
+    let is_included = |device_key| includes_device(subscription, device_key, &next.groups);
+
+    let mut patched_devices = HashSet::new();
+    let mut filtered = vec![];
+
+    for patch in patches {
+        match patch {
+            WebSocketResponse::DevicePatch(device_patch) => {
+                let device_key = &device_patch.device_key;
+                patched_devices.insert(device_key);
+
+                if is_included(device_key) {
+                    filtered.push(patch.clone());
+                } else if was_included(device_key) {
+                    filtered.push(WebSocketResponse::DevicePatch(DevicePatch {
+                        device: None,
+                        ..device_patch.clone()
+                    }));
+                }
+            }
+            WebSocketResponse::GroupPatch(group_patch) => {
+                if includes_group(subscription, &group_patch.group_id) {
+                    filtered.push(patch.clone());
+                }
+            }
+            _ => filtered.push(patch.clone()),
+        }
+    }
+
+    if prev.groups.0 == next.groups.0 {
+        return filtered;
+    }
+
+    for (device_key, device) in &next.devices.0 {
+        if patched_devices.contains(device_key) {
+            continue;
+        }
+
+        let device = match (was_included(device_key), is_included(device_key)) {
+            (false, true) => Some(device.clone()),
+            (true, false) => None,
+            _ => continue,
+        };
+
+        filtered.push(WebSocketResponse::DevicePatch(DevicePatch {
+            seq: 0,
+            device_key: device_key.clone(),
+            device,
+        }));
+    }
+
+    filtered
+}
+
+fn set_seq(patch: &mut WebSocketResponse, seq: u64) {
+    match patch {
+        WebSocketResponse::DevicePatch(patch) => patch.seq = seq,
+        WebSocketResponse::ScenePatch(patch) => patch.seq = seq,
+        WebSocketResponse::GroupPatch(patch) => patch.seq = seq,
+        WebSocketResponse::UiStatePatch(patch) => patch.seq = seq,
+        WebSocketResponse::State(state) => state.seq = seq,
+        WebSocketResponse::Event(_) | WebSocketResponse::Log(_) | WebSocketResponse::Error(_) => {}
+    }
+}
+
+fn device_key_of_event(event: &Event) -> Option<DeviceKey> {
+    match event {
+        Event::ExternalStateUpdate { device }
+        | Event::SetExternalState { device }
+        | Event::SetInternalState { device, .. }
+        | Event::Action(Action::SetDeviceState(device)) => Some(device.get_device_key()),
+        Event::DeviceRemoved { device_key } | Event::DeviceRenamed { device_key, .. } => {
+            Some(device_key.clone())
+        }
+        _ => None,
+    }
+}
+
+fn send_message(sender: &UnboundedSender<warp::ws::Message>, message: &WebSocketResponse) {
+    let s = serde_json::to_string(message).unwrap();
+    sender.send(warp::ws::Message::text(s)).ok();
+}
+
+/// Returns entries that were added or changed (according to `eq`) in `next`
+/// compared to `prev`, followed by entries that were removed (with a value of
+/// None).
+fn diff_entries<'a, K, V>(
+    prev: impl IntoIterator<Item = (&'a K, &'a V)>,
+    next: impl IntoIterator<Item = (&'a K, &'a V)>,
+    eq: impl Fn(&V, &V) -> bool,
+) -> Vec<(K, Option<V>)>
+where
+    K: Ord + Clone + 'a,
+    V: Clone + 'a,
+{
+    let prev: BTreeMap<&K, &V> = prev.into_iter().collect();
+    let next: BTreeMap<&K, &V> = next.into_iter().collect();
+
+    let changed = next
+        .iter()
+        .filter(|(key, value)| !prev.get(*key).is_some_and(|prev| eq(prev, value)))
+        .map(|(key, value)| ((*key).clone(), Some((*value).clone())));
+
+    let removed = prev
+        .keys()
+        .filter(|key| !next.contains_key(*key))
+        .map(|key| ((*key).clone(), None));
+
+    changed.chain(removed).collect()
+}
+
+/// Device equality for diffing, ignoring `last_seen` so that devices merely
+/// reporting an unchanged state don't cause patches.
+fn device_eq(a: &Device, b: &Device) -> bool {
+    a.id == b.id
+        && a.name == b.name
+        && a.integration_id == b.integration_id
+        && a.data == b.data
+        && a.raw == b.raw
+        && a.available == b.available
+}
+
+/// Computes patches that turn `prev` into `next`. Sequence numbers are
+/// assigned separately for each user when sending.
+fn diff_state(prev: &StateUpdate, next: &StateUpdate) -> Vec<WebSocketResponse> {
+    let mut patches = vec![];
+
+    for (device_key, device) in diff_entries(&prev.devices.0, &next.devices.0, device_eq) {
+        patches.push(WebSocketResponse::DevicePatch(DevicePatch {
+            seq: 0,
+            device_key,
+            device,
+        }));
+    }
+
+    for (scene_id, scene) in diff_entries(&prev.scenes.0, &next.scenes.0, PartialEq::eq) {
+        patches.push(WebSocketResponse::ScenePatch(ScenePatch {
+            seq: 0,
+            scene_id,
+            scene,
+        }));
+    }
+
+    for (group_id, group) in diff_entries(&prev.groups.0, &next.groups.0, PartialEq::eq) {
+        patches.push(WebSocketResponse::GroupPatch(GroupPatch {
+            seq: 0,
+            group_id,
+            group,
+        }));
+    }
+
+    for (key, value) in diff_entries(&prev.ui_state, &next.ui_state, PartialEq::eq) {
+        patches.push(WebSocketResponse::UiStatePatch(UiStatePatch {
+            seq: 0,
+            key,
+            value,
+        }));
+    }
+
+    patches
 }
 
 impl WebSockets {
     pub async fn user_connected(&self, user_id: usize, sender: UnboundedSender<warp::ws::Message>) {
-        self.users.write().await.insert(user_id, sender);
+        let user = User {
+            sender,
+            subscription: Subscription::default(),
+            seq: 0,
+        };
+
+        self.users.write().await.insert(user_id, user);
     }
 
     pub async fn user_disconnected(&self, user_id: usize) {
         self.users.write().await.remove(&user_id);
     }
 
+    /// Sends a close frame to every connected user and forgets about them.
+    /// Dropping the senders ends each connection's forwarding task once the
+    /// close frame has been written.
+    pub async fn close_all(&self) {
+        let users = std::mem::take(&mut *self.users.write().await);
+
+        for user in users.values() {
+            user.sender
+                .send(warp::ws::Message::close_with(
+                    1001u16,
+                    "Server shutting down",
+                ))
+                .ok();
+        }
+    }
+
     pub async fn num_users(&self) -> usize {
         self.users.read().await.len()
     }
 
+    /// Replaces the user's subscription. Returns whether the user wants to
+    /// receive state messages.
+    pub async fn subscribe(&self, user_id: usize, subscription: Subscription) -> bool {
+        let wants_state = wants_kind(&subscription, MessageKind::State);
+
+        if let Some(user) = self.users.write().await.get_mut(&user_id) {
+            user.subscription = subscription;
+        }
+
+        wants_state
+    }
+
     pub async fn send(&self, user_id: Option<usize>, message: &WebSocketResponse) -> Option<()> {
         let s = serde_json::to_string(message).unwrap();
         let msg = warp::ws::Message::text(s);
@@ -34,17 +333,323 @@ impl WebSockets {
 
         match user_id {
             Some(user_id) => {
-                let user = users.get(&user_id).cloned()?;
-                user.send(msg).ok()
+                let user = users.get(&user_id)?;
+                user.sender.send(msg).ok()
             }
             None => {
-                let users = users.clone();
-                for (_, user) in users {
-                    user.send(msg.clone()).ok();
+                for user in users.values() {
+                    user.sender.send(msg.clone()).ok();
                 }
 
                 Some(())
             }
         }
     }
+
+    /// Sends a snapshot of the state to the given user, limited to their
+    /// subscription.
+    pub async fn send_snapshot(&self, user_id: usize, state: StateUpdate) {
+        let mut prev_state = self.prev_state.lock().await;
+
+        // Patches are full replacements of individual entries, so they can be
+        // applied on top of any snapshot newer than the previous state
+        if prev_state.is_none() {
+            *prev_state = Some(state.clone());
+        }
+
+        let users = self.users.read().await;
+        let Some(user) = users.get(&user_id) else {
+            return;
+        };
+
+        let mut state = filter_state(&user.subscription, &state);
+        state.seq = user.seq;
+
+        send_message(&user.sender, &WebSocketResponse::State(state));
+    }
+
+    /// Called when nobody is connected. The next user to connect receives a
+    /// snapshot, which becomes the base for later patches.
+    pub async fn forget_prev_state(&self) {
+        *self.prev_state.lock().await = None;
+    }
+
+    /// Sends patches for everything that has changed since the previous
+    /// broadcast to all users subscribed to the changes.
+    pub async fn broadcast_changes(&self, state: StateUpdate) {
+        let mut prev_state = self.prev_state.lock().await;
+        let mut users = self.users.write().await;
+
+        match prev_state.as_ref() {
+            Some(prev) => {
+                let patches = diff_state(prev, &state);
+
+                for user in users.values_mut() {
+                    if !wants_kind(&user.subscription, MessageKind::State) {
+                        continue;
+                    }
+
+                    for mut patch in filter_patches(&user.subscription, &patches, prev, &state) {
+                        user.seq += 1;
+
+                        set_seq(&mut patch, user.seq);
+                        send_message(&user.sender, &patch);
+                    }
+                }
+            }
+            None => {
+                for user in users.values() {
+                    if !wants_kind(&user.subscription, MessageKind::State) {
+                        continue;
+                    }
+
+                    let mut snapshot = filter_state(&user.subscription, &state);
+                    snapshot.seq = user.seq;
+                    send_message(&user.sender, &WebSocketResponse::State(snapshot));
+                }
+            }
+        }
+
+        *prev_state = Some(state);
+    }
+
+    /// Sends a handled event to users subscribed to events. Purely internal
+    /// bookkeeping events are skipped.
+    pub async fn send_event(
+        &self,
+        source: &EventSource,
+        event: &Event,
+        groups: &FlattenedGroupsConfig,
+    ) {
+        if matches!(
+            event,
+            Event::InternalStateUpdate { .. } | Event::WsBroadcastState | Event::Tick
+        ) {
+            return;
+        }
+
+        let users = self.users.read().await;
+        let device_key = device_key_of_event(event);
+        let mut message = None;
+
+        for user in users.values() {
+            if !wants_kind(&user.subscription, MessageKind::Events) {
+                continue;
+            }
+
+            // Events that aren't about a specific device are always included
+            if let Some(device_key) = &device_key {
+                if !includes_device(&user.subscription, device_key, groups) {
+                    continue;
+                }
+            }
+
+            let message = message.get_or_insert_with(|| {
+                WebSocketResponse::Event(Box::new(EventMessage {
+                    source: source.clone(),
+                    event: event.clone(),
+                }))
+            });
+            send_message(&user.sender, message);
+        }
+    }
+
+    /// Sends a log message to users subscribed to logs.
+    pub async fn send_log(&self, log: LogMessage) {
+        let message = WebSocketResponse::Log(log);
+
+        for user in self.users.read().await.values() {
+            if wants_kind(&user.subscription, MessageKind::Logs) {
+                send_message(&user.sender, &message);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{
+        device::{DeviceData, DeviceId, DevicesState, SensorDevice},
+        group::FlattenedGroupConfig,
+    };
+
+    fn mk_state(devices: Vec<(&str, bool)>, ui_state: Vec<(&str, i32)>) -> StateUpdate {
+        let devices = devices
+            .into_iter()
+            .map(|(id, value)| {
+                let device = Device::new(
+                    "test".parse().unwrap(),
+                    DeviceId::new(id),
+                    id.to_string(),
+                    DeviceData::Sensor(SensorDevice::Boolean { value }),
+                    None,
+                );
+
+                (device.get_device_key(), device)
+            })
+            .collect();
+
+        StateUpdate {
+            seq: 0,
+            devices: DevicesState(devices),
+            scenes: Default::default(),
+            groups: Default::default(),
+            ui_state: ui_state
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), serde_json::json!(value)))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_diff_state_unchanged() {
+        let state = mk_state(vec![("a", false)], vec![("x", 1)]);
+
+        assert!(diff_state(&state, &state).is_empty());
+    }
+
+    #[test]
+    fn test_diff_state_heartbeat() {
+        let prev = mk_state(vec![("a", false)], vec![]);
+        let mut next = prev.clone();
+
+        // A device reporting the same state only updates last_seen
+        for device in next.devices.0.values_mut() {
+            device.last_seen = Some(chrono::Utc::now());
+        }
+        assert!(diff_state(&prev, &next).is_empty());
+
+        // Going offline is still a change
+        for device in next.devices.0.values_mut() {
+            device.available = false;
+        }
+        assert_eq!(diff_state(&prev, &next).len(), 1);
+    }
+
+    #[test]
+    fn test_diff_state_patches() {
+        let prev = mk_state(vec![("a", false), ("b", false)], vec![("x", 1)]);
+        let next = mk_state(vec![("a", true), ("c", false)], vec![("x", 2)]);
+
+        let patches = diff_state(&prev, &next);
+        let patches: Vec<String> = patches
+            .iter()
+            .map(|patch| match patch {
+                WebSocketResponse::DevicePatch(p) => {
+                    format!("device {} {}", p.device_key, p.device.is_some())
+                }
+                WebSocketResponse::UiStatePatch(p) => format!("ui {} {:?}", p.key, p.value),
+                _ => panic!("Unexpected patch {patch:?}"),
+            })
+            .collect();
+
+        assert_eq!(
+            patches,
+            vec![
+                "device test/a true",
+                "device test/c true",
+                "device test/b false",
+                "ui x Some(Number(2))",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_subscription_filters() {
+        let mut state = mk_state(vec![("a", false), ("b", false), ("c", false)], vec![]);
+        let group_id = GroupId("room".to_string());
+        state.groups.0.insert(
+            group_id.clone(),
+            FlattenedGroupConfig {
+                name: "Room".to_string(),
+                device_keys: vec![DeviceKey::new("test".parse().unwrap(), DeviceId::new("b"))],
+                hidden: None,
+            },
+        );
+
+        let subscription = Subscription {
+            devices: Some(vec![DeviceKey::new(
+                "test".parse().unwrap(),
+                DeviceId::new("a"),
+            )]),
+            groups: Some(vec![group_id.clone()]),
+            ..Default::default()
+        };
+
+        let filtered = filter_state(&subscription, &state);
+        let device_ids: Vec<String> = filtered
+            .devices
+            .0
+            .keys()
+            .map(|key| key.device_id.to_string())
+            .collect();
+        assert_eq!(device_ids, vec!["a", "b"]);
+        assert!(filtered.groups.0.contains_key(&group_id));
+
+        // Device "b" was removed from the group, the client still needs to
+        // know about the change
+        let mut next = state.clone();
+        next.groups
+            .0
+            .get_mut(&group_id)
+            .unwrap()
+            .device_keys
+            .clear();
+        let patch = WebSocketResponse::DevicePatch(DevicePatch {
+            seq: 0,
+            device_key: DeviceKey::new("test".parse().unwrap(), DeviceId::new("b")),
+            device: None,
+        });
+        let patches = filter_patches(&subscription, &[patch], &state, &next);
+        assert_eq!(patches.len(), 1);
+
+        let subscription = Subscription {
+            integrations: Some(vec!["other".parse().unwrap()]),
+            ..Default::default()
+        };
+        assert!(filter_state(&subscription, &state).devices.0.is_empty());
+        assert!(!wants_kind(&subscription, MessageKind::Events));
+        assert!(wants_kind(&subscription, MessageKind::State));
+    }
+
+    #[test]
+    fn test_group_membership_patches() {
+        let mut prev = mk_state(vec![("a", false), ("b", false)], vec![]);
+        let group_id = GroupId("room".to_string());
+        let device_key = |id| DeviceKey::new("test".parse().unwrap(), DeviceId::new(id));
+        prev.groups.0.insert(
+            group_id.clone(),
+            FlattenedGroupConfig {
+                name: "Room".to_string(),
+                device_keys: vec![device_key("a")],
+                hidden: None,
+            },
+        );
+
+        // Device "b" joins the group and "a" leaves it, neither changes
+        let mut next = prev.clone();
+        next.groups.0.get_mut(&group_id).unwrap().device_keys = vec![device_key("b")];
+
+        let subscription = Subscription {
+            groups: Some(vec![group_id]),
+            ..Default::default()
+        };
+        let patches = filter_patches(&subscription, &diff_state(&prev, &next), &prev, &next);
+        let patches: Vec<String> = patches
+            .iter()
+            .map(|patch| match patch {
+                WebSocketResponse::DevicePatch(p) => {
+                    format!("device {} {}", p.device_key, p.device.is_some())
+                }
+                WebSocketResponse::GroupPatch(p) => format!("group {}", p.group_id),
+                _ => panic!("Unexpected patch {patch:?}"),
+            })
+            .collect();
+
+        assert_eq!(
+            patches,
+            vec!["group room", "device test/a false", "device test/b true"]
+        );
+    }
 }
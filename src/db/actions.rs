@@ -1,9 +1,13 @@
 use std::collections::HashMap;
 
 use super::get_db_connection;
-use crate::types::device::{Device, DeviceData, DeviceKey, DeviceRow};
+use crate::types::auth::ApiScope;
+use crate::types::device::{Device, DeviceData, DeviceHistoryEntry, DeviceKey, DeviceRow};
+use crate::types::integration::IntegrationId;
+use crate::types::rule::RoutineId;
 use crate::types::scene::{SceneConfig, SceneId};
 use crate::types::scene::{SceneDevicesConfig, SceneOverridesConfig, ScenesConfig};
+use chrono::{DateTime, Utc};
 use color_eyre::Result;
 use sqlx::types::Json;
 
@@ -93,6 +97,8 @@ pub async fn db_get_devices() -> Result<HashMap<DeviceKey, Device>> {
                 name: row.name,
                 data: row.state.0,
                 raw: None,
+                last_seen: None,
+                available: true,
             };
 
             (key, device)
@@ -100,6 +106,115 @@ pub async fn db_get_devices() -> Result<HashMap<DeviceKey, Device>> {
         .collect())
 }
 
+pub async fn db_delete_device(key: &DeviceKey) -> Result<bool> {
+    let db = get_db_connection().await?;
+
+    let result = sqlx::query!(
+        r#"
+            delete from devices
+            where integration_id = $1
+              and device_id = $2
+        "#,
+        &key.integration_id.to_string(),
+        &key.device_id.to_string()
+    )
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn db_delete_device_history(key: &DeviceKey) -> Result<u64> {
+    let db = get_db_connection().await?;
+
+    let result = sqlx::query!(
+        r#"
+            delete from device_history
+            where integration_id = $1
+              and device_id = $2
+        "#,
+        &key.integration_id.to_string(),
+        &key.device_id.to_string()
+    )
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+pub async fn db_insert_device_history(device: &Device) -> Result<()> {
+    let db = get_db_connection().await?;
+
+    sqlx::query!(
+        r#"
+            insert into device_history (integration_id, device_id, state, raw)
+            values ($1, $2, $3, $4)
+        "#,
+        &device.integration_id.to_string(),
+        &device.id.to_string(),
+        Json(device.data.clone()) as _,
+        device.raw.clone().map(Json) as _
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn db_get_device_history(
+    key: &DeviceKey,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+) -> Result<Vec<DeviceHistoryEntry>> {
+    let db = get_db_connection().await?;
+
+    let result = sqlx::query!(
+        r#"
+            select
+                state as "state: Json<DeviceData>",
+                raw as "raw: Json<serde_json::Value>",
+                recorded_at
+            from device_history
+            where integration_id = $1
+              and device_id = $2
+              and ($3::timestamptz is null or recorded_at >= $3)
+              and ($4::timestamptz is null or recorded_at <= $4)
+            order by recorded_at
+        "#,
+        &key.integration_id.to_string(),
+        &key.device_id.to_string(),
+        from,
+        to
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(result
+        .into_iter()
+        .map(|row| DeviceHistoryEntry {
+            recorded_at: row.recorded_at,
+            data: row.state.0,
+            raw: row.raw.map(|raw| raw.0),
+        })
+        .collect())
+}
+
+pub async fn db_prune_device_history(older_than: DateTime<Utc>) -> Result<u64> {
+    let db = get_db_connection().await?;
+
+    let result = sqlx::query!(
+        r#"
+            delete from device_history
+            where recorded_at < $1
+        "#,
+        older_than
+    )
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
 pub async fn db_get_scenes() -> Result<ScenesConfig> {
     let db = get_db_connection().await?;
 
@@ -268,3 +383,166 @@ pub async fn db_get_ui_state() -> Result<HashMap<String, serde_json::Value>> {
         .map(|row| (row.key, row.value.0))
         .collect())
 }
+
+pub async fn db_store_routine_enabled(routine_id: &RoutineId, enabled: bool) -> Result<()> {
+    let db = get_db_connection().await?;
+
+    sqlx::query!(
+        r#"
+            insert into routine_state (routine_id, enabled)
+            values ($1, $2)
+
+            on conflict (routine_id)
+            do update set
+                enabled = excluded.enabled
+        "#,
+        routine_id.to_string(),
+        enabled
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn db_get_routine_states() -> Result<HashMap<RoutineId, bool>> {
+    let db = get_db_connection().await?;
+
+    let result = sqlx::query!(
+        r#"
+            select
+                routine_id,
+                enabled
+            from routine_state
+        "#
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(result
+        .into_iter()
+        .map(|row| (RoutineId(row.routine_id), row.enabled))
+        .collect())
+}
+
+pub async fn db_get_api_tokens() -> Result<Vec<(String, String, ApiScope)>> {
+    let db = get_db_connection().await?;
+
+    let result = sqlx::query!(
+        r#"
+            select
+                name,
+                token_hash,
+                scope
+            from api_tokens
+            order by name
+        "#
+    )
+    .fetch_all(db)
+    .await?;
+
+    result
+        .into_iter()
+        .map(|row| Ok((row.name, row.token_hash, row.scope.parse()?)))
+        .collect()
+}
+
+pub async fn db_store_api_token(name: &str, token_hash: &str, scope: ApiScope) -> Result<()> {
+    let db = get_db_connection().await?;
+
+    sqlx::query!(
+        r#"
+            insert into api_tokens (name, token_hash, scope)
+            values ($1, $2, $3)
+        "#,
+        name,
+        token_hash,
+        scope.to_string()
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn db_delete_api_token(name: &str) -> Result<bool> {
+    let db = get_db_connection().await?;
+
+    let result = sqlx::query!(
+        r#"
+            delete from api_tokens
+            where name = $1
+        "#,
+        name
+    )
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn db_get_integration_value(
+    integration_id: &IntegrationId,
+    key: &str,
+) -> Result<Option<serde_json::Value>> {
+    let db = get_db_connection().await?;
+
+    let row = sqlx::query!(
+        r#"
+            select value
+            from integration_storage
+            where integration_id = $1
+              and key = $2
+        "#,
+        &integration_id.to_string(),
+        key
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(row.map(|row| row.value))
+}
+
+pub async fn db_store_integration_value(
+    integration_id: &IntegrationId,
+    key: &str,
+    value: &serde_json::Value,
+) -> Result<()> {
+    let db = get_db_connection().await?;
+
+    sqlx::query!(
+        r#"
+            insert into integration_storage (integration_id, key, value)
+            values ($1, $2, $3)
+
+            on conflict (integration_id, key)
+            do update set
+                value = excluded.value
+        "#,
+        &integration_id.to_string(),
+        key,
+        value
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn db_delete_integration_value(integration_id: &IntegrationId, key: &str) -> Result<()> {
+    let db = get_db_connection().await?;
+
+    sqlx::query!(
+        r#"
+            delete from integration_storage
+            where integration_id = $1
+              and key = $2
+        "#,
+        &integration_id.to_string(),
+        key
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
@@ -1,13 +1,25 @@
 use color_eyre::Result;
 use eyre::eyre;
-use once_cell::sync::OnceCell;
+use once_cell::sync::{Lazy, OnceCell};
 use sqlx::{pool::PoolOptions, PgPool};
-use std::{env, time::Duration};
+use std::{collections::HashMap, env, future::Future, sync::Mutex, time::Duration};
+use tokio::{sync::oneshot, task::JoinSet};
+
+use crate::types::device::DeviceKey;
 
 pub mod actions;
 
 static DB_CONNECTION: OnceCell<PgPool> = OnceCell::new();
 
+/// Writes that have been spawned in the background, and may not have
+/// completed yet.
+static PENDING_WRITES: Lazy<Mutex<JoinSet<()>>> = Lazy::new(Default::default);
+
+/// Completion of the latest write spawned for each device with
+/// [`spawn_device_db_write`].
+static LATEST_DEVICE_WRITES: Lazy<Mutex<HashMap<DeviceKey, oneshot::Receiver<()>>>> =
+    Lazy::new(Default::default);
+
 pub async fn init_db() -> Option<()> {
     let database_url = env::var("DATABASE_URL").ok();
 
@@ -30,8 +42,98 @@ pub async fn init_db() -> Option<()> {
     Some(())
 }
 
+/// Runs a DB write in the background. Unlike a plain `tokio::spawn`, the
+/// write can be waited for with [`flush_db_writes`] before shutting down.
+pub fn spawn_db_write(write: impl Future<Output = ()> + Send + 'static) {
+    let mut pending = PENDING_WRITES.lock().unwrap();
+
+    // Forget about writes that have already completed
+    while pending.try_join_next().is_some() {}
+
+    pending.spawn(write);
+}
+
+/// Like [`spawn_db_write`], but writes concerning the same device run in the
+/// order they were spawned in, so that e.g. a device can't be stored again
+/// after it has been deleted.
+pub fn spawn_device_db_write(
+    device_key: &DeviceKey,
+    write: impl Future<Output = ()> + Send + 'static,
+) {
+    let (done_tx, done_rx) = oneshot::channel();
+    let prev = LATEST_DEVICE_WRITES
+        .lock()
+        .unwrap()
+        .insert(device_key.clone(), done_rx);
+
+    spawn_db_write(async move {
+        if let Some(prev) = prev {
+            prev.await.ok();
+        }
+
+        write.await;
+        done_tx.send(()).ok();
+    });
+}
+
+/// Waits until all writes spawned with [`spawn_db_write`] have completed.
+pub async fn flush_db_writes() {
+    loop {
+        let mut pending = std::mem::take(&mut *PENDING_WRITES.lock().unwrap());
+
+        if pending.is_empty() {
+            return;
+        }
+
+        while pending.join_next().await.is_some() {}
+    }
+}
+
 pub async fn get_db_connection<'a>() -> Result<&'a PgPool> {
     DB_CONNECTION
         .get()
         .ok_or_else(|| eyre!("Not connected to database"))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::device::DeviceId;
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    #[tokio::test]
+    async fn test_flush_db_writes() {
+        let done = Arc::new(AtomicBool::new(false));
+
+        {
+            let done = done.clone();
+            spawn_db_write(async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                done.store(true, Ordering::SeqCst);
+            });
+        }
+
+        flush_db_writes().await;
+        assert!(done.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_device_db_write_order() {
+        let device_key = DeviceKey::new("test".parse().unwrap(), DeviceId::new("order"));
+        let writes = Arc::new(Mutex::new(vec![]));
+
+        for (delay, write) in [(50, "update"), (0, "delete")] {
+            let writes = writes.clone();
+            spawn_device_db_write(&device_key, async move {
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                writes.lock().unwrap().push(write);
+            });
+        }
+
+        flush_db_writes().await;
+        assert_eq!(*writes.lock().unwrap(), vec!["update", "delete"]);
+    }
+}
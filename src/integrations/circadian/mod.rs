@@ -1,5 +1,6 @@
-use crate::utils::from_hh_mm;
+use crate::utils::solar::{get_location, solar_elevation, TimeOfDay};
 use crate::{
+    core::storage::IntegrationStorage,
     types::{
         color::DeviceColor,
         device::{ControllableState, Device, DeviceData, DeviceId, SensorDevice},
@@ -15,20 +16,20 @@ use ordered_float::OrderedFloat;
 use palette::Mix;
 use serde::Deserialize;
 use std::time::Duration;
-use tokio::time;
+use tokio::{task::AbortHandle, time};
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct CircadianConfig {
     device_name: String,
 
-    #[serde(deserialize_with = "from_hh_mm")]
-    day_fade_start: chrono::NaiveTime,
+    /// Either a wall-clock time or relative to a solar event, e.g.
+    /// "sunrise-30m"
+    day_fade_start: TimeOfDay,
     day_fade_duration_hours: i64,
     day_color: DeviceColor,
     day_brightness: Option<f32>,
 
-    #[serde(deserialize_with = "from_hh_mm")]
-    night_fade_start: chrono::NaiveTime,
+    night_fade_start: TimeOfDay,
     night_fade_duration_hours: i64,
     night_color: DeviceColor,
     night_brightness: Option<f32>,
@@ -41,6 +42,7 @@ pub struct Circadian {
     event_tx: TxEventChannel,
     converted_day_color: DeviceColor,
     converted_night_color: DeviceColor,
+    poll_task: Option<AbortHandle>,
 }
 
 #[async_trait]
@@ -50,18 +52,30 @@ impl Integration for Circadian {
         config: &config::Value,
         _cli: &Cli,
         event_tx: TxEventChannel,
+        _storage: IntegrationStorage,
     ) -> Result<Self> {
         let config: CircadianConfig = config
             .clone()
             .try_deserialize()
             .wrap_err("Failed to deserialize config of Circadian integration")?;
 
+        let uses_solar_events = [&config.day_fade_start, &config.night_fade_start]
+            .iter()
+            .any(|t| matches!(t, TimeOfDay::Solar { .. }));
+
+        if uses_solar_events && get_location().is_none() {
+            return Err(eyre!(
+                "Circadian integration {id} uses solar events as fade anchors, but [core.location] is not configured"
+            ));
+        }
+
         Ok(Circadian {
             id: id.clone(),
             config: config.clone(),
             event_tx,
             converted_day_color: config.day_color,
             converted_night_color: config.night_color,
+            poll_task: None,
         })
     }
 
@@ -78,20 +92,43 @@ impl Integration for Circadian {
 
         // FIXME: can we restructure the integrations / devices systems such
         // that polling is not needed here?
-        tokio::spawn(async { poll_sensor(circadian).await });
+        let poll_task = tokio::spawn(async { poll_sensor(circadian).await });
+        self.poll_task = Some(poll_task.abort_handle());
+
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        if let Some(poll_task) = self.poll_task.take() {
+            poll_task.abort();
+        }
 
         Ok(())
     }
 }
 
 fn get_night_fade(circadian: &Circadian) -> f32 {
-    let local = chrono::Local::now().naive_local().time();
+    let now = chrono::Local::now();
+    let local = now.naive_local().time();
+
+    let anchors = (
+        circadian.config.day_fade_start.resolve(now.date_naive()),
+        circadian.config.night_fade_start.resolve(now.date_naive()),
+    );
+
+    let (Some(day_fade_start), Some(night_fade_start)) = anchors else {
+        // The sun doesn't rise or set today (polar day or night), so use
+        // whichever color matches the current position of the sun
+        let is_day = get_location()
+            .map(|location| solar_elevation(&location, &now.to_utc()) > 0.0)
+            .unwrap_or(true);
+
+        return if is_day { 0.0 } else { 1.0 };
+    };
 
-    let day_fade_start = circadian.config.day_fade_start;
     let day_fade_duration = chrono::Duration::hours(circadian.config.day_fade_duration_hours);
     let day_fade_end = day_fade_start + day_fade_duration;
 
-    let night_fade_start = circadian.config.night_fade_start;
     let night_fade_duration = chrono::Duration::hours(circadian.config.night_fade_duration_hours);
     let night_fade_end = night_fade_start + night_fade_duration;
 
@@ -185,5 +222,7 @@ fn mk_circadian_device(circadian: &Circadian) -> Device {
         integration_id: circadian.id.clone(),
         data: state,
         raw: None,
+        last_seen: None,
+        available: true,
     }
 }
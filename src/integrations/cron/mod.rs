@@ -1,10 +1,11 @@
 use crate::{
+    core::storage::IntegrationStorage,
     types::{
         action::Action,
         color::Capabilities,
         device::{ControllableDevice, Device, DeviceData, DeviceId, ManageKind},
         event::{Event, TxEventChannel},
-        integration::{Integration, IntegrationActionPayload, IntegrationId},
+        integration::{Integration, IntegrationActionPayload, IntegrationHealth, IntegrationId},
     },
     utils::cli::Cli,
 };
@@ -13,9 +14,10 @@ use chrono::Local;
 use color_eyre::Result;
 use eyre::Context;
 use serde::Deserialize;
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 use tokio::{
     sync::RwLock,
+    task::JoinHandle,
     time::{sleep_until, Instant},
 };
 
@@ -37,6 +39,14 @@ pub struct Cron {
     event_tx: TxEventChannel,
     config: CronConfig,
     devices: Arc<RwLock<HashMap<DeviceId, Device>>>,
+    tasks: Vec<JoinHandle<()>>,
+    storage: IntegrationStorage,
+}
+
+/// Storage key of the enabled flag of a cron job, which is persisted so that
+/// disabled jobs stay disabled across restarts.
+fn enabled_key(id: &DeviceId) -> String {
+    format!("enabled/{id}")
 }
 
 #[async_trait]
@@ -46,6 +56,7 @@ impl Integration for Cron {
         config: &config::Value,
         _cli: &Cli,
         event_tx: TxEventChannel,
+        storage: IntegrationStorage,
     ) -> Result<Self> {
         let config = config
             .clone()
@@ -57,14 +68,25 @@ impl Integration for Cron {
             config,
             event_tx,
             devices: Default::default(),
+            tasks: Vec::new(),
+            storage,
         })
     }
 
     async fn register(&mut self) -> Result<()> {
         for (id, device) in &self.config.schedules {
+            let stored_enabled = self
+                .storage
+                .get::<bool>(&enabled_key(id))
+                .await
+                .unwrap_or_else(|e| {
+                    warn!("Failed to read stored state of cron job {id}: {e:?}");
+                    None
+                });
+
             let state = DeviceData::Controllable(ControllableDevice::new(
                 None,
-                device.init_enabled.unwrap_or(true),
+                stored_enabled.or(device.init_enabled).unwrap_or(true),
                 None,
                 None,
                 None,
@@ -98,32 +120,65 @@ impl Integration for Cron {
 
             let cron = croner::Cron::new(&config.schedule).parse()?;
 
-            tokio::spawn(async move {
+            let task = tokio::spawn(async move {
                 loop {
-                    let next = cron.find_next_occurrence(&Local::now(), false).unwrap();
+                    let next = match cron.find_next_occurrence(&Local::now(), false) {
+                        Ok(next) => next,
+                        Err(e) => {
+                            error!("Failed to find next occurrence for cron job {id}: {e}");
+                            break;
+                        }
+                    };
 
                     let duration = next - Local::now();
                     trace!("Sleeping for {duration:?}");
-                    sleep_until(Instant::now() + duration.to_std().unwrap()).await;
+                    sleep_until(Instant::now() + duration.to_std().unwrap_or(Duration::ZERO)).await;
 
                     debug!("Running cron job for device {id}");
 
                     let devices = devices.read().await;
-                    let device = devices.get(&id).unwrap();
+                    let Some(device) = devices.get(&id) else {
+                        continue;
+                    };
                     if device.is_powered_on() == Some(true) {
                         event_tx.send(Event::Action(action.clone()));
                     }
                 }
             });
+
+            self.tasks.push(task);
+        }
+
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        for task in self.tasks.drain(..) {
+            task.abort();
         }
 
         Ok(())
     }
 
+    fn health(&self) -> IntegrationHealth {
+        if self.tasks.iter().any(|task| task.is_finished()) {
+            IntegrationHealth::Failed("Cron job task has stopped".to_string())
+        } else {
+            IntegrationHealth::Healthy
+        }
+    }
+
     async fn set_integration_device_state(&mut self, device: &Device) -> Result<()> {
-        {
+        let prev_enabled = {
             let mut devices = self.devices.write().await;
-            devices.insert(device.id.clone(), device.clone());
+            devices
+                .insert(device.id.clone(), device.clone())
+                .and_then(|prev| prev.is_powered_on())
+        };
+
+        let enabled = device.is_powered_on();
+        if let Some(enabled) = enabled.filter(|_| enabled != prev_enabled) {
+            self.storage.set(&enabled_key(&device.id), &enabled).await?;
         }
 
         Ok(())
@@ -1,4 +1,5 @@
 use crate::{
+    core::storage::IntegrationStorage,
     types::{
         color::Capabilities,
         device::{ControllableDevice, Device, DeviceData, DeviceId, ManageKind},
@@ -21,6 +22,7 @@ pub struct DummyDeviceConfig {
 
 #[derive(Debug, Deserialize)]
 pub struct DummyConfig {
+    #[serde(default)]
     devices: HashMap<DeviceId, DummyDeviceConfig>,
 }
 
@@ -38,6 +40,7 @@ impl Integration for Dummy {
         config: &config::Value,
         _cli: &Cli,
         event_tx: TxEventChannel,
+        _storage: IntegrationStorage,
     ) -> Result<Self> {
         let config = config
             .clone()
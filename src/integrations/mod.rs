@@ -2,5 +2,6 @@ pub mod circadian;
 pub mod cron;
 pub mod dummy;
 pub mod mqtt;
+pub mod process;
 pub mod random;
 pub mod timer;
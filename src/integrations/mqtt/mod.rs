@@ -3,21 +3,22 @@
 mod utils;
 
 use crate::{
+    core::storage::IntegrationStorage,
     types::{
         color::Capabilities,
         device::{Device, ManageKind},
         event::{Event, TxEventChannel},
-        integration::{Integration, IntegrationActionPayload, IntegrationId},
+        integration::{Integration, IntegrationActionPayload, IntegrationHealth, IntegrationId},
     },
     utils::cli::Cli,
 };
 use async_trait::async_trait;
 use color_eyre::Result;
-use eyre::Context;
+use eyre::{eyre, Context};
 use rand::{distributions::Alphanumeric, Rng};
 use rumqttc::{AsyncClient, MqttOptions, QoS};
 use serde::Deserialize;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use tokio::task;
 
@@ -25,6 +26,9 @@ use crate::integrations::mqtt::utils::mqtt_to_homectl;
 
 use self::utils::homectl_to_mqtt;
 
+/// How long stop() waits for the disconnect packet to be sent.
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
 #[derive(Default, Debug, Deserialize, Clone)]
 pub struct MqttConfig {
     host: String,
@@ -63,6 +67,11 @@ pub struct Mqtt {
     config: MqttConfig,
     cli: Cli,
     client: Option<AsyncClient>,
+    eventloop_task: Option<task::JoinHandle<()>>,
+
+    /// Most recent error from the event loop, cleared once polling succeeds
+    /// again.
+    eventloop_error: Arc<Mutex<Option<String>>>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -78,6 +87,7 @@ impl Integration for Mqtt {
         config: &config::Value,
         cli: &Cli,
         event_tx: TxEventChannel,
+        _storage: IntegrationStorage,
     ) -> Result<Self> {
         let config = config
             .clone()
@@ -90,6 +100,8 @@ impl Integration for Mqtt {
             cli: cli.clone(),
             event_tx,
             client: None,
+            eventloop_task: None,
+            eventloop_error: Default::default(),
         })
     }
 
@@ -119,11 +131,17 @@ impl Integration for Mqtt {
         let id = self.id.clone();
         let event_tx = self.event_tx.clone();
         let config = Arc::new(self.config.clone());
+        let eventloop_error = Arc::clone(&self.eventloop_error);
 
-        task::spawn(async move {
+        let eventloop_task = task::spawn(async move {
             loop {
                 let notification = eventloop.poll().await;
 
+                // Sent by stop(), all earlier requests have been sent by now
+                if let Ok(rumqttc::Event::Outgoing(rumqttc::Outgoing::Disconnect)) = notification {
+                    break;
+                }
+
                 let id = id.clone();
                 let event_tx = event_tx.clone();
                 let config = Arc::clone(&config);
@@ -152,6 +170,10 @@ impl Integration for Mqtt {
                 })()
                 .await;
 
+                if let Ok(mut last_error) = eventloop_error.lock() {
+                    *last_error = res.as_ref().err().map(|e| format!("MQTT error: {e}"));
+                }
+
                 if let Err(e) = res {
                     error!(
                         target: &format!("homectl_server::integrations::mqtt::{id}"),
@@ -162,14 +184,50 @@ impl Integration for Mqtt {
             }
         });
 
+        self.eventloop_task = Some(eventloop_task);
+
         Ok(())
     }
 
+    async fn stop(&mut self) -> Result<()> {
+        if let Some(client) = self.client.take() {
+            client.disconnect().await.ok();
+        }
+
+        // Give the event loop a chance to send queued messages and the
+        // disconnect packet before giving up on it
+        if let Some(mut eventloop_task) = self.eventloop_task.take() {
+            if tokio::time::timeout(DISCONNECT_TIMEOUT, &mut eventloop_task)
+                .await
+                .is_err()
+            {
+                eventloop_task.abort();
+            }
+        }
+
+        Ok(())
+    }
+
+    fn health(&self) -> IntegrationHealth {
+        if self
+            .eventloop_task
+            .as_ref()
+            .is_some_and(|task| task.is_finished())
+        {
+            return IntegrationHealth::Failed("MQTT event loop has stopped".to_string());
+        }
+
+        match self.eventloop_error.lock().ok().and_then(|e| e.clone()) {
+            Some(e) => IntegrationHealth::Degraded(e),
+            None => IntegrationHealth::Healthy,
+        }
+    }
+
     async fn set_integration_device_state(&mut self, device: &Device) -> Result<()> {
         let client = self
             .client
             .as_ref()
-            .expect("Expected self.client to be set in start phase");
+            .ok_or_else(|| eyre!("MQTT client not connected, integration has not started"))?;
 
         let topic = self
             .config
@@ -196,7 +254,7 @@ impl Integration for Mqtt {
         let client = self
             .client
             .as_ref()
-            .expect("Expected self.client to be set in start phase");
+            .ok_or_else(|| eyre!("MQTT client not connected, integration has not started"))?;
 
         client
             .publish(action.topic, QoS::AtLeastOnce, true, action.json)
@@ -195,6 +195,8 @@ pub fn mqtt_to_homectl(
         integration_id,
         data: device_state,
         raw,
+        last_seen: None,
+        available: true,
     })
 }
 
@@ -253,7 +255,7 @@ pub fn homectl_to_mqtt(device: Device, config: &MqttConfig) -> Result<serde_json
                 &brightness_field,
                 serde_json::Number::from_f64((*value).into())
                     .map(serde_json::Value::Number)
-                    .unwrap(),
+                    .ok_or_else(|| eyre!("Cannot represent {value} as a JSON number"))?,
             )?;
         }
 
@@ -273,7 +275,7 @@ pub fn homectl_to_mqtt(device: Device, config: &MqttConfig) -> Result<serde_json
                 &transition_field,
                 serde_json::Number::from_f64((*value).into())
                     .map(serde_json::Value::Number)
-                    .unwrap(),
+                    .ok_or_else(|| eyre!("Cannot represent {value} as a JSON number"))?,
             )?;
         }
     };
@@ -313,6 +315,8 @@ mod tests {
                 ManageKind::Full,
             )),
             raw: None,
+            last_seen: None,
+            available: true,
         };
 
         let config = MqttConfig {
@@ -384,6 +388,8 @@ mod tests {
                 ManageKind::Unmanaged,
             )),
             raw: None,
+            last_seen: None,
+            available: true,
         };
 
         assert_eq!(device, expected);
@@ -0,0 +1,695 @@
+//! Runs an integration as a separate executable that speaks JSON-RPC 2.0 over
+//! stdin/stdout, one message per line. See `docs/process-integrations.md` for
+//! a description of the protocol.
+
+use crate::{
+    core::storage::IntegrationStorage,
+    types::{
+        device::{Device, DeviceData, DeviceId, DeviceKey},
+        event::{Event, TxEventChannel},
+        integration::{Integration, IntegrationActionPayload, IntegrationHealth, IntegrationId},
+    },
+    utils::cli::Cli,
+};
+use async_trait::async_trait;
+use color_eyre::Result;
+use eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    process::Stdio,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    process::{Child, ChildStdin, ChildStdout, Command},
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+    time::timeout,
+};
+
+/// How long to wait for a response to a request, unless configured otherwise.
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long the process has to exit after receiving the `stop` notification
+/// before it gets killed.
+const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Deserialize)]
+pub struct ProcessConfig {
+    /// Executable to run, looked up from `PATH` if not an absolute path
+    command: String,
+
+    #[serde(default)]
+    args: Vec<String>,
+
+    /// Working directory of the process, defaults to the working directory of
+    /// homectl
+    working_dir: Option<String>,
+
+    #[serde(default)]
+    env: HashMap<String, String>,
+
+    request_timeout_seconds: Option<u64>,
+}
+
+/// A device as reported by the process. The integration id is filled in by
+/// homectl.
+#[derive(Debug, Deserialize)]
+struct ProcessDevice {
+    id: DeviceId,
+    name: String,
+    data: DeviceData,
+    raw: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExternalStateUpdateParams {
+    device: ProcessDevice,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeviceRemovedParams {
+    id: DeviceId,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeviceRenamedParams {
+    id: DeviceId,
+    name: String,
+}
+
+#[derive(Debug, Serialize)]
+struct RpcRequest<'a, P: Serialize> {
+    jsonrpc: &'static str,
+
+    /// Omitted for notifications, which don't get a response
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<u64>,
+    method: &'a str,
+    params: P,
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcError {
+    code: Option<i64>,
+    message: String,
+}
+
+/// Any message sent by the process, before telling responses and
+/// notifications apart.
+#[derive(Debug, Deserialize)]
+struct RawRpcMessage {
+    id: Option<u64>,
+    method: Option<String>,
+    #[serde(default)]
+    params: serde_json::Value,
+    #[serde(default)]
+    result: serde_json::Value,
+    error: Option<RpcError>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(try_from = "RawRpcMessage")]
+enum RpcMessage {
+    Response {
+        id: u64,
+        result: serde_json::Value,
+        error: Option<RpcError>,
+    },
+    Notification {
+        method: String,
+        params: serde_json::Value,
+    },
+}
+
+impl TryFrom<RawRpcMessage> for RpcMessage {
+    type Error = String;
+
+    fn try_from(message: RawRpcMessage) -> Result<Self, Self::Error> {
+        match (message.id, message.method) {
+            (Some(id), None) => Ok(RpcMessage::Response {
+                id,
+                result: message.result,
+                error: message.error,
+            }),
+            (None, Some(method)) => Ok(RpcMessage::Notification {
+                method,
+                params: message.params,
+            }),
+            (Some(_), Some(method)) => Err(format!("Requests are not supported (got {method})")),
+            (None, None) => Err("Expected either an id or a method".to_string()),
+        }
+    }
+}
+
+type PendingRequests = Arc<Mutex<HashMap<u64, oneshot::Sender<Result<serde_json::Value>>>>>;
+
+struct Connection {
+    child: Child,
+
+    /// Lines to be written to the process' stdin by the writer task, so that
+    /// sending never blocks on a process that isn't reading its input
+    writer: mpsc::UnboundedSender<String>,
+    next_request_id: u64,
+    pending: PendingRequests,
+    reader_task: JoinHandle<()>,
+    writer_task: JoinHandle<()>,
+    stderr_task: JoinHandle<()>,
+}
+
+pub struct Process {
+    id: IntegrationId,
+    event_tx: TxEventChannel,
+    config: ProcessConfig,
+
+    /// The integration's whole config table, passed to the process in the
+    /// `register` request
+    raw_config: serde_json::Value,
+    cli: Cli,
+    connection: Option<Connection>,
+}
+
+impl Process {
+    fn spawn(&mut self) -> Result<()> {
+        let mut command = Command::new(&self.config.command);
+        command
+            .args(&self.config.args)
+            .envs(&self.config.env)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+
+        if let Some(working_dir) = &self.config.working_dir {
+            command.current_dir(working_dir);
+        }
+
+        let mut child = command
+            .spawn()
+            .wrap_err_with(|| format!("Failed to spawn {}", self.config.command))?;
+
+        let stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| eyre!("Expected stdin of process to be piped"))?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| eyre!("Expected stdout of process to be piped"))?;
+        let stderr = child
+            .stderr
+            .take()
+            .ok_or_else(|| eyre!("Expected stderr of process to be piped"))?;
+
+        let pending: PendingRequests = Default::default();
+
+        let reader_task = tokio::spawn(read_messages(
+            self.id.clone(),
+            stdout,
+            Arc::clone(&pending),
+            self.event_tx.clone(),
+        ));
+
+        let (writer, writer_rx) = mpsc::unbounded_channel();
+        let writer_task = tokio::spawn(write_messages(self.id.clone(), stdin, writer_rx));
+
+        let id = self.id.clone();
+        let stderr_task = tokio::spawn(async move {
+            let mut lines = BufReader::new(stderr).lines();
+
+            while let Ok(Some(line)) = lines.next_line().await {
+                info!(
+                    target: &format!("homectl_server::integrations::process::{id}"),
+                    "{line}"
+                );
+            }
+        });
+
+        self.connection = Some(Connection {
+            child,
+            writer,
+            next_request_id: 1,
+            pending,
+            reader_task,
+            writer_task,
+            stderr_task,
+        });
+
+        Ok(())
+    }
+
+    /// Queues a message to be written to the process' stdin.
+    fn send_message<P: Serialize>(
+        connection: &Connection,
+        message: &RpcRequest<'_, P>,
+    ) -> Result<()> {
+        let mut line = serde_json::to_string(message)?;
+        line.push('\n');
+
+        connection
+            .writer
+            .send(line)
+            .map_err(|_| eyre!("Failed to write to process stdin"))
+    }
+
+    fn request_timeout(&self) -> Duration {
+        self.config
+            .request_timeout_seconds
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_REQUEST_TIMEOUT)
+    }
+
+    /// Sends a request to the process and waits for its response.
+    async fn request<P: Serialize>(
+        &mut self,
+        method: &'static str,
+        params: P,
+    ) -> Result<serde_json::Value> {
+        let request_timeout = self.request_timeout();
+        let response = self.send_request(method, params)?;
+
+        response.wait(request_timeout).await
+    }
+
+    /// Sends a request to the process without waiting for its response.
+    fn send_request<P: Serialize>(
+        &mut self,
+        method: &'static str,
+        params: P,
+    ) -> Result<PendingResponse> {
+        let connection = self
+            .connection
+            .as_mut()
+            .ok_or_else(|| eyre!("Process is not running"))?;
+
+        let request_id = connection.next_request_id;
+        connection.next_request_id += 1;
+
+        let (tx, rx) = oneshot::channel();
+        if let Ok(mut pending) = connection.pending.lock() {
+            pending.insert(request_id, tx);
+        }
+
+        let request = RpcRequest {
+            jsonrpc: "2.0",
+            id: Some(request_id),
+            method,
+            params,
+        };
+
+        if let Err(e) = Self::send_message(connection, &request) {
+            if let Ok(mut pending) = connection.pending.lock() {
+                pending.remove(&request_id);
+            }
+            return Err(e);
+        }
+
+        Ok(PendingResponse {
+            request_id,
+            method,
+            rx,
+            pending: Arc::clone(&connection.pending),
+        })
+    }
+}
+
+/// A request that has been sent to the process, waiting for its response.
+struct PendingResponse {
+    request_id: u64,
+    method: &'static str,
+    rx: oneshot::Receiver<Result<serde_json::Value>>,
+    pending: PendingRequests,
+}
+
+impl PendingResponse {
+    async fn wait(self, request_timeout: Duration) -> Result<serde_json::Value> {
+        let method = self.method;
+
+        match timeout(request_timeout, self.rx).await {
+            Ok(Ok(result)) => result.wrap_err_with(|| format!("Process failed to handle {method}")),
+            Ok(Err(_)) => Err(eyre!("Process exited before responding to {method}")),
+            Err(_) => {
+                if let Ok(mut pending) = self.pending.lock() {
+                    pending.remove(&self.request_id);
+                }
+                Err(eyre!(
+                    "Process did not respond to {method} within {}s",
+                    request_timeout.as_secs()
+                ))
+            }
+        }
+    }
+}
+
+/// Writes queued messages to the process until the queue is closed, which
+/// also closes the process' stdin.
+async fn write_messages(
+    integration_id: IntegrationId,
+    mut stdin: ChildStdin,
+    mut rx: mpsc::UnboundedReceiver<String>,
+) {
+    while let Some(line) = rx.recv().await {
+        let result = match stdin.write_all(line.as_bytes()).await {
+            Ok(()) => stdin.flush().await,
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = result {
+            warn!("Failed to write to process integration {integration_id}: {e}");
+            break;
+        }
+    }
+}
+
+/// Reads messages from the process until its stdout is closed. Responses are
+/// routed to pending requests, and notifications are turned into events.
+async fn read_messages(
+    integration_id: IntegrationId,
+    stdout: ChildStdout,
+    pending: PendingRequests,
+    event_tx: TxEventChannel,
+) {
+    let mut lines = BufReader::new(stdout).lines();
+
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(e) => {
+                error!("Failed to read from process integration {integration_id}: {e}");
+                break;
+            }
+        };
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let message = match serde_json::from_str::<RpcMessage>(&line) {
+            Ok(message) => message,
+            Err(e) => {
+                warn!("Invalid message from process integration {integration_id}: {e}: {line}");
+                continue;
+            }
+        };
+
+        match message {
+            RpcMessage::Response { id, result, error } => {
+                let sender = pending.lock().ok().and_then(|mut p| p.remove(&id));
+                let Some(sender) = sender else {
+                    warn!("Process integration {integration_id} responded to unknown request {id}");
+                    continue;
+                };
+
+                let result = match error {
+                    Some(RpcError {
+                        code: Some(code),
+                        message,
+                    }) => Err(eyre!("{message} (code {code})")),
+                    Some(RpcError {
+                        code: None,
+                        message,
+                    }) => Err(eyre!(message)),
+                    None => Ok(result),
+                };
+
+                sender.send(result).ok();
+            }
+            RpcMessage::Notification { method, params } => match method.as_str() {
+                "external_state_update" => {
+                    match serde_json::from_value::<ExternalStateUpdateParams>(params) {
+                        Ok(ExternalStateUpdateParams { device }) => {
+                            let device = Device::new(
+                                integration_id.clone(),
+                                device.id,
+                                device.name,
+                                device.data,
+                                device.raw,
+                            );
+                            event_tx.send(Event::ExternalStateUpdate { device });
+                        }
+                        Err(e) => warn!(
+                            "Invalid external_state_update from process integration {integration_id}: {e}"
+                        ),
+                    }
+                }
+                "device_removed" => match serde_json::from_value::<DeviceRemovedParams>(params) {
+                    Ok(DeviceRemovedParams { id }) => {
+                        let device_key = DeviceKey::new(integration_id.clone(), id);
+                        event_tx.send(Event::DeviceRemoved { device_key });
+                    }
+                    Err(e) => warn!(
+                        "Invalid device_removed from process integration {integration_id}: {e}"
+                    ),
+                },
+                "device_renamed" => match serde_json::from_value::<DeviceRenamedParams>(params) {
+                    Ok(DeviceRenamedParams { id, name }) => {
+                        let device_key = DeviceKey::new(integration_id.clone(), id);
+                        event_tx.send(Event::DeviceRenamed { device_key, name });
+                    }
+                    Err(e) => warn!(
+                        "Invalid device_renamed from process integration {integration_id}: {e}"
+                    ),
+                },
+                _ => {
+                    warn!("Unknown notification {method} from process integration {integration_id}")
+                }
+            },
+        }
+    }
+
+    // Dropping the senders fails any requests still waiting for a response
+    if let Ok(mut pending) = pending.lock() {
+        pending.clear();
+    }
+}
+
+#[async_trait]
+impl Integration for Process {
+    fn new(
+        id: &IntegrationId,
+        config: &config::Value,
+        cli: &Cli,
+        event_tx: TxEventChannel,
+        _storage: IntegrationStorage,
+    ) -> Result<Self> {
+        let raw_config: serde_json::Value = config
+            .clone()
+            .try_deserialize()
+            .wrap_err("Failed to deserialize config of Process integration")?;
+        let config = config
+            .clone()
+            .try_deserialize()
+            .wrap_err("Failed to deserialize config of Process integration")?;
+
+        Ok(Process {
+            id: id.clone(),
+            event_tx,
+            config,
+            raw_config,
+            cli: cli.clone(),
+            connection: None,
+        })
+    }
+
+    async fn register(&mut self) -> Result<()> {
+        self.spawn()?;
+
+        let params = serde_json::json!({
+            "integration_id": self.id,
+            "config": self.raw_config,
+        });
+        self.request("register", params).await?;
+
+        Ok(())
+    }
+
+    async fn start(&mut self) -> Result<()> {
+        self.request("start", serde_json::json!({})).await?;
+
+        Ok(())
+    }
+
+    async fn set_integration_device_state(&mut self, device: &Device) -> Result<()> {
+        if self.cli.dry_run {
+            debug!("(dry run) would send device state to process: {device}");
+            return Ok(());
+        }
+
+        // The response is waited for in the background, as this is called
+        // while homectl is busy handling an event
+        let request_timeout = self.request_timeout();
+        let response = self.send_request(
+            "set_integration_device_state",
+            serde_json::json!({ "device": device }),
+        )?;
+
+        let integration_id = self.id.clone();
+        tokio::spawn(async move {
+            if let Err(e) = response.wait(request_timeout).await {
+                warn!("Process integration {integration_id}: {e:#}");
+            }
+        });
+
+        Ok(())
+    }
+
+    async fn run_integration_action(&mut self, payload: &IntegrationActionPayload) -> Result<()> {
+        self.request(
+            "run_integration_action",
+            serde_json::json!({ "payload": payload }),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        let Some(mut connection) = self.connection.take() else {
+            return Ok(());
+        };
+
+        let notification = RpcRequest {
+            jsonrpc: "2.0",
+            id: None,
+            method: "stop",
+            params: serde_json::json!({}),
+        };
+
+        // The process may already have exited, in which case there's nobody
+        // to notify. Closing the queue closes stdin once everything queued
+        // has been written.
+        Self::send_message(&connection, &notification).ok();
+        drop(connection.writer);
+
+        let exited = timeout(STOP_TIMEOUT, async {
+            (&mut connection.writer_task).await.ok();
+            connection.child.wait().await
+        })
+        .await;
+
+        match exited {
+            Ok(_) => {}
+            Err(_) => {
+                warn!(
+                    "Process integration {} did not exit within {}s, killing it",
+                    self.id,
+                    STOP_TIMEOUT.as_secs()
+                );
+                connection.child.kill().await.ok();
+            }
+        }
+
+        connection.reader_task.abort();
+        connection.writer_task.abort();
+        connection.stderr_task.abort();
+
+        Ok(())
+    }
+
+    fn health(&self) -> IntegrationHealth {
+        match &self.connection {
+            Some(connection) if connection.reader_task.is_finished() => {
+                IntegrationHealth::Failed("Process has exited".to_string())
+            }
+            Some(connection) if connection.writer_task.is_finished() => {
+                IntegrationHealth::Failed("Failed to write to process".to_string())
+            }
+            _ => IntegrationHealth::Healthy,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        core::storage::Storage, types::device::SensorDevice, types::event::mk_event_channel,
+    };
+    use clap::Parser;
+
+    #[test]
+    fn test_parse_messages() {
+        let message: RpcMessage =
+            serde_json::from_str(r#"{"jsonrpc":"2.0","id":3,"result":null}"#).unwrap();
+        assert!(matches!(
+            message,
+            RpcMessage::Response {
+                id: 3,
+                error: None,
+                ..
+            }
+        ));
+
+        let message: RpcMessage = serde_json::from_str(
+            r#"{"jsonrpc":"2.0","id":4,"error":{"code":-32601,"message":"Method not found"}}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            message,
+            RpcMessage::Response {
+                id: 4,
+                error: Some(_),
+                ..
+            }
+        ));
+
+        let message: RpcMessage = serde_json::from_str(
+            r#"{"jsonrpc":"2.0","method":"external_state_update","params":{"device":{"id":"sensor","name":"Sensor","data":{"Sensor":{"value":21.5}}}}}"#,
+        )
+        .unwrap();
+        let RpcMessage::Notification { method, params } = message else {
+            panic!("Expected a notification");
+        };
+        assert_eq!(method, "external_state_update");
+
+        let params: ExternalStateUpdateParams = serde_json::from_value(params).unwrap();
+        assert_eq!(params.device.id, DeviceId::new("sensor"));
+        assert!(params.device.raw.is_none());
+
+        // Requests from the process aren't mistaken for responses
+        assert!(serde_json::from_str::<RpcMessage>(
+            r#"{"jsonrpc":"2.0","id":5,"method":"start","params":{}}"#
+        )
+        .is_err());
+        assert!(serde_json::from_str::<RpcMessage>(r#"{"jsonrpc":"2.0"}"#).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_set_state_does_not_wait() {
+        let (event_tx, _event_rx) = mk_event_channel();
+        let cli = Cli::parse_from(["homectl-server"]);
+        let storage = Storage::new(std::env::temp_dir().join("homectl-test-storage.json"), &cli);
+        let id = IntegrationId::from("process".to_string());
+        let config = config::Config::builder()
+            .set_override("command", "cat")
+            .unwrap()
+            .build()
+            .unwrap()
+            .cache;
+
+        // cat echoes requests back without ever responding to them
+        let mut process =
+            Process::new(&id, &config, &cli, event_tx, storage.for_integration(&id)).unwrap();
+        process.spawn().unwrap();
+
+        let device = Device::new(
+            id.clone(),
+            DeviceId::new("lamp"),
+            "Lamp".to_string(),
+            DeviceData::Sensor(SensorDevice::Boolean { value: true }),
+            None,
+        );
+        tokio::time::timeout(
+            Duration::from_secs(1),
+            process.set_integration_device_state(&device),
+        )
+        .await
+        .expect("should not wait for a response")
+        .unwrap();
+
+        process.stop().await.unwrap();
+    }
+}
@@ -1,4 +1,5 @@
 use crate::{
+    core::storage::IntegrationStorage,
     types::{
         color::DeviceColor,
         device::{ControllableState, Device, DeviceData, DeviceId, SensorDevice},
@@ -14,7 +15,7 @@ use ordered_float::OrderedFloat;
 use rand::prelude::*;
 use serde::Deserialize;
 use std::time::Duration;
-use tokio::time;
+use tokio::{task::AbortHandle, time};
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct RandomConfig {
@@ -26,6 +27,7 @@ pub struct Random {
     id: IntegrationId,
     config: RandomConfig,
     event_tx: TxEventChannel,
+    poll_task: Option<AbortHandle>,
 }
 
 #[async_trait]
@@ -35,6 +37,7 @@ impl Integration for Random {
         config: &config::Value,
         _cli: &Cli,
         event_tx: TxEventChannel,
+        _storage: IntegrationStorage,
     ) -> Result<Self> {
         let config: RandomConfig = config
             .clone()
@@ -45,6 +48,7 @@ impl Integration for Random {
             id: id.clone(),
             config,
             event_tx,
+            poll_task: None,
         })
     }
 
@@ -61,7 +65,16 @@ impl Integration for Random {
 
         // FIXME: can we restructure the integrations / devices systems such
         // that polling is not needed here?
-        tokio::spawn(async { poll_sensor(random).await });
+        let poll_task = tokio::spawn(async { poll_sensor(random).await });
+        self.poll_task = Some(poll_task.abort_handle());
+
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        if let Some(poll_task) = self.poll_task.take() {
+            poll_task.abort();
+        }
 
         Ok(())
     }
@@ -106,5 +119,7 @@ fn mk_random_device(random: &Random) -> Device {
         integration_id: random.id.clone(),
         data: state,
         raw: None,
+        last_seen: None,
+        available: true,
     }
 }
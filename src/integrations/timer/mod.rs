@@ -1,4 +1,5 @@
 use crate::{
+    core::storage::IntegrationStorage,
     types::{
         device::{Device, DeviceData, DeviceId, SensorDevice},
         event::{Event, TxEventChannel},
@@ -9,7 +10,7 @@ use crate::{
 use async_trait::async_trait;
 use color_eyre::Result;
 use eyre::Context;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::task::JoinHandle;
@@ -20,11 +21,81 @@ pub struct TimerConfig {
     device_name: String,
 }
 
+/// Running timer, persisted so that it survives restarts.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+struct StoredTimer {
+    started_at_ms: u64,
+    timeout_ms: u64,
+}
+
+const TIMER_KEY: &str = "timer";
+
 pub struct Timer {
     id: IntegrationId,
     config: TimerConfig,
     event_tx: TxEventChannel,
     timer_task: Option<JoinHandle<()>>,
+    storage: IntegrationStorage,
+}
+
+impl Timer {
+    fn abort_timer(&mut self) {
+        if let Some(timer_task) = self.timer_task.take() {
+            timer_task.abort();
+        }
+    }
+
+    /// Reports the timer as running and spawns a task that reports it as
+    /// finished once the timeout has elapsed. A previously running timer is
+    /// replaced.
+    fn spawn_timer(&mut self, timer: StoredTimer) -> Result<()> {
+        self.abort_timer();
+
+        let started_at = Duration::from_millis(timer.started_at_ms);
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let remaining = (started_at + Duration::from_millis(timer.timeout_ms)).saturating_sub(now);
+
+        let device = mk_timer_device(
+            &self.id,
+            &self.config,
+            true,
+            Some(started_at),
+            Some(timer.timeout_ms),
+        );
+
+        self.event_tx.send(Event::ExternalStateUpdate { device });
+
+        let sender = self.event_tx.clone();
+        let id = self.id.clone();
+        let config = self.config.clone();
+        let storage = self.storage.clone();
+        let timer_task = tokio::spawn(async move {
+            time::sleep(remaining).await;
+
+            let device = mk_timer_device(
+                &id,
+                &config,
+                false,
+                Some(started_at),
+                Some(timer.timeout_ms),
+            );
+            sender.send(Event::ExternalStateUpdate { device });
+
+            // Leave a timer that has replaced this one in place
+            let stored_timer = storage.get::<StoredTimer>(TIMER_KEY).await.ok().flatten();
+            if stored_timer.is_none_or(|stored| stored.started_at_ms != timer.started_at_ms) {
+                return;
+            }
+
+            if let Err(e) = storage.delete(TIMER_KEY).await {
+                warn!("Failed to clear stored timer of {id}: {e:?}");
+            }
+        });
+
+        self.timer_task = Some(timer_task);
+
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -34,6 +105,7 @@ impl Integration for Timer {
         config: &config::Value,
         _cli: &Cli,
         event_tx: TxEventChannel,
+        storage: IntegrationStorage,
     ) -> Result<Self> {
         let config: TimerConfig = config
             .clone()
@@ -45,10 +117,26 @@ impl Integration for Timer {
             config,
             event_tx,
             timer_task: None,
+            storage,
         })
     }
 
     async fn register(&mut self) -> Result<()> {
+        let stored_timer = self
+            .storage
+            .get::<StoredTimer>(TIMER_KEY)
+            .await
+            .unwrap_or_else(|e| {
+                warn!("Failed to read stored timer of {}: {e:?}", self.id);
+                None
+            });
+
+        // Resume a timer that was running when homectl was stopped. Timers
+        // that have already elapsed finish immediately.
+        if let Some(timer) = stored_timer {
+            return self.spawn_timer(timer);
+        }
+
         let device = mk_timer_device(&self.id, &self.config, false, None, None);
 
         self.event_tx.send(Event::ExternalStateUpdate { device });
@@ -61,32 +149,23 @@ impl Integration for Timer {
         let timeout_ms: u64 = payload.parse()?;
         let started_at = SystemTime::now().duration_since(UNIX_EPOCH)?;
 
-        let device = mk_timer_device(
-            &self.id,
-            &self.config,
-            true,
-            Some(started_at),
-            Some(timeout_ms),
-        );
-
-        self.event_tx.send(Event::ExternalStateUpdate { device });
-
-        let sender = self.event_tx.clone();
-        let id = self.id.clone();
-        let config = self.config.clone();
-        let timer_task = tokio::spawn(async move {
-            let sleep_duration = Duration::from_millis(timeout_ms);
-            time::sleep(sleep_duration).await;
+        let timer = StoredTimer {
+            started_at_ms: started_at.as_millis() as u64,
+            timeout_ms,
+        };
 
-            let device = mk_timer_device(&id, &config, false, Some(started_at), Some(timeout_ms));
-            sender.send(Event::ExternalStateUpdate { device });
-        });
+        // The previous timer must not clear the new one from storage
+        self.abort_timer();
 
-        if let Some(timer_task) = self.timer_task.take() {
-            timer_task.abort();
+        if let Err(e) = self.storage.set(TIMER_KEY, &timer).await {
+            warn!("Failed to store timer of {}: {e:?}", self.id);
         }
 
-        self.timer_task = Some(timer_task);
+        self.spawn_timer(timer)
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        self.abort_timer();
 
         Ok(())
     }
@@ -109,5 +188,7 @@ fn mk_timer_device(
         raw: Some(
             json!({ "timeout_ms": timeout_ms, "started_at": started_at.map(|t| t.as_millis()) }),
         ),
+        last_seen: None,
+        available: true,
     }
 }
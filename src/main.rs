@@ -17,60 +17,156 @@ mod integrations;
 mod types;
 mod utils;
 
+use crate::core::auth::Auth;
+use crate::core::config::{config_path, set_config_path, ApiConfig};
 use crate::core::expr::Expr;
+use crate::core::history::DeviceHistory;
+use crate::core::journal::{next_event, EventJournal, Replay};
+use crate::core::reload::watch_config;
+use crate::core::storage::Storage;
+use crate::core::validation::check_config;
+use crate::core::websockets::WebSockets;
 use crate::core::{
     devices::Devices, event::handle_event, groups::Groups, integrations::Integrations,
     routines::Routines, scenes::Scenes, state::AppState,
 };
-use crate::types::event::{mk_event_channel, Event};
+use crate::types::event::{mk_event_channel, Event, EventSource, RxEventChannel};
 use api::init_api;
 use clap::Parser;
 use color_eyre::Result;
 use core::ui::Ui;
-use db::init_db;
+use db::{flush_db_writes, init_db};
 use eyre::eyre;
 use std::time::Duration;
 use std::{error::Error, sync::Arc};
-use tokio::sync::RwLock;
-use utils::cli::Cli;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{watch, RwLock};
+use utils::{
+    cli::{Cli, Command},
+    clock::{Clock, FixedClock, SystemClock},
+    logger::{forward_logs, init_logger},
+    solar::set_location,
+};
+
+static TICK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long to keep handling events that were queued before shutdown was
+/// requested.
+static SHUTDOWN_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long each integration gets to stop during shutdown.
+static INTEGRATION_STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait for pending database writes during shutdown.
+static DB_FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    let cli = Cli::parse();
+    let mut cli = Cli::parse();
     color_eyre::install()?;
-    pretty_env_logger::init();
+    init_logger();
+    set_config_path(cli.config.clone());
+
+    if let Some(Command::CheckConfig) = cli.command {
+        let valid = check_config().await;
+        std::process::exit(if valid { 0 } else { 1 });
+    }
 
-    // Attempt connecting to Postgres
-    init_db().await;
+    // Replayed events must not be able to affect any real devices
+    if cli.replay.is_some() {
+        cli.dry_run = true;
+    }
+
+    // Attempt connecting to Postgres, unless we're replaying events in which
+    // case we don't want to risk writing anything to the DB
+    if cli.replay.is_none() {
+        init_db().await;
+    }
 
     let (config, opaque_integrations_configs) = core::config::read_config()?;
 
     trace!("Using config:\n    {:#?}", config);
 
+    let core_config = config.core.unwrap_or_default();
+    set_location(core_config.location);
+
+    let journal = match (&core_config.event_journal_path, &cli.replay) {
+        (Some(path), None) => {
+            let max_size = core_config
+                .event_journal_max_size_mb
+                .map(|mb| mb * 1024 * 1024);
+            Some(EventJournal::open(path, max_size).await?)
+        }
+        _ => None,
+    };
+
+    // When replaying, time follows the timestamps of the replayed events
+    let replay_clock = Arc::new(FixedClock::new(chrono::Local::now()));
+    let mut replay = cli
+        .replay
+        .as_deref()
+        .map(|path| Replay::open(path, replay_clock.clone(), TICK_INTERVAL))
+        .transpose()?;
+    let clock: Arc<dyn Clock> = match replay {
+        Some(_) => replay_clock,
+        None => Arc::new(SystemClock),
+    };
+
     let (event_tx, mut event_rx) = mk_event_channel();
 
-    let mut integrations = Integrations::new(event_tx.clone());
+    let storage_path = core_config
+        .integration_storage_path
+        .clone()
+        .unwrap_or_else(|| config_path().with_file_name("integration_storage.json"));
+    let storage = Storage::new(storage_path, &cli);
+    let mut integrations = Integrations::new(event_tx.clone(), &cli, storage);
     let groups = Groups::new(config.groups.unwrap_or_default());
     let mut scenes = Scenes::new(config.scenes.unwrap_or_default());
     scenes.refresh_db_scenes().await;
+    let integrations_config = config.integrations.unwrap_or_default();
     let mut devices = Devices::new(event_tx.clone(), &cli);
+    devices.set_offline_timeouts(&integrations_config);
     devices.refresh_db_devices(&scenes).await;
-    let expr = Expr::new();
-    let rules = Routines::new(config.routines.unwrap_or_default(), event_tx.clone());
+    let expr = Expr::new(clock.clone());
+    let mut rules = Routines::new(
+        config.routines.unwrap_or_default(),
+        event_tx.clone(),
+        clock.clone(),
+    );
+    rules.refresh_db_state().await;
     let mut ui = Ui::new();
     ui.refresh_db_state().await;
+    let api_config = core_config.api.clone().unwrap_or_default();
+    let mut auth = Auth::new(&api_config);
+    auth.refresh_db_tokens().await;
+    let history = DeviceHistory::new(core_config.device_history.clone(), &cli);
+    history.start_pruning();
 
-    for (id, integration_config) in &config.integrations.unwrap_or_default() {
-        let opaque_integration_config: &config::Value = opaque_integrations_configs
-            .get(id)
-            .ok_or_else(|| eyre!("Expected to find config for integration with id {id}"))?;
+    // During replay, integrations are substituted with dummy integrations
+    // that have no devices of their own
+    let replay_integration_config =
+        config::Value::new(None, config::ValueKind::Table(Default::default()));
+
+    for (id, integration_config) in &integrations_config {
+        let (plugin, opaque_integration_config) = if replay.is_some() {
+            ("dummy", &replay_integration_config)
+        } else {
+            let opaque_integration_config: &config::Value = opaque_integrations_configs
+                .get(id)
+                .ok_or_else(|| eyre!("Expected to find config for integration with id {id}"))?;
+
+            (
+                integration_config.plugin.as_str(),
+                opaque_integration_config,
+            )
+        };
 
         integrations
             .load_integration(
-                &integration_config.plugin,
+                plugin,
                 id,
                 opaque_integration_config,
-                &cli,
+                integration_config.required.unwrap_or(false),
             )
             .await?;
     }
@@ -78,6 +174,9 @@ async fn main() -> Result<(), Box<dyn Error>> {
     integrations.run_register_pass().await?;
     integrations.run_start_pass().await?;
 
+    let ws = WebSockets::default();
+    forward_logs(ws.clone());
+
     let state = AppState {
         warming_up: true,
         integrations,
@@ -88,18 +187,28 @@ async fn main() -> Result<(), Box<dyn Error>> {
         event_tx,
         expr,
         ui,
-        ws: Default::default(),
+        ws,
+        history,
+        auth,
     };
 
     let state = Arc::new(RwLock::new(state));
 
-    init_api(&state)?;
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
 
-    {
+    // API input and live ticks would get mixed into replayed events, so these
+    // are only started once replay has finished
+    if replay.is_none() {
+        start_live(&state, &api_config, shutdown_rx.clone()).await?;
+    }
+
+    // When replaying, the end of warmup is signaled by the replayed
+    // StartupCompleted event instead
+    if replay.is_none() {
         let state = state.clone();
         tokio::spawn(async move {
             tokio::time::sleep(Duration::from_secs(
-                config.core.and_then(|c| c.warmup_time_seconds).unwrap_or(1),
+                core_config.warmup_time_seconds.unwrap_or(1),
             ))
             .await;
             let mut state = state.write().await;
@@ -108,21 +217,126 @@ async fn main() -> Result<(), Box<dyn Error>> {
         });
     }
 
+    // Integrations are substituted with dummy integrations during replay, so
+    // there's nothing to reload
+    if replay.is_none() {
+        watch_config(state.read().await.event_tx.clone());
+    }
+
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+
     loop {
-        let event = event_rx
-            .recv()
-            .await
-            .expect("Expected sender end of channel to never be dropped");
+        let (source, event) = tokio::select! {
+            biased;
 
-        // trace!("Received event: {:.100}", format!("{event:?}"));
+            _ = interrupt.recv() => break,
+            _ = terminate.recv() => break,
+            event = next_event(&mut event_rx, &mut replay) => match event {
+                Some(event) => event,
+                None => {
+                    // Replay has finished, the resulting state can now be
+                    // inspected through the API
+                    start_live(&state, &api_config, shutdown_rx.clone()).await?;
+                    continue;
+                }
+            },
+        };
 
-        let mut state = state.write().await;
-        let result = handle_event(&mut state, &event).await;
+        handle(&state, journal.as_ref(), source, event).await;
+    }
 
-        if let Err(err) = result {
-            error!(
-                "Error while handling event:\n    Event:\n    {event:#?}\n\n    Err:\n    {err:#?}",
+    info!("Shutting down...");
+
+    // Stop accepting new API connections
+    shutdown_tx.send(true).ok();
+
+    shutdown(&state, &mut event_rx, journal).await;
+
+    Ok(())
+}
+
+/// Serves the API and starts sending ticks.
+async fn start_live(
+    state: &Arc<RwLock<AppState>>,
+    api_config: &ApiConfig,
+    shutdown_rx: watch::Receiver<bool>,
+) -> Result<()> {
+    init_api(state, api_config, shutdown_rx)?;
+
+    let event_tx = state.read().await.event_tx.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(TICK_INTERVAL);
+
+        loop {
+            interval.tick().await;
+            event_tx.send(Event::Tick);
+        }
+    });
+
+    Ok(())
+}
+
+async fn handle(
+    state: &Arc<RwLock<AppState>>,
+    journal: Option<&EventJournal>,
+    source: EventSource,
+    event: Event,
+) {
+    // trace!("Received event: {:.100}", format!("{event:?}"));
+
+    if let Some(journal) = journal {
+        journal.record(&source, &event);
+    }
+
+    let mut state = state.write().await;
+    let result = handle_event(&mut state, &event).await;
+
+    state
+        .ws
+        .send_event(&source, &event, state.groups.get_flattened_groups())
+        .await;
+
+    if let Err(err) = result {
+        error!("Error while handling event:\n    Event:\n    {event:#?}\n\n    Err:\n    {err:#?}",);
+    }
+}
+
+/// Handles events that were already queued when shutdown was requested, then
+/// closes WebSocket connections, stops integrations and waits for pending
+/// database and journal writes.
+async fn shutdown(
+    state: &Arc<RwLock<AppState>>,
+    event_rx: &mut RxEventChannel,
+    journal: Option<EventJournal>,
+) {
+    let deadline = tokio::time::Instant::now() + SHUTDOWN_DRAIN_TIMEOUT;
+
+    while let Ok((source, event)) = event_rx.try_recv() {
+        if tokio::time::Instant::now() >= deadline {
+            warn!(
+                "Timed out handling queued events, dropping {} events",
+                event_rx.len() + 1
             );
+            break;
         }
+
+        handle(state, journal.as_ref(), source, event).await;
+    }
+
+    let mut state = state.write().await;
+    state.ws.close_all().await;
+    state.integrations.stop_all(INTEGRATION_STOP_TIMEOUT).await;
+    state.history.flush();
+
+    if tokio::time::timeout(DB_FLUSH_TIMEOUT, flush_db_writes())
+        .await
+        .is_err()
+    {
+        warn!("Timed out waiting for pending database writes");
+    }
+
+    if let Some(journal) = journal {
+        journal.close().await;
     }
 }
@@ -5,7 +5,7 @@ use super::{
     device::{Device, DeviceKey},
     dim::DimDescriptor,
     integration::CustomActionDescriptor,
-    rule::ForceTriggerRoutineDescriptor,
+    rule::{ForceTriggerRoutineDescriptor, RoutineDescriptor},
     scene::{ActivateSceneDescriptor, CycleScenesDescriptor},
     ui::UiActionDescriptor,
 };
@@ -29,6 +29,13 @@ pub enum Action {
     /// Forcibly triggers a routine, ignoring any possible rules.
     ForceTriggerRoutine(ForceTriggerRoutineDescriptor),
 
+    /// Re-enables a previously disabled routine.
+    EnableRoutine(RoutineDescriptor),
+
+    /// Disables a routine until it is enabled again. Persists across
+    /// restarts.
+    DisableRoutine(RoutineDescriptor),
+
     /// Sets device state to given state.
     SetDeviceState(Device),
 
@@ -0,0 +1,57 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+/// Access level of an API token. Scopes are ordered so that each one includes
+/// everything allowed by the scopes before it.
+#[derive(TS, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum ApiScope {
+    /// Read state and receive state updates.
+    ReadOnly,
+
+    /// Control devices, trigger actions and enable/disable routines.
+    Actions,
+
+    /// Everything, including reloading the config and managing tokens.
+    Admin,
+}
+
+impl fmt::Display for ApiScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            ApiScope::ReadOnly => "read_only",
+            ApiScope::Actions => "actions",
+            ApiScope::Admin => "admin",
+        };
+
+        f.write_str(s)
+    }
+}
+
+impl FromStr for ApiScope {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read_only" => Ok(ApiScope::ReadOnly),
+            "actions" => Ok(ApiScope::Actions),
+            "admin" => Ok(ApiScope::Admin),
+            _ => Err(eyre!("Unknown API scope {s}")),
+        }
+    }
+}
+
+/// An API token as listed through the API. The token itself is never exposed
+/// after creation.
+#[derive(TS, Clone, Debug, Deserialize, Serialize)]
+#[ts(export)]
+pub struct ApiToken {
+    pub name: String,
+    pub scope: ApiScope,
+
+    /// Tokens defined in the config file can't be deleted through the API.
+    pub from_config: bool,
+}
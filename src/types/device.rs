@@ -1,3 +1,4 @@
+use chrono::{DateTime, Utc};
 use eyre::Result;
 use ordered_float::OrderedFloat;
 use std::{
@@ -21,6 +22,7 @@ use ts_rs::TS;
 macro_attr! {
     #[derive(TS, Clone, Debug, Deserialize, Serialize, Eq, PartialEq, Ord, PartialOrd, Hash, NewtypeDisplay!, NewtypeFrom!)]
     #[ts(export)]
+    #[serde(transparent)]
     /// unique identifier for the Device
     pub struct DeviceId(String);
 }
@@ -372,6 +374,20 @@ pub struct Device {
 
     #[ts(type = "Record<string, any> | null")]
     pub raw: Option<serde_json::Value>,
+
+    /// When the integration last reported state for this device
+    #[serde(default)]
+    #[ts(type = "string | null")]
+    pub last_seen: Option<DateTime<Utc>>,
+
+    /// Whether the device has reported state within its integration's
+    /// offline timeout
+    #[serde(default = "default_available")]
+    pub available: bool,
+}
+
+fn default_available() -> bool {
+    true
 }
 
 impl Display for Device {
@@ -388,6 +404,8 @@ impl From<DeviceRow> for Device {
             integration_id: row.integration_id.into(),
             data: row.state.0,
             raw: None,
+            last_seen: None,
+            available: true,
         }
     }
 }
@@ -406,11 +424,15 @@ impl Device {
             integration_id,
             data: state,
             raw,
+            last_seen: None,
+            available: true,
         }
     }
 
     pub fn is_state_eq(&self, other: &Device) -> bool {
-        self.data.is_state_eq(&other.data) && self.raw == other.raw
+        self.data.is_state_eq(&other.data)
+            && self.raw == other.raw
+            && self.available == other.available
     }
 
     pub fn get_device_key(&self) -> DeviceKey {
@@ -742,6 +764,18 @@ impl<'de> Deserialize<'de> for DeviceKey {
 #[ts(export)]
 pub struct DevicesState(pub BTreeMap<DeviceKey, Device>);
 
+/// Device state at some point in time
+#[derive(TS, Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[ts(export)]
+pub struct DeviceHistoryEntry {
+    #[ts(type = "string")]
+    pub recorded_at: DateTime<Utc>,
+    pub data: DeviceData,
+
+    #[ts(type = "Record<string, any> | null")]
+    pub raw: Option<serde_json::Value>,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -2,9 +2,13 @@ use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 use ts_rs::TS;
 
+use super::integration::IntegrationId;
 use super::scene::{SceneConfig, SceneId};
 
-use super::{action::Action, device::Device, device::DevicesState};
+use super::{
+    action::Action,
+    device::{Device, DeviceKey, DevicesState},
+};
 
 #[allow(clippy::large_enum_variant)]
 #[derive(TS, Clone, Debug, Deserialize, Serialize)]
@@ -15,6 +19,15 @@ pub enum Event {
     /// mismatch, we'll try to correct it.
     ExternalStateUpdate { device: Device },
 
+    /// A device no longer exists, e.g. because it was unpaired from its
+    /// integration. The device is forgotten along with its stored state and
+    /// scene overrides.
+    DeviceRemoved { device_key: DeviceKey },
+
+    /// A device has been given a new name. References to the device by name
+    /// are resolved using the new name from now on.
+    DeviceRenamed { device_key: DeviceKey, name: String },
+
     /// Internal device state update has taken place, need to take appropriate
     /// actions such as checking (and possibly triggering) routines.
     InternalStateUpdate {
@@ -38,6 +51,18 @@ pub enum Event {
     /// Wait for a bit for devices to come online before starting up.
     StartupCompleted,
 
+    /// Sent periodically by core, used for checking time based conditions
+    /// such as device availability.
+    Tick,
+
+    /// An integration restarted in the background has finished registering
+    /// and starting, or failed to do so.
+    IntegrationRestarted {
+        integration_id: IntegrationId,
+        restart_id: u64,
+        error: Option<String>,
+    },
+
     /// Store new scene in DB.
     DbStoreScene {
         scene_id: SceneId,
@@ -50,6 +75,9 @@ pub enum Event {
     /// Delete scene from DB.
     DbDeleteScene { scene_id: SceneId },
 
+    /// Settings.toml has changed on disk and should be reloaded.
+    ReloadConfig,
+
     /// Broadcast current state to all WS peers
     WsBroadcastState,
 
@@ -57,24 +85,60 @@ pub enum Event {
     Action(Action),
 }
 
+/// Describes where an [Event] originated from.
+#[derive(TS, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[ts(export)]
+pub enum EventSource {
+    /// Event was sent by homectl core, usually as a result of handling some
+    /// other event.
+    Core,
+
+    /// Event was sent by the integration with given id.
+    Integration(IntegrationId),
+
+    /// Event was received via the REST API.
+    Api,
+
+    /// Event was received from a WebSocket peer.
+    WebSocket,
+
+    /// Event was read from an event journal in replay mode.
+    Replay,
+}
+
 #[derive(Clone)]
 pub struct Sender<T> {
-    tx: UnboundedSender<T>,
+    tx: UnboundedSender<(EventSource, T)>,
+    source: EventSource,
 }
 
 impl<T: std::fmt::Debug> Sender<T> {
     pub fn send(&self, event: T) {
-        self.tx.send(event).expect("Receiver end of channel closed");
+        self.tx
+            .send((self.source.clone(), event))
+            .expect("Receiver end of channel closed");
+    }
+
+    /// Returns a sender that tags all events sent through it with the given
+    /// source.
+    pub fn with_source(&self, source: EventSource) -> Self {
+        Sender {
+            tx: self.tx.clone(),
+            source,
+        }
     }
 }
 
 pub type TxEventChannel = Sender<Event>;
-pub type RxEventChannel = UnboundedReceiver<Event>;
+pub type RxEventChannel = UnboundedReceiver<(EventSource, Event)>;
 
 pub fn mk_event_channel() -> (TxEventChannel, RxEventChannel) {
-    let (tx, rx) = unbounded_channel::<Event>();
+    let (tx, rx) = unbounded_channel::<(EventSource, Event)>();
 
-    let sender = Sender { tx };
+    let sender = Sender {
+        tx,
+        source: EventSource::Core,
+    };
 
     (sender, rx)
 }
@@ -7,6 +7,7 @@ use ts_rs::TS;
 macro_attr! {
     #[derive(TS, Clone, Debug, Deserialize, Serialize, Eq, PartialEq, Hash, Ord, PartialOrd, NewtypeDisplay!)]
     #[ts(export)]
+    #[serde(transparent)]
     pub struct GroupId(pub String);
 }
 
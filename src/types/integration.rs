@@ -1,7 +1,8 @@
-use crate::utils::cli::Cli;
+use crate::{core::storage::IntegrationStorage, utils::cli::Cli};
 
 use super::{device::Device, event::TxEventChannel};
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use color_eyre::Result;
 use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, convert::Infallible, str::FromStr};
@@ -10,6 +11,7 @@ use ts_rs::TS;
 macro_attr! {
     #[derive(TS, Clone, Debug, Deserialize, Serialize, Eq, PartialEq, Ord, PartialOrd, Hash, NewtypeDisplay!, NewtypeFrom!)]
     #[ts(export)]
+    #[serde(transparent)]
     pub struct IntegrationId(String);
 }
 
@@ -24,6 +26,16 @@ impl FromStr for IntegrationId {
 #[derive(Deserialize, Debug)]
 pub struct IntegrationConfig {
     pub plugin: String,
+
+    /// Devices of this integration are marked unavailable if no state update
+    /// has been received for this many seconds. Devices never go offline if
+    /// omitted.
+    pub offline_timeout_seconds: Option<u64>,
+
+    /// If set, homectl refuses to start unless this integration registers
+    /// and starts successfully. Other integrations that fail are marked as
+    /// failed and restarted in the background.
+    pub required: Option<bool>,
     // NOTE: integration configs may contain other fields as well.
 
     // but since we don't know what fields those might be, they have to be
@@ -45,6 +57,49 @@ pub struct CustomActionDescriptor {
     pub payload: IntegrationActionPayload,
 }
 
+/// Health reported by a running integration, see [Integration::health].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IntegrationHealth {
+    Healthy,
+
+    /// The integration works but has trouble, e.g. it is reconnecting to a
+    /// broker.
+    Degraded(String),
+
+    /// The integration has stopped working and should be restarted.
+    Failed(String),
+}
+
+#[derive(TS, Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[ts(export)]
+#[serde(rename_all = "snake_case")]
+pub enum IntegrationState {
+    Starting,
+    Running,
+    Degraded,
+    Failed,
+}
+
+#[derive(TS, Clone, Debug, Deserialize, Serialize)]
+#[ts(export)]
+pub struct IntegrationStatus {
+    pub id: IntegrationId,
+    pub plugin: String,
+    pub required: bool,
+    pub state: IntegrationState,
+    pub last_error: Option<String>,
+
+    #[ts(type = "string | null")]
+    pub last_error_at: Option<DateTime<Utc>>,
+
+    /// Number of times the integration has been restarted
+    pub restarts: u32,
+
+    /// When a failed integration will be restarted next
+    #[ts(type = "string | null")]
+    pub next_restart_at: Option<DateTime<Utc>>,
+}
+
 #[async_trait]
 pub trait Integration: Send {
     // rustc --explain E0038
@@ -53,6 +108,7 @@ pub trait Integration: Send {
         config: &config::Value,
         cli: &Cli,
         event_tx: TxEventChannel,
+        storage: IntegrationStorage,
     ) -> Result<Self>
     where
         Self: Sized;
@@ -69,4 +125,19 @@ pub trait Integration: Send {
     async fn run_integration_action(&mut self, _payload: &IntegrationActionPayload) -> Result<()> {
         Ok(())
     }
+
+    /// Called before the integration is unloaded, e.g. when it has been
+    /// removed from the config during a reload, or when homectl shuts down.
+    /// Integrations that spawn background tasks should stop them here.
+    async fn stop(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Polled periodically by homectl core once the integration has started.
+    /// Integrations with background tasks should report
+    /// [IntegrationHealth::Failed] if those tasks have died, which causes the
+    /// integration to be restarted.
+    fn health(&self) -> IntegrationHealth {
+        IntegrationHealth::Healthy
+    }
 }
@@ -1,4 +1,5 @@
 pub mod action;
+pub mod auth;
 pub mod color;
 pub mod device;
 pub mod dim;
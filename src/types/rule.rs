@@ -2,20 +2,39 @@ use super::device::{DeviceRef, SensorDevice};
 use super::{group::GroupId, scene::SceneId};
 
 use super::action::Actions;
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use crate::utils::{
+    from_duration, from_optional_duration,
+    solar::{get_location, Location, TimeOfDay},
+};
+use chrono::{DateTime, Datelike, Local, NaiveDate, NaiveTime, Utc, Weekday};
+use eyre::Result;
+use serde::{de, Deserialize, Serialize};
+use std::{collections::HashMap, convert::Infallible, str::FromStr, time::Duration};
 use ts_rs::TS;
 
 macro_attr! {
     #[derive(TS, Clone, Debug, Deserialize, Serialize, Eq, PartialEq, Hash, NewtypeDisplay!, NewtypeFrom!)]
     #[ts(export)]
+    #[serde(transparent)]
     pub struct RoutineId(pub String);
 }
 
+impl FromStr for RoutineId {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(RoutineId(s.to_string()))
+    }
+}
+
 #[derive(Clone, Deserialize, Debug)]
 pub struct SensorRule {
     pub state: SensorDevice,
 
+    /// Rule only matches once it has been matching for at least this long.
+    #[serde(default, rename = "for", deserialize_with = "from_optional_duration")]
+    pub for_duration: Option<Duration>,
+
     #[serde(flatten)]
     pub device_ref: DeviceRef,
 }
@@ -25,6 +44,10 @@ pub struct DeviceRule {
     pub power: Option<bool>,
     pub scene: Option<SceneId>,
 
+    /// Rule only matches once it has been matching for at least this long.
+    #[serde(default, rename = "for", deserialize_with = "from_optional_duration")]
+    pub for_duration: Option<Duration>,
+
     #[serde(flatten)]
     pub device_ref: DeviceRef,
 }
@@ -34,6 +57,10 @@ pub struct GroupRule {
     pub group_id: GroupId,
     pub power: Option<bool>,
     pub scene: Option<SceneId>,
+
+    /// Rule only matches once it has been matching for at least this long.
+    #[serde(default, rename = "for", deserialize_with = "from_optional_duration")]
+    pub for_duration: Option<Duration>,
 }
 
 #[derive(Clone, Deserialize, Debug)]
@@ -41,6 +68,158 @@ pub struct AnyRule {
     pub any: Rules,
 }
 
+#[derive(Clone, Deserialize, Debug)]
+pub struct ExprRule {
+    pub expr: evalexpr::Node,
+
+    /// Rule only matches once the expression has been true for at least this
+    /// long.
+    #[serde(default, rename = "for", deserialize_with = "from_optional_duration")]
+    pub for_duration: Option<Duration>,
+}
+
+/// Time of day range, which may cross midnight (e.g. 22:00 - 06:00). Either
+/// end may be relative to a solar event (e.g. "sunset-30m").
+///
+/// Whether a range with solar events crosses midnight is decided by how it
+/// resolves on the March equinox, as solar events can move past fixed times
+/// during the year. On days where such a range ends up inverted (e.g.
+/// "sunset".."23:00" when the sun sets after 23:00), or where a solar event
+/// doesn't occur at all (polar day or night), the range is empty.
+#[derive(Clone, Deserialize, Debug)]
+pub struct TimeRange {
+    /// Start of the range (inclusive)
+    pub after: TimeOfDay,
+
+    /// End of the range (exclusive)
+    pub before: TimeOfDay,
+}
+
+impl TimeRange {
+    pub fn contains(&self, now: &DateTime<Local>) -> Result<bool> {
+        self.contains_at(now, get_location().as_ref())
+    }
+
+    fn contains_at(&self, now: &DateTime<Local>, location: Option<&Location>) -> Result<bool> {
+        let time = now.time();
+
+        if let (TimeOfDay::Fixed(after), TimeOfDay::Fixed(before)) = (&self.after, &self.before) {
+            return Ok(in_range(*after, *before, time, after > before));
+        }
+
+        let location = location.ok_or_else(|| {
+            eyre!(
+                "Could not determine time of {} - {}, is [core.location] configured?",
+                self.after,
+                self.before
+            )
+        })?;
+
+        let date = now.date_naive();
+        let (Some(after), Some(before)) = (
+            self.after.resolve_at(date, Some(location)),
+            self.before.resolve_at(date, Some(location)),
+        ) else {
+            return Ok(false);
+        };
+
+        let crosses_midnight = self
+            .crosses_midnight(date, location)
+            .unwrap_or(after > before);
+
+        if crosses_midnight != (after > before) {
+            return Ok(false);
+        }
+
+        Ok(in_range(after, before, time, crosses_midnight))
+    }
+
+    /// Whether the range crosses midnight on the March equinox of the given
+    /// date's year.
+    fn crosses_midnight(&self, date: NaiveDate, location: &Location) -> Option<bool> {
+        let equinox = NaiveDate::from_ymd_opt(date.year(), 3, 20)?;
+        let after = self.after.resolve_at(equinox, Some(location))?;
+        let before = self.before.resolve_at(equinox, Some(location))?;
+
+        Some(after > before)
+    }
+}
+
+fn in_range(after: NaiveTime, before: NaiveTime, time: NaiveTime, crosses_midnight: bool) -> bool {
+    if crosses_midnight {
+        after <= time || time < before
+    } else {
+        after <= time && time < before
+    }
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct TimeRule {
+    pub time: TimeRange,
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct WeekdayRule {
+    pub weekdays: Vec<Weekday>,
+}
+
+/// Day of year without a year, given as "MM-DD" in config.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MonthDay {
+    pub month: u32,
+    pub day: u32,
+}
+
+impl<'de> Deserialize<'de> for MonthDay {
+    fn deserialize<D>(d: D) -> Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        let s = String::deserialize(d)?;
+
+        let parsed = s.split_once('-').and_then(|(month, day)| {
+            let month: u32 = month.parse().ok()?;
+            let day: u32 = day.parse().ok()?;
+            ((1..=12).contains(&month) && (1..=31).contains(&day))
+                .then_some(MonthDay { month, day })
+        });
+
+        parsed.ok_or_else(|| de::Error::custom(format!("Expected date in MM-DD format, got '{s}'")))
+    }
+}
+
+impl From<&DateTime<Local>> for MonthDay {
+    fn from(date: &DateTime<Local>) -> Self {
+        MonthDay {
+            month: date.month(),
+            day: date.day(),
+        }
+    }
+}
+
+/// Range of dates, which may cross new year (e.g. 12-01 - 01-06). Both ends
+/// are inclusive.
+#[derive(Clone, Deserialize, Debug)]
+pub struct DateRange {
+    pub from: MonthDay,
+    pub to: MonthDay,
+}
+
+impl DateRange {
+    pub fn contains(&self, date: MonthDay) -> bool {
+        if self.from <= self.to {
+            self.from <= date && date <= self.to
+        } else {
+            self.from <= date || date <= self.to
+        }
+    }
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct DateRule {
+    pub date: DateRange,
+}
+
 #[derive(Clone, Deserialize, Debug)]
 #[serde(untagged)]
 pub enum Rule {
@@ -58,10 +237,65 @@ pub enum Rule {
     /// one of the contained rules need to match.
     Any(AnyRule),
 
+    /// Matches when the current time of day is within given range.
+    Time(TimeRule),
+
+    /// Matches on given days of the week.
+    Weekday(WeekdayRule),
+
+    /// Matches when the current date is within given range.
+    Date(DateRule),
+
+    /// Evaluates given expression, with additional options.
+    Expr(ExprRule),
+
     /// Evaluates given expression.
     EvalExpr(evalexpr::Node),
 }
 
+impl Rule {
+    /// How long the rule needs to have been matching before it is considered
+    /// triggered, if at all.
+    pub fn for_duration(&self) -> Option<Duration> {
+        match self {
+            Rule::Sensor(SensorRule { for_duration, .. })
+            | Rule::Device(DeviceRule { for_duration, .. })
+            | Rule::Group(GroupRule { for_duration, .. })
+            | Rule::Expr(ExprRule { for_duration, .. }) => *for_duration,
+            Rule::Any(_) | Rule::Time(_) | Rule::Weekday(_) | Rule::Date(_) | Rule::EvalExpr(_) => {
+                None
+            }
+        }
+    }
+
+    /// Returns true if this rule, or any rule nested within it, may change
+    /// outcome as time passes even if no state changes. This includes
+    /// expressions that read the position of the sun.
+    pub fn is_timed(&self) -> bool {
+        let reads_solar = |expr: &evalexpr::Node| {
+            expr.iter_read_variable_identifiers()
+                .any(|name| name.starts_with("solar."))
+        };
+
+        match self {
+            Rule::Any(AnyRule { any }) => any.iter().any(Rule::is_timed),
+            Rule::Time(_) | Rule::Weekday(_) | Rule::Date(_) => true,
+            Rule::Expr(ExprRule { expr, .. }) | Rule::EvalExpr(expr) if reads_solar(expr) => true,
+            rule => rule.for_duration().is_some(),
+        }
+    }
+
+    /// Evaluates time, weekday and date rules at the given point in time.
+    pub fn matches_time(&self, now: &DateTime<Local>) -> Result<bool> {
+        match self {
+            Rule::Time(TimeRule { time }) => time.contains(now),
+            Rule::Weekday(WeekdayRule { weekdays }) => Ok(weekdays.contains(&now.weekday())),
+            Rule::Date(DateRule { date }) => Ok(date.contains(MonthDay::from(now))),
+            rule => Err(eyre!("Expected {rule:?} to be a time based rule")),
+        }
+    }
+}
+
 pub type Rules = Vec<Rule>;
 
 #[derive(Clone, Deserialize, Debug)]
@@ -69,12 +303,268 @@ pub struct Routine {
     pub name: String,
     pub rules: Rules,
     pub actions: Actions,
+
+    /// Actions to run when the routine's rules stop matching after the
+    /// routine has been triggered.
+    #[serde(alias = "else")]
+    pub on_release: Option<Actions>,
+
+    /// Minimum time between two triggers of the routine.
+    #[serde(default, deserialize_with = "from_optional_duration")]
+    pub cooldown: Option<Duration>,
+
+    /// Triggers are suppressed if the routine was previously triggered
+    /// (including suppressed triggers) less than this long ago. Useful for
+    /// flapping sensors.
+    #[serde(default, deserialize_with = "from_optional_duration")]
+    pub debounce: Option<Duration>,
+
+    /// Maximum number of triggers within a time period.
+    pub rate_limit: Option<RateLimit>,
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct RateLimit {
+    pub max: usize,
+
+    #[serde(deserialize_with = "from_duration")]
+    pub period: Duration,
 }
 
 pub type RoutinesConfig = HashMap<RoutineId, Routine>;
 
+/// Evaluation result of a single rule.
+#[derive(TS, Clone, Debug, Serialize)]
+#[ts(export)]
+pub struct RuleStatus {
+    pub triggered: bool,
+    pub error: Option<String>,
+
+    /// Statuses of rules nested within an `any` rule
+    pub any: Vec<RuleStatus>,
+}
+
+#[derive(TS, Clone, Debug, Serialize)]
+#[ts(export)]
+pub struct RoutineStatus {
+    pub id: RoutineId,
+    pub name: String,
+
+    /// Disabled routines are never triggered
+    pub enabled: bool,
+
+    /// Whether all rules of the routine matched when routines were last
+    /// evaluated
+    pub triggered: bool,
+
+    /// Status of each rule, in the same order as in the routine config
+    pub rules: Vec<RuleStatus>,
+
+    #[ts(type = "string | null")]
+    pub last_triggered: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub trigger_count: u64,
+    pub suppressed_count: u64,
+}
+
 #[derive(TS, Clone, Deserialize, Debug, Serialize)]
 #[ts(export)]
 pub struct ForceTriggerRoutineDescriptor {
     pub routine_id: RoutineId,
 }
+
+#[derive(TS, Clone, Deserialize, Debug, Serialize)]
+#[ts(export)]
+pub struct RoutineDescriptor {
+    pub routine_id: RoutineId,
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::utils::clock::{Clock, FixedClock};
+
+    fn clock_at(month: u32, day: u32, hour: u32, min: u32) -> FixedClock {
+        // 2024-01-01 was a Monday
+        FixedClock::new(
+            Local
+                .with_ymd_and_hms(2024, month, day, hour, min, 0)
+                .unwrap(),
+        )
+    }
+
+    fn matches(rule: &Rule, clock: FixedClock) -> bool {
+        rule.matches_time(&clock.now()).unwrap()
+    }
+
+    fn time_rule(after: &str, before: &str) -> Rule {
+        Rule::Time(TimeRule {
+            time: TimeRange {
+                after: after.parse().unwrap(),
+                before: before.parse().unwrap(),
+            },
+        })
+    }
+
+    #[test]
+    fn test_time_range() {
+        let rule = time_rule("08:00", "17:00");
+
+        assert!(!matches(&rule, clock_at(1, 1, 7, 59)));
+        assert!(matches(&rule, clock_at(1, 1, 8, 0)));
+        assert!(matches(&rule, clock_at(1, 1, 16, 59)));
+        assert!(!matches(&rule, clock_at(1, 1, 17, 0)));
+    }
+
+    #[test]
+    fn test_time_range_crossing_midnight() {
+        let rule = time_rule("22:00", "06:00");
+
+        assert!(!matches(&rule, clock_at(1, 1, 21, 59)));
+        assert!(matches(&rule, clock_at(1, 1, 23, 0)));
+        assert!(matches(&rule, clock_at(1, 1, 3, 0)));
+        assert!(!matches(&rule, clock_at(1, 1, 6, 0)));
+    }
+
+    #[test]
+    fn test_weekdays() {
+        let rule = Rule::Weekday(WeekdayRule {
+            weekdays: vec![Weekday::Sat, Weekday::Sun],
+        });
+
+        assert!(!matches(&rule, clock_at(1, 5, 12, 0)));
+        assert!(matches(&rule, clock_at(1, 6, 12, 0)));
+        assert!(matches(&rule, clock_at(1, 7, 12, 0)));
+        assert!(!matches(&rule, clock_at(1, 8, 12, 0)));
+    }
+
+    #[test]
+    fn test_date_range_crossing_new_year() {
+        let rule: Rule = toml::from_str::<HashMap<String, Rule>>(
+            r#"rule = { date = { from = "12-01", to = "01-06" } }"#,
+        )
+        .unwrap()
+        .remove("rule")
+        .unwrap();
+
+        assert!(!matches(&rule, clock_at(11, 30, 12, 0)));
+        assert!(matches(&rule, clock_at(12, 1, 0, 0)));
+        assert!(matches(&rule, clock_at(1, 6, 23, 59)));
+        assert!(!matches(&rule, clock_at(1, 7, 0, 0)));
+    }
+
+    #[test]
+    fn test_deserialize_time_rules() {
+        let rules: HashMap<String, Rules> = toml::from_str(
+            r#"rules = [
+                { time = { after = "22:00", before = "06:00" } },
+                { time = { after = "sunset-30m", before = "23:00" } },
+                { weekdays = ["Mon", "Tue"] },
+                { date = { from = "06-01", to = "08-31" } },
+            ]"#,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            rules["rules"].as_slice(),
+            [
+                Rule::Time(_),
+                Rule::Time(_),
+                Rule::Weekday(_),
+                Rule::Date(_)
+            ]
+        ));
+    }
+
+    const HELSINKI: Location = Location {
+        latitude: 60.17,
+        longitude: 24.94,
+    };
+
+    fn local(date: NaiveDate, time: NaiveTime) -> DateTime<Local> {
+        Local.from_local_datetime(&date.and_time(time)).unwrap()
+    }
+
+    #[test]
+    fn test_solar_time_range() {
+        let range = TimeRange {
+            after: "sunset".parse().unwrap(),
+            before: "sunrise".parse().unwrap(),
+        };
+        let date = NaiveDate::from_ymd_opt(2024, 3, 20).unwrap();
+        let sunset = range.after.resolve_at(date, Some(&HELSINKI)).unwrap();
+        let sunrise = range.before.resolve_at(date, Some(&HELSINKI)).unwrap();
+        let contains = |time| {
+            range
+                .contains_at(&local(date, time), Some(&HELSINKI))
+                .unwrap()
+        };
+
+        assert!(contains(sunset));
+        assert!(contains(sunrise - chrono::Duration::minutes(1)));
+        assert!(!contains(sunrise));
+        assert!(!contains(sunset - chrono::Duration::minutes(1)));
+
+        assert!(range.contains_at(&local(date, sunset), None).is_err());
+    }
+
+    #[test]
+    fn test_inverted_solar_time_range() {
+        const OULU: Location = Location {
+            latitude: 65.01,
+            longitude: 25.47,
+        };
+
+        // On the equinox the sun sets well before the end of the range, but
+        // in early summer it sets after it
+        let date = NaiveDate::from_ymd_opt(2024, 6, 1).unwrap();
+        let sunset = TimeOfDay::Solar {
+            event: crate::utils::solar::SolarEvent::Sunset,
+            offset: chrono::Duration::zero(),
+        };
+        let summer_sunset = sunset.resolve_at(date, Some(&OULU)).unwrap();
+        let range = TimeRange {
+            after: sunset,
+            before: TimeOfDay::Fixed(summer_sunset - chrono::Duration::hours(1)),
+        };
+        let contains = |time| range.contains_at(&local(date, time), Some(&OULU)).unwrap();
+
+        assert!(!contains(NaiveTime::from_hms_opt(12, 0, 0).unwrap()));
+        assert!(!contains(summer_sunset - chrono::Duration::minutes(30)));
+        assert!(!contains(summer_sunset + chrono::Duration::minutes(10)));
+    }
+
+    #[test]
+    fn test_solar_time_range_polar_day() {
+        const TROMSO: Location = Location {
+            latitude: 69.65,
+            longitude: 18.96,
+        };
+
+        let range = TimeRange {
+            after: "sunset".parse().unwrap(),
+            before: "23:00".parse().unwrap(),
+        };
+        let date = NaiveDate::from_ymd_opt(2024, 6, 21).unwrap();
+
+        for hour in [0, 12, 22] {
+            let now = local(date, NaiveTime::from_hms_opt(hour, 30, 0).unwrap());
+            assert!(!range.contains_at(&now, Some(&TROMSO)).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_solar_expr_rule_is_timed() {
+        let rule = |expr: &str| {
+            Rule::Expr(ExprRule {
+                expr: evalexpr::build_operator_tree(expr).unwrap(),
+                for_duration: None,
+            })
+        };
+
+        assert!(rule("solar.elevation > 5").is_timed());
+        assert!(!rule("devices.test.lamp.power == true").is_timed());
+    }
+}
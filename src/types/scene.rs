@@ -11,6 +11,7 @@ use ts_rs::TS;
 macro_attr! {
     #[derive(TS, Clone, Debug, Deserialize, Serialize, Eq, PartialEq, Hash, Ord, PartialOrd, NewtypeDisplay!, NewtypeFrom!)]
     #[ts(export)]
+    #[serde(transparent)]
     pub struct SceneId(String);
 }
 
@@ -1,29 +1,206 @@
 use std::collections::HashMap;
 
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
 use super::{
-    device::DevicesState, event::Event, group::FlattenedGroupsConfig, scene::FlattenedScenesConfig,
+    action::Action,
+    device::{Device, DeviceKey, DevicesState},
+    event::{Event, EventSource},
+    group::{FlattenedGroupConfig, FlattenedGroupsConfig, GroupId},
+    integration::IntegrationId,
+    scene::{FlattenedSceneConfig, FlattenedScenesConfig, SceneConfig, SceneId},
 };
 
+/// Requests that WebSocket clients are allowed to send. Unlike internal
+/// [Event]s, these can't spoof device state reported by
+/// integrations.
 #[derive(TS, Deserialize, Serialize, Debug)]
 #[ts(export)]
 pub enum WebSocketRequest {
-    EventMessage(Event),
+    /// Runs the given action, e.g. activating a scene or storing UI state.
+    Action(Action),
+
+    /// Stores a new scene in the DB, or replaces an existing DB scene.
+    StoreScene {
+        scene_id: SceneId,
+        config: SceneConfig,
+    },
+
+    /// Renames a scene stored in the DB.
+    EditScene { scene_id: SceneId, name: String },
+
+    /// Deletes a scene stored in the DB.
+    DeleteScene { scene_id: SceneId },
+
+    /// Replaces the client's subscription. A new snapshot of the state is
+    /// sent if the subscription includes state messages.
+    Subscribe(Subscription),
+
+    /// Requests a snapshot of the current state. Clients should send this
+    /// to resync whenever they notice a gap in patch sequence numbers.
+    GetState,
 }
 
-#[derive(TS, Deserialize, Serialize, Debug)]
+#[derive(TS, Clone, Copy, Deserialize, Serialize, Debug, PartialEq, Eq)]
+#[ts(export)]
+pub enum MessageKind {
+    /// State snapshots and patches.
+    State,
+
+    /// Events handled by homectl, such as actions and device state changes.
+    Events,
+
+    /// Log messages. Requires the admin scope.
+    Logs,
+}
+
+/// Limits which messages a WebSocket client receives. A device is included if
+/// it matches any of the device, group or integration filters. If none of
+/// them are given, all devices and groups are included. Scenes and UI state
+/// are always included.
+#[derive(TS, Clone, Default, Deserialize, Serialize, Debug)]
+#[ts(export)]
+pub struct Subscription {
+    pub devices: Option<Vec<DeviceKey>>,
+
+    /// Includes these groups and their devices.
+    pub groups: Option<Vec<GroupId>>,
+
+    /// Includes all devices of these integrations.
+    pub integrations: Option<Vec<IntegrationId>>,
+
+    /// Kinds of messages to receive. Defaults to only state messages.
+    pub kinds: Option<Vec<MessageKind>>,
+}
+
+#[derive(TS, Clone, Copy, Deserialize, Serialize, Debug, PartialEq, Eq)]
+#[ts(export)]
+pub enum WebSocketErrorKind {
+    /// The message couldn't be parsed as a [WebSocketRequest].
+    InvalidMessage,
+
+    /// The connection's API token doesn't allow this request.
+    Forbidden,
+
+    /// The request refers to something that doesn't exist or isn't allowed
+    /// to be modified.
+    InvalidRequest,
+}
+
+#[derive(TS, Clone, Deserialize, Serialize, Debug)]
+#[ts(export)]
+pub struct WebSocketError {
+    pub kind: WebSocketErrorKind,
+    pub message: String,
+}
+
+/// Full snapshot of the current state, sent on connect and on request.
+#[derive(TS, Clone, Deserialize, Serialize, Debug, PartialEq)]
 #[ts(export)]
 pub struct StateUpdate {
+    /// Sequence number of the latest patch included in this snapshot.
+    pub seq: u64,
     pub devices: DevicesState,
     pub scenes: FlattenedScenesConfig,
     pub groups: FlattenedGroupsConfig,
     pub ui_state: HashMap<String, serde_json::Value>,
 }
 
-#[derive(TS, Deserialize, Serialize, Debug)]
+/// Changed device, or None if the device was removed.
+#[derive(TS, Clone, Deserialize, Serialize, Debug)]
+#[ts(export)]
+pub struct DevicePatch {
+    pub seq: u64,
+    pub device_key: DeviceKey,
+    pub device: Option<Device>,
+}
+
+/// Changed scene, or None if the scene was removed.
+#[derive(TS, Clone, Deserialize, Serialize, Debug)]
+#[ts(export)]
+pub struct ScenePatch {
+    pub seq: u64,
+    pub scene_id: SceneId,
+    pub scene: Option<FlattenedSceneConfig>,
+}
+
+/// Changed group, or None if the group was removed.
+#[derive(TS, Clone, Deserialize, Serialize, Debug)]
+#[ts(export)]
+pub struct GroupPatch {
+    pub seq: u64,
+    pub group_id: GroupId,
+    pub group: Option<FlattenedGroupConfig>,
+}
+
+/// Changed UI state value, or None if the key was removed.
+#[derive(TS, Clone, Deserialize, Serialize, Debug)]
+#[ts(export)]
+pub struct UiStatePatch {
+    pub seq: u64,
+    pub key: String,
+    pub value: Option<serde_json::Value>,
+}
+
+/// An event handled by homectl.
+#[derive(TS, Clone, Deserialize, Serialize, Debug)]
+#[ts(export)]
+pub struct EventMessage {
+    pub source: EventSource,
+    pub event: Event,
+}
+
+#[derive(TS, Clone, Deserialize, Serialize, Debug)]
+#[ts(export)]
+pub struct LogMessage {
+    #[ts(type = "string")]
+    pub timestamp: DateTime<Utc>,
+    pub level: String,
+    pub target: String,
+    pub message: String,
+}
+
+/// Messages sent to WebSocket clients. After the initial [StateUpdate],
+/// changes are sent as patches. Every patch sent to a client increments the
+/// sequence number by one, so clients can detect missed patches and resync
+/// with [WebSocketRequest::GetState].
+#[derive(TS, Clone, Deserialize, Serialize, Debug)]
 #[ts(export)]
 pub enum WebSocketResponse {
     State(StateUpdate),
+    DevicePatch(DevicePatch),
+    ScenePatch(ScenePatch),
+    GroupPatch(GroupPatch),
+    UiStatePatch(UiStatePatch),
+    Event(Box<EventMessage>),
+    Log(LogMessage),
+
+    /// Sent only to the client whose request was rejected.
+    Error(WebSocketError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_internal_events_are_not_accepted() {
+        let request = serde_json::from_str::<WebSocketRequest>(
+            r#"{"EventMessage":{"ExternalStateUpdate":{"device":{}}}}"#,
+        );
+        assert!(request.is_err());
+
+        let request = serde_json::from_str::<WebSocketRequest>(
+            r#"{"Action":{"action":"ActivateScene","scene_id":"evening"}}"#,
+        );
+        assert!(matches!(
+            request,
+            Ok(WebSocketRequest::Action(Action::ActivateScene(_)))
+        ));
+
+        let request = serde_json::from_str::<WebSocketRequest>(r#""GetState""#);
+        assert!(matches!(request, Ok(WebSocketRequest::GetState)));
+    }
 }
@@ -1,8 +1,30 @@
-use clap::Parser;
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
 
 #[derive(Clone, Parser)]
 #[command(version, about, long_about = None)]
 pub struct Cli {
+    /// Path to the main config file. Defaults to Settings.toml in the current
+    /// directory.
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
     #[arg(long, required = false, default_value_t = false)]
     pub dry_run: bool,
+
+    /// Replay events from an event journal against dummy integrations.
+    /// Implies --dry-run.
+    #[arg(long, value_name = "JOURNAL")]
+    pub replay: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Clone, Subcommand)]
+pub enum Command {
+    /// Validate Settings.toml, including references between groups, scenes,
+    /// routines and devices, then exit.
+    CheckConfig,
 }
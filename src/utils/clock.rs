@@ -0,0 +1,38 @@
+use chrono::{DateTime, Local};
+
+/// Source of the current time. Time based logic should go through this
+/// instead of calling [Local::now] directly, so that tests can use a frozen
+/// clock.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Local>;
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Local> {
+        Local::now()
+    }
+}
+
+/// Clock that stands still until moved with [FixedClock::set]. Used by tests,
+/// and for following the recorded time when replaying events.
+#[derive(Debug)]
+pub struct FixedClock(std::sync::Mutex<DateTime<Local>>);
+
+impl FixedClock {
+    pub fn new(now: DateTime<Local>) -> Self {
+        FixedClock(std::sync::Mutex::new(now))
+    }
+
+    pub fn set(&self, now: DateTime<Local>) {
+        *self.0.lock().unwrap() = now;
+    }
+}
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Local> {
+        *self.0.lock().unwrap()
+    }
+}
@@ -0,0 +1,83 @@
+use chrono::Utc;
+use log::{Log, Metadata, Record};
+use once_cell::sync::OnceCell;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+
+use crate::core::websockets::WebSockets;
+use crate::types::websockets::LogMessage;
+
+static LOG_TX: OnceCell<UnboundedSender<LogMessage>> = OnceCell::new();
+
+/// Writes log messages to stderr like `pretty_env_logger`, and also forwards
+/// them to WebSocket users once [forward_logs] has been called.
+struct ForwardingLogger {
+    inner: Box<dyn Log>,
+}
+
+impl Log for ForwardingLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.inner.enabled(record.metadata()) {
+            return;
+        }
+
+        self.inner.log(record);
+
+        // Only our own messages are forwarded, as sending messages over
+        // WebSockets may cause dependencies to log messages of their own
+        if !record.target().starts_with(env!("CARGO_CRATE_NAME")) {
+            return;
+        }
+
+        if let Some(tx) = LOG_TX.get() {
+            tx.send(LogMessage {
+                timestamp: Utc::now(),
+                level: record.level().to_string(),
+                target: record.target().to_string(),
+                message: record.args().to_string(),
+            })
+            .ok();
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
+
+/// Same as `pretty_env_logger::init()`, but with support for forwarding log
+/// messages.
+pub fn init_logger() {
+    let mut builder = pretty_env_logger::formatted_builder();
+
+    if let Ok(filters) = std::env::var("RUST_LOG") {
+        builder.parse_filters(&filters);
+    }
+
+    let logger = builder.build();
+    let max_level = logger.filter();
+
+    log::set_boxed_logger(Box::new(ForwardingLogger {
+        inner: Box::new(logger),
+    }))
+    .expect("Logger initialized twice");
+    log::set_max_level(max_level);
+}
+
+/// Starts forwarding log messages to WebSocket users subscribed to logs.
+pub fn forward_logs(ws: WebSockets) {
+    let (tx, mut rx) = unbounded_channel();
+
+    if LOG_TX.set(tx).is_err() {
+        return;
+    }
+
+    tokio::spawn(async move {
+        while let Some(log) = rx.recv().await {
+            ws.send_log(log).await;
+        }
+    });
+}
@@ -1,18 +1,121 @@
-use std::{collections::BTreeMap, hash::Hash};
+use std::{collections::BTreeMap, hash::Hash, time::Duration};
 
 use color_eyre::Result;
 use serde::{de, Deserialize};
 
 pub mod cli;
+pub mod clock;
+pub mod logger;
+pub mod solar;
 
-pub fn from_hh_mm<'de, D>(d: D) -> Result<chrono::NaiveTime, D::Error>
+/// Parses durations such as "90s", "10m" or "1h30m". A plain number is
+/// interpreted as seconds.
+pub fn parse_duration(s: &str) -> Result<Duration> {
+    let s = s.trim();
+
+    if let Ok(seconds) = s.parse::<u64>() {
+        return Ok(Duration::from_secs(seconds));
+    }
+
+    let mut total = Duration::ZERO;
+    let mut digits = String::new();
+
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+
+        let value: u64 = digits
+            .parse()
+            .map_err(|_| eyre!("Expected a number before unit '{c}' in duration '{s}'"))?;
+        digits.clear();
+
+        let unit_seconds = match c {
+            'd' => 24 * 60 * 60,
+            'h' => 60 * 60,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(eyre!("Unknown unit '{c}' in duration '{s}'")),
+        };
+
+        total = value
+            .checked_mul(unit_seconds)
+            .and_then(|seconds| total.checked_add(Duration::from_secs(seconds)))
+            .ok_or_else(|| eyre!("Duration '{s}' is too long"))?;
+    }
+
+    if !digits.is_empty() {
+        return Err(eyre!("Missing unit after '{digits}' in duration '{s}'"));
+    }
+
+    if s.is_empty() {
+        return Err(eyre!("Empty duration"));
+    }
+
+    Ok(total)
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum DurationValue {
+    Seconds(u64),
+    Text(String),
+}
+
+impl DurationValue {
+    fn into_duration<E: de::Error>(self) -> Result<Duration, E> {
+        match self {
+            DurationValue::Seconds(seconds) => Ok(Duration::from_secs(seconds)),
+            DurationValue::Text(s) => parse_duration(&s).map_err(E::custom),
+        }
+    }
+}
+
+/// Deserializes a duration given either as a number of seconds or as a
+/// string accepted by [parse_duration].
+pub fn from_duration<'de, D>(d: D) -> Result<Duration, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    DurationValue::deserialize(d)?.into_duration()
+}
+
+/// Like [from_duration], but for optional fields.
+pub fn from_optional_duration<'de, D>(d: D) -> Result<Option<Duration>, D::Error>
 where
     D: de::Deserializer<'de>,
 {
-    let str = String::deserialize(d)?;
-    chrono::NaiveTime::parse_from_str(&str, "%H:%M").map_err(serde::de::Error::custom)
+    Option::<DurationValue>::deserialize(d)?
+        .map(DurationValue::into_duration)
+        .transpose()
 }
 
 pub fn keys_match<T: Eq + Hash + Ord, U, V>(map1: &BTreeMap<T, U>, map2: &BTreeMap<T, V>) -> bool {
     map1.len() == map2.len() && map1.keys().all(|k| map2.contains_key(k))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("45").unwrap(), Duration::from_secs(45));
+        assert_eq!(parse_duration("90s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("10m").unwrap(), Duration::from_secs(600));
+        assert_eq!(parse_duration("1h30m").unwrap(), Duration::from_secs(5400));
+        assert_eq!(parse_duration("1d").unwrap(), Duration::from_secs(86400));
+    }
+
+    #[test]
+    fn test_parse_duration_invalid() {
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("10").is_ok());
+        assert!(parse_duration("10x").is_err());
+        assert!(parse_duration("1h30").is_err());
+        assert!(parse_duration("999999999999999999d").is_err());
+        assert!(parse_duration(&format!("{}s1s", u64::MAX)).is_err());
+    }
+}
@@ -0,0 +1,315 @@
+use std::{
+    f64::consts::PI,
+    fmt::{self, Display},
+    str::FromStr,
+    sync::RwLock,
+};
+
+use chrono::{DateTime, Datelike, Local, NaiveDate, NaiveTime, Timelike, Utc};
+use color_eyre::Result;
+use serde::{de, Deserialize};
+
+use super::parse_duration;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub struct Location {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+static LOCATION: RwLock<Option<Location>> = RwLock::new(None);
+
+/// Sets the location used for solar calculations.
+pub fn set_location(location: Option<Location>) {
+    *LOCATION.write().unwrap() = location;
+}
+
+pub fn get_location() -> Option<Location> {
+    *LOCATION.read().unwrap()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SolarEvent {
+    /// Start of civil twilight in the morning
+    Dawn,
+    Sunrise,
+    Sunset,
+    /// End of civil twilight in the evening
+    Dusk,
+}
+
+impl SolarEvent {
+    /// Zenith angle of the sun's center at the time of the event, in degrees.
+    fn zenith(&self) -> f64 {
+        match self {
+            // Accounts for atmospheric refraction and the size of the solar disk
+            SolarEvent::Sunrise | SolarEvent::Sunset => 90.833,
+            SolarEvent::Dawn | SolarEvent::Dusk => 96.0,
+        }
+    }
+
+    fn is_morning(&self) -> bool {
+        matches!(self, SolarEvent::Dawn | SolarEvent::Sunrise)
+    }
+}
+
+impl Display for SolarEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SolarEvent::Dawn => f.write_str("dawn"),
+            SolarEvent::Sunrise => f.write_str("sunrise"),
+            SolarEvent::Sunset => f.write_str("sunset"),
+            SolarEvent::Dusk => f.write_str("dusk"),
+        }
+    }
+}
+
+impl FromStr for SolarEvent {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "dawn" => Ok(SolarEvent::Dawn),
+            "sunrise" => Ok(SolarEvent::Sunrise),
+            "sunset" => Ok(SolarEvent::Sunset),
+            "dusk" => Ok(SolarEvent::Dusk),
+            _ => Err(eyre!("Unknown solar event '{s}'")),
+        }
+    }
+}
+
+/// Equation of time (in minutes) and solar declination (in radians) for the
+/// given day of year and UTC hour, using NOAA's approximations.
+fn eqtime_and_declination(day_of_year: u32, hour: f64) -> (f64, f64) {
+    let gamma = 2.0 * PI / 365.0 * (day_of_year as f64 - 1.0 + (hour - 12.0) / 24.0);
+
+    let eqtime = 229.18
+        * (0.000075 + 0.001868 * gamma.cos()
+            - 0.032077 * gamma.sin()
+            - 0.014615 * (2.0 * gamma).cos()
+            - 0.040849 * (2.0 * gamma).sin());
+
+    let declination = 0.006918 - 0.399912 * gamma.cos() + 0.070257 * gamma.sin()
+        - 0.006758 * (2.0 * gamma).cos()
+        + 0.000907 * (2.0 * gamma).sin()
+        - 0.002697 * (3.0 * gamma).cos()
+        + 0.00148 * (3.0 * gamma).sin();
+
+    (eqtime, declination)
+}
+
+/// Elevation of the sun above the horizon in degrees at the given time.
+pub fn solar_elevation(location: &Location, time: &DateTime<Utc>) -> f64 {
+    let hour = time.hour() as f64 + time.minute() as f64 / 60.0 + time.second() as f64 / 3600.0;
+    let (eqtime, declination) = eqtime_and_declination(time.ordinal(), hour);
+
+    let true_solar_time = hour * 60.0 + eqtime + 4.0 * location.longitude;
+    let hour_angle = (true_solar_time / 4.0 - 180.0).to_radians();
+    let latitude = location.latitude.to_radians();
+
+    let cos_zenith =
+        latitude.sin() * declination.sin() + latitude.cos() * declination.cos() * hour_angle.cos();
+
+    90.0 - cos_zenith.clamp(-1.0, 1.0).acos().to_degrees()
+}
+
+/// Time of the given solar event on the given date. Returns None if the event
+/// does not occur on that date, e.g. during polar day or night.
+pub fn solar_event_time(
+    location: &Location,
+    date: NaiveDate,
+    event: SolarEvent,
+) -> Option<DateTime<Utc>> {
+    let (eqtime, declination) = eqtime_and_declination(date.ordinal(), 12.0);
+    let latitude = location.latitude.to_radians();
+
+    let cos_hour_angle = event.zenith().to_radians().cos() / (latitude.cos() * declination.cos())
+        - latitude.tan() * declination.tan();
+
+    if !(-1.0..=1.0).contains(&cos_hour_angle) {
+        return None;
+    }
+
+    let hour_angle = cos_hour_angle.acos().to_degrees();
+    let hour_angle = if event.is_morning() {
+        hour_angle
+    } else {
+        -hour_angle
+    };
+
+    let minutes = 720.0 - 4.0 * (location.longitude + hour_angle) - eqtime;
+    let midnight = date.and_hms_opt(0, 0, 0)?.and_utc();
+
+    Some(midnight + chrono::Duration::milliseconds((minutes * 60_000.0) as i64))
+}
+
+/// Time of day, given either as a fixed wall-clock time ("07:30") or relative
+/// to a solar event ("sunset", "sunrise+30m", "dusk-1h").
+#[derive(Clone, Debug, PartialEq)]
+pub enum TimeOfDay {
+    Fixed(NaiveTime),
+    Solar {
+        event: SolarEvent,
+        offset: chrono::Duration,
+    },
+}
+
+impl TimeOfDay {
+    /// Returns the local wall-clock time on the given date, using the
+    /// configured location for solar events.
+    pub fn resolve(&self, date: NaiveDate) -> Option<NaiveTime> {
+        self.resolve_at(date, get_location().as_ref())
+    }
+
+    pub fn resolve_at(&self, date: NaiveDate, location: Option<&Location>) -> Option<NaiveTime> {
+        match self {
+            TimeOfDay::Fixed(time) => Some(*time),
+            TimeOfDay::Solar { event, offset } => {
+                let time = solar_event_time(location?, date, *event)? + *offset;
+                Some(time.with_timezone(&Local).time())
+            }
+        }
+    }
+}
+
+impl Display for TimeOfDay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimeOfDay::Fixed(time) => write!(f, "{}", time.format("%H:%M")),
+            TimeOfDay::Solar { event, offset } if offset.is_zero() => write!(f, "{event}"),
+            TimeOfDay::Solar { event, offset } => {
+                let sign = if *offset < chrono::Duration::zero() {
+                    '-'
+                } else {
+                    '+'
+                };
+                write!(f, "{event}{sign}{}s", offset.num_seconds().abs())
+            }
+        }
+    }
+}
+
+impl FromStr for TimeOfDay {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Ok(time) = NaiveTime::parse_from_str(s, "%H:%M") {
+            return Ok(TimeOfDay::Fixed(time));
+        }
+
+        let (event, offset) = match s.find(['+', '-']) {
+            Some(index) => {
+                let (event, offset) = s.split_at(index);
+                let duration = chrono::Duration::from_std(parse_duration(&offset[1..])?)?;
+                let offset = if offset.starts_with('-') {
+                    -duration
+                } else {
+                    duration
+                };
+
+                (event, offset)
+            }
+            None => (s, chrono::Duration::zero()),
+        };
+
+        let event = event.trim().parse().map_err(|_| {
+            eyre!("Expected time in HH:MM format or relative to dawn, sunrise, sunset or dusk, got '{s}'")
+        })?;
+
+        Ok(TimeOfDay::Solar { event, offset })
+    }
+}
+
+impl<'de> Deserialize<'de> for TimeOfDay {
+    fn deserialize<D>(d: D) -> Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        let s = String::deserialize(d)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HELSINKI: Location = Location {
+        latitude: 60.17,
+        longitude: 24.94,
+    };
+
+    fn assert_close(actual: DateTime<Utc>, expected: &str) {
+        let expected: DateTime<Utc> = expected.parse().unwrap();
+        let diff = (actual - expected).num_minutes().abs();
+        assert!(diff <= 3, "expected {expected}, got {actual}");
+    }
+
+    #[test]
+    fn test_sunrise_sunset() {
+        let date = NaiveDate::from_ymd_opt(2024, 3, 20).unwrap();
+
+        let sunrise = solar_event_time(&HELSINKI, date, SolarEvent::Sunrise).unwrap();
+        let sunset = solar_event_time(&HELSINKI, date, SolarEvent::Sunset).unwrap();
+        let dawn = solar_event_time(&HELSINKI, date, SolarEvent::Dawn).unwrap();
+        let dusk = solar_event_time(&HELSINKI, date, SolarEvent::Dusk).unwrap();
+
+        assert_close(sunrise, "2024-03-20T04:21:00Z");
+        assert_close(sunset, "2024-03-20T16:34:00Z");
+        assert!(dawn < sunrise);
+        assert!(dusk > sunset);
+    }
+
+    #[test]
+    fn test_polar_day() {
+        let tromso = Location {
+            latitude: 69.65,
+            longitude: 18.96,
+        };
+        let date = NaiveDate::from_ymd_opt(2024, 6, 21).unwrap();
+
+        assert_eq!(solar_event_time(&tromso, date, SolarEvent::Sunset), None);
+        assert!(solar_elevation(&tromso, &"2024-06-21T23:00:00Z".parse().unwrap()) > 0.0);
+    }
+
+    #[test]
+    fn test_solar_elevation() {
+        let noon: DateTime<Utc> = "2024-06-21T10:20:00Z".parse().unwrap();
+        let midnight: DateTime<Utc> = "2024-06-21T22:20:00Z".parse().unwrap();
+
+        // Maximum elevation in Helsinki is 90 - 60.17 + 23.44 degrees
+        assert!((solar_elevation(&HELSINKI, &noon) - 53.27).abs() < 0.5);
+        assert!(solar_elevation(&HELSINKI, &midnight) < 0.0);
+    }
+
+    #[test]
+    fn test_parse_time_of_day() {
+        assert_eq!(
+            "07:30".parse::<TimeOfDay>().unwrap(),
+            TimeOfDay::Fixed(NaiveTime::from_hms_opt(7, 30, 0).unwrap())
+        );
+        assert_eq!(
+            "sunset".parse::<TimeOfDay>().unwrap(),
+            TimeOfDay::Solar {
+                event: SolarEvent::Sunset,
+                offset: chrono::Duration::zero()
+            }
+        );
+        assert_eq!(
+            "sunset-30m".parse::<TimeOfDay>().unwrap(),
+            TimeOfDay::Solar {
+                event: SolarEvent::Sunset,
+                offset: chrono::Duration::minutes(-30)
+            }
+        );
+        assert_eq!(
+            "dawn+1h".parse::<TimeOfDay>().unwrap(),
+            TimeOfDay::Solar {
+                event: SolarEvent::Dawn,
+                offset: chrono::Duration::hours(1)
+            }
+        );
+        assert!("noon".parse::<TimeOfDay>().is_err());
+        assert!("25:00".parse::<TimeOfDay>().is_err());
+    }
+}